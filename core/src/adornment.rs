@@ -0,0 +1,233 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Adornments - named canvas regions that act on notes moved into them
+//!
+//! An adornment is not a note: it has no content or links of its own. It
+//! marks out a rectangle on the canvas and runs its `on_enter` rules when a
+//! note's centre moves into that rectangle and its `on_exit` rules when the
+//! centre moves out again.
+
+use crate::note::{NoteId, Rect};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Unique identifier for an adornment
+pub type AdornmentId = Uuid;
+
+/// Visual style of an adornment
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AdornmentStyle {
+    /// Fill colour as a CSS colour string
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fill: Option<String>,
+
+    /// Border colour as a CSS colour string
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub border: Option<String>,
+
+    /// Fill opacity from 0.0 to 1.0
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opacity: Option<f64>,
+}
+
+/// An action an adornment applies to a note entering or leaving it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum AdornmentRule {
+    /// Set an attribute on the note
+    SetAttribute {
+        key: String,
+        value: serde_json::Value,
+    },
+
+    /// Remove an attribute from the note
+    RemoveAttribute { key: String },
+
+    /// Make the given note the note's prototype
+    ApplyPrototype { prototype: NoteId },
+
+    /// Clear the note's prototype
+    RemovePrototype,
+
+    /// Link the note to the target note
+    AddLink { target: NoteId },
+
+    /// Remove the note's link to the target note
+    RemoveLink { target: NoteId },
+}
+
+/// A named rectangle on the canvas with enter/exit rules
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Adornment {
+    /// Unique identifier
+    pub id: AdornmentId,
+
+    /// Adornment name, shown on the canvas
+    pub name: String,
+
+    /// Area covered on the canvas
+    pub bounds: Rect,
+
+    /// Visual style
+    #[serde(default)]
+    pub style: AdornmentStyle,
+
+    /// Rules applied when a note moves into the adornment
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_enter: Vec<AdornmentRule>,
+
+    /// Rules applied when a note moves out of the adornment
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_exit: Vec<AdornmentRule>,
+}
+
+impl Adornment {
+    /// Create an adornment with no style or rules
+    pub fn new(name: impl Into<String>, bounds: Rect) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            name: name.into(),
+            bounds,
+            style: AdornmentStyle::default(),
+            on_enter: Vec::new(),
+            on_exit: Vec::new(),
+        }
+    }
+
+    /// Add a rule to run when a note enters
+    pub fn on_enter(mut self, rule: AdornmentRule) -> Self {
+        self.on_enter.push(rule);
+        self
+    }
+
+    /// Add a rule to run when a note leaves
+    pub fn on_exit(mut self, rule: AdornmentRule) -> Self {
+        self.on_exit.push(rule);
+        self
+    }
+
+    /// Check if a note with the given bounds counts as inside (its centre
+    /// lies within the adornment)
+    pub fn contains_note(&self, note_bounds: &Rect) -> bool {
+        self.bounds.contains(note_bounds.center())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::note::{Note, Point2D};
+    use crate::notebook::Notebook;
+    use serde_json::json;
+
+    #[test]
+    fn test_enter_and_exit_rules() {
+        let mut notebook = Notebook::new("Test");
+        let proto = notebook.create_note("Task prototype");
+        let hub = notebook.create_note("Done hub");
+        let note = notebook.add_note(Note::new("Task").with_position(1000.0, 1000.0));
+
+        let done = Adornment::new("Done", Rect::new(0.0, 0.0, 500.0, 500.0))
+            .on_enter(AdornmentRule::SetAttribute {
+                key: "status".into(),
+                value: json!("done"),
+            })
+            .on_enter(AdornmentRule::ApplyPrototype { prototype: proto })
+            .on_enter(AdornmentRule::AddLink { target: hub })
+            .on_exit(AdornmentRule::RemoveAttribute {
+                key: "status".into(),
+            })
+            .on_exit(AdornmentRule::RemoveLink { target: hub });
+        notebook.add_adornment(done);
+
+        notebook
+            .set_note_position(&note, Some(Point2D::new(100.0, 100.0)))
+            .unwrap();
+        let moved = notebook.get_note(&note).unwrap();
+        assert_eq!(moved.get_attribute("status"), Some(&json!("done")));
+        assert_eq!(moved.prototype, Some(proto));
+        assert!(moved.links_to(&hub));
+        assert_eq!(notebook.get_backlinks(&hub), vec![note]);
+
+        // Moving within the adornment does not re-run the rules
+        notebook
+            .get_note_mut(&note)
+            .unwrap()
+            .attributes
+            .insert("status".into(), json!("reopened"));
+        notebook
+            .set_note_position(&note, Some(Point2D::new(150.0, 100.0)))
+            .unwrap();
+        let moved = notebook.get_note(&note).unwrap();
+        assert_eq!(moved.get_attribute("status"), Some(&json!("reopened")));
+
+        notebook
+            .set_note_position(&note, Some(Point2D::new(900.0, 900.0)))
+            .unwrap();
+        let moved = notebook.get_note(&note).unwrap();
+        assert!(moved.get_attribute("status").is_none());
+        assert!(!moved.links_to(&hub));
+        assert!(notebook.get_backlinks(&hub).is_empty());
+        // The prototype is kept because there is no exit rule for it
+        assert_eq!(moved.prototype, Some(proto));
+    }
+
+    #[test]
+    fn test_prototype_rule_avoids_cycles() {
+        let mut notebook = Notebook::new("Test");
+        let proto = notebook.add_note(Note::new("Prototype").with_position(1000.0, 1000.0));
+        let mut child = Note::new("Child");
+        child.prototype = Some(proto);
+        let child = notebook.add_note(child);
+
+        // The prototype entering a frame that applies its own child as
+        // prototype would make a cycle, so it is left alone
+        notebook.add_adornment(
+            Adornment::new("Frame", Rect::new(0.0, 0.0, 500.0, 500.0))
+                .on_enter(AdornmentRule::ApplyPrototype { prototype: child }),
+        );
+        notebook
+            .set_note_position(&proto, Some(Point2D::new(100.0, 100.0)))
+            .unwrap();
+        assert_eq!(notebook.get_note(&proto).unwrap().prototype, None);
+    }
+
+    #[test]
+    fn test_notes_in_adornment() {
+        let mut notebook = Notebook::new("Test");
+        let frame =
+            notebook.add_adornment(Adornment::new("Frame", Rect::new(0.0, 0.0, 300.0, 300.0)));
+        let inside = notebook.add_note(Note::new("Inside").with_position(10.0, 10.0));
+        // Overlaps the frame but its centre is outside
+        notebook.add_note(Note::new("Edge").with_position(250.0, 250.0));
+
+        let members: Vec<NoteId> = notebook
+            .notes_in_adornment(&frame)
+            .iter()
+            .map(|note| note.id)
+            .collect();
+        assert_eq!(members, vec![inside]);
+    }
+
+    #[test]
+    fn test_adornments_survive_serialization() {
+        let mut notebook = Notebook::new("Test");
+        let id = notebook.add_adornment(
+            Adornment::new("Inbox", Rect::new(0.0, 0.0, 100.0, 100.0)).on_enter(
+                AdornmentRule::SetAttribute {
+                    key: "inbox".into(),
+                    value: json!(true),
+                },
+            ),
+        );
+
+        let json = serde_json::to_string(&notebook).unwrap();
+        let mut loaded: Notebook = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.get_adornment(&id).unwrap().name, "Inbox");
+
+        let note = loaded.add_note(Note::new("New").with_position(0.0, 0.0));
+        assert_eq!(
+            loaded.get_note(&note).unwrap().get_attribute("inbox"),
+            Some(&json!(true))
+        );
+    }
+}
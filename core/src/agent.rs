@@ -0,0 +1,459 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Agents - saved queries that keep collecting matching notes
+//!
+//! An agent pairs a [`Query`] with an action and a schedule. Its matches are
+//! cached in memory and brought up to date when it runs: if the query only
+//! looks at each note on its own, only notes changed since the last run are
+//! re-checked; queries with link or prototype terms or relative dates are
+//! re-evaluated in full.
+//!
+//! On each run the agent's action is applied to every match. Actions are
+//! idempotent: a note that already has the attribute, link or prototype is
+//! left alone, so only real changes are made and recorded. Because one
+//! agent's changes can make notes match another, agents refreshed together
+//! run in passes until nothing changes, and an agent never changes the same
+//! note twice in one refresh, so agents undoing each other's work stop after
+//! one round instead of looping forever.
+
+use crate::note::NoteId;
+use crate::query::Query;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// Unique identifier for an agent
+pub type AgentId = Uuid;
+
+/// What an agent does with the notes it matches
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum AgentAction {
+    /// Just collect the matches
+    #[default]
+    Collect,
+
+    /// Set an attribute on each match
+    SetAttribute {
+        key: String,
+        value: serde_json::Value,
+    },
+
+    /// Link each match to the target note
+    AddLink { target: NoteId },
+
+    /// Make the given note each match's prototype
+    ApplyPrototype { prototype: NoteId },
+
+    /// Remove an attribute from each match
+    RemoveAttribute { key: String },
+
+    /// Remove each match's link to the target note
+    RemoveLink { target: NoteId },
+
+    /// Clear each match's prototype
+    RemovePrototype,
+}
+
+/// A change an agent made, or would make, to a note
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum NoteChange {
+    AttributeSet {
+        key: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        old: Option<serde_json::Value>,
+        new: serde_json::Value,
+    },
+    AttributeRemoved {
+        key: String,
+        old: serde_json::Value,
+    },
+    LinkAdded {
+        target: NoteId,
+    },
+    LinkRemoved {
+        target: NoteId,
+    },
+    PrototypeSet {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        old: Option<NoteId>,
+        new: NoteId,
+    },
+    PrototypeRemoved {
+        old: NoteId,
+    },
+}
+
+/// A change to one note
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgentChange {
+    pub note: NoteId,
+
+    #[serde(flatten)]
+    pub change: NoteChange,
+}
+
+/// What one run of an agent did, or would do in a dry run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgentRun {
+    /// The agent that ran
+    pub agent: AgentId,
+
+    /// When it ran
+    pub ran_at: DateTime<Utc>,
+
+    /// Whether the changes were only previewed
+    #[serde(default)]
+    pub dry_run: bool,
+
+    /// Changes made (or that would be made) to matching notes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<AgentChange>,
+
+    /// Matches left alone because the agent had already changed them in
+    /// the same refresh
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<NoteId>,
+}
+
+/// Number of runs that changed something kept in an agent's history
+pub const MAX_AGENT_HISTORY: usize = 20;
+
+/// Most passes a single refresh makes while agents keep changing notes
+pub const MAX_AGENT_PASSES: usize = 8;
+
+/// When an agent runs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "when", rename_all = "snake_case")]
+pub enum Schedule {
+    /// Whenever agents are refreshed after notes change
+    #[default]
+    Continuous,
+
+    /// At most once every so many minutes
+    Every { minutes: u32 },
+
+    /// Only when run explicitly
+    Manual,
+}
+
+/// In-memory match cache, rebuilt after loading
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AgentCache {
+    /// Whether `results` reflects a full evaluation
+    pub(crate) valid: bool,
+
+    /// Matching notes
+    pub(crate) results: HashSet<NoteId>,
+
+    /// Notes added, edited or removed since the last run
+    pub(crate) changed: HashSet<NoteId>,
+
+    /// Day of the last evaluation, for queries with relative dates
+    pub(crate) evaluated_on: Option<NaiveDate>,
+}
+
+/// A saved query that collects, and optionally acts on, matching notes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Agent {
+    /// Unique identifier
+    pub id: AgentId,
+
+    /// Agent name
+    pub name: String,
+
+    /// Which notes the agent collects
+    pub query: Query,
+
+    /// What to do with the matches
+    #[serde(default)]
+    pub action: AgentAction,
+
+    /// When the agent runs
+    #[serde(default)]
+    pub schedule: Schedule,
+
+    /// When the agent last ran
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_run: Option<DateTime<Utc>>,
+
+    /// Recent runs that changed notes, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<AgentRun>,
+
+    #[serde(skip)]
+    pub(crate) cache: AgentCache,
+}
+
+impl Agent {
+    /// Create a continuously running agent that collects its matches
+    pub fn new(name: impl Into<String>, query: Query) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            name: name.into(),
+            query,
+            action: AgentAction::Collect,
+            schedule: Schedule::Continuous,
+            last_run: None,
+            history: Vec::new(),
+            cache: AgentCache::default(),
+        }
+    }
+
+    /// Set the action
+    pub fn with_action(mut self, action: AgentAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Set the schedule
+    pub fn with_schedule(mut self, schedule: Schedule) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    /// Check if the schedule calls for a run at the given time
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        match self.schedule {
+            Schedule::Continuous => true,
+            Schedule::Every { minutes } => self
+                .last_run
+                .is_none_or(|last| now - last >= chrono::Duration::minutes(i64::from(minutes))),
+            Schedule::Manual => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::note::Note;
+    use crate::notebook::{Notebook, NotebookError};
+    use serde_json::json;
+
+    fn agent(query: &str) -> Agent {
+        Agent::new("Agent", Query::parse(query).unwrap())
+    }
+
+    #[test]
+    fn test_results_follow_note_changes() {
+        let mut notebook = Notebook::new("Test");
+        let done = notebook.create_note("Finished");
+        notebook
+            .get_note_mut(&done)
+            .unwrap()
+            .set_attribute("status", json!("done"));
+        let open = notebook.create_note("Open");
+
+        let id = notebook.add_agent(agent("status=done"));
+        notebook.run_agent(&id).unwrap();
+        assert_eq!(notebook.agent_results(&id).unwrap()[0].id, done);
+
+        // Edited directly, then picked up by the next refresh
+        notebook
+            .get_note_mut(&open)
+            .unwrap()
+            .set_attribute("status", json!("done"));
+        notebook.refresh_agents(Utc::now());
+        assert_eq!(notebook.agent_results(&id).unwrap().len(), 2);
+
+        notebook.remove_note(&done);
+        notebook.refresh_agents(Utc::now());
+        let results: Vec<NoteId> = notebook
+            .agent_results(&id)
+            .unwrap()
+            .iter()
+            .map(|note| note.id)
+            .collect();
+        assert_eq!(results, vec![open]);
+    }
+
+    #[test]
+    fn test_link_queries_reevaluate_fully() {
+        let mut notebook = Notebook::new("Test");
+        let hub = notebook.create_note("Hub");
+        let a = notebook.create_note("A");
+        let id = notebook.add_agent(agent("links-to:Hub"));
+        notebook.run_agent(&id).unwrap();
+        assert!(notebook.agent_results(&id).unwrap().is_empty());
+
+        notebook.link_notes(a, hub).unwrap();
+        notebook.refresh_agents(Utc::now());
+        assert_eq!(notebook.agent_results(&id).unwrap()[0].id, a);
+
+        // Renaming the hub changes which note the query refers to
+        notebook.get_note_mut(&hub).unwrap().title = "Renamed".into();
+        notebook.refresh_agents(Utc::now());
+        assert!(notebook.agent_results(&id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_schedules() {
+        let mut notebook = Notebook::new("Test");
+        notebook.create_note("Note");
+        let manual = notebook.add_agent(agent("note").with_schedule(Schedule::Manual));
+        let hourly =
+            notebook.add_agent(agent("note").with_schedule(Schedule::Every { minutes: 60 }));
+
+        let now = Utc::now();
+        let ran = |runs: Vec<AgentRun>| runs.iter().map(|run| run.agent).collect::<Vec<_>>();
+        assert_eq!(ran(notebook.refresh_agents(now)), vec![hourly]);
+        assert!(notebook.get_agent(&manual).unwrap().last_run.is_none());
+
+        assert!(notebook
+            .refresh_agents(now + chrono::Duration::minutes(30))
+            .is_empty());
+        assert_eq!(
+            ran(notebook.refresh_agents(now + chrono::Duration::minutes(61))),
+            vec![hourly]
+        );
+        notebook.run_agent(&manual).unwrap();
+        assert_eq!(notebook.agent_results(&manual).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_actions_are_idempotent_and_recorded() {
+        let mut notebook = Notebook::new("Test");
+        let hub = notebook.create_note("Inbox");
+        let a = notebook.create_note("Todo: write report");
+        let b = notebook.create_note("Todo: call back");
+        notebook.link_notes(b, hub).unwrap();
+
+        let tag = notebook.add_agent(agent("title:todo").with_action(AgentAction::SetAttribute {
+            key: "kind".into(),
+            value: json!("task"),
+        }));
+        let link = notebook
+            .add_agent(agent("title:todo").with_action(AgentAction::AddLink { target: hub }));
+
+        let run = notebook.run_agent(&tag).unwrap();
+        assert_eq!(run.changes.len(), 2);
+        assert_eq!(
+            notebook.get_note(&a).unwrap().get_attribute("kind"),
+            Some(&json!("task"))
+        );
+        // Nothing left to change the second time
+        assert!(notebook.run_agent(&tag).unwrap().changes.is_empty());
+        assert_eq!(notebook.get_agent(&tag).unwrap().history.len(), 1);
+
+        // b already links to the hub
+        let run = notebook.run_agent(&link).unwrap();
+        assert_eq!(
+            run.changes,
+            vec![AgentChange {
+                note: a,
+                change: NoteChange::LinkAdded { target: hub },
+            }]
+        );
+        assert!(notebook.get_backlinks(&hub).contains(&a));
+    }
+
+    #[test]
+    fn test_dry_run_changes_nothing() {
+        let mut notebook = Notebook::new("Test");
+        let proto = notebook.create_note("Task");
+        let note = notebook.create_note("Fix bug");
+        let id = notebook.add_agent(
+            agent("title:fix").with_action(AgentAction::ApplyPrototype { prototype: proto }),
+        );
+
+        let preview = notebook.preview_agent(&id).unwrap();
+        assert!(preview.dry_run);
+        assert_eq!(
+            preview.changes[0].change,
+            NoteChange::PrototypeSet {
+                old: None,
+                new: proto
+            }
+        );
+        assert!(notebook.get_note(&note).unwrap().prototype.is_none());
+        assert!(notebook.get_agent(&id).unwrap().history.is_empty());
+        assert!(notebook.get_agent(&id).unwrap().last_run.is_none());
+
+        notebook.run_agent(&id).unwrap();
+        assert_eq!(notebook.get_note(&note).unwrap().prototype, Some(proto));
+
+        let remove =
+            notebook.add_agent(agent("title:fix").with_action(AgentAction::RemovePrototype));
+        let run = notebook.run_agent(&remove).unwrap();
+        assert_eq!(
+            run.changes[0].change,
+            NoteChange::PrototypeRemoved { old: proto }
+        );
+    }
+
+    #[test]
+    fn test_agents_cascade_and_loops_stop() {
+        let mut notebook = Notebook::new("Test");
+        let note = notebook.create_note("Ticket");
+        notebook
+            .get_note_mut(&note)
+            .unwrap()
+            .set_attribute("status", json!("new"));
+
+        // new -> triaged -> done, each agent triggered by the previous one
+        notebook.add_agent(agent("status=new").with_action(AgentAction::SetAttribute {
+            key: "status".into(),
+            value: json!("triaged"),
+        }));
+        notebook.add_agent(
+            agent("status=triaged").with_action(AgentAction::SetAttribute {
+                key: "status".into(),
+                value: json!("done"),
+            }),
+        );
+        notebook.refresh_agents(Utc::now());
+        assert_eq!(
+            notebook.get_note(&note).unwrap().get_attribute("status"),
+            Some(&json!("done"))
+        );
+
+        // Two agents flipping a flag back and forth stop after one round
+        let other = notebook.create_note("Flip");
+        notebook
+            .get_note_mut(&other)
+            .unwrap()
+            .set_attribute("flag", json!(true));
+        let on = notebook.add_agent(agent("title:flip flag=false").with_action(
+            AgentAction::SetAttribute {
+                key: "flag".into(),
+                value: json!(true),
+            },
+        ));
+        let off = notebook.add_agent(agent("title:flip flag=true").with_action(
+            AgentAction::SetAttribute {
+                key: "flag".into(),
+                value: json!(false),
+            },
+        ));
+        let runs = notebook.refresh_agents(Utc::now());
+        let run = |id: AgentId| runs.iter().find(|run| run.agent == id).unwrap();
+        assert_eq!(run(off).changes.len(), 1);
+        assert_eq!(run(on).changes.len(), 1);
+        assert_eq!(run(off).skipped, vec![other]);
+    }
+
+    #[test]
+    fn test_agents_survive_serialization() {
+        let mut notebook = Notebook::new("Test");
+        let target = notebook.create_note("Target");
+        notebook.add_note(Note::new("Match me"));
+        let id =
+            notebook.add_agent(agent("title:match").with_action(AgentAction::AddLink { target }));
+
+        let json = serde_json::to_string(&notebook).unwrap();
+        assert!(json.contains("\"query\":\"title:match\""));
+        let mut loaded: Notebook = serde_json::from_str(&json).unwrap();
+        let agent = loaded.get_agent(&id).unwrap();
+        assert_eq!(agent.action, AgentAction::AddLink { target });
+        loaded.run_agent(&id).unwrap();
+        assert_eq!(loaded.agent_results(&id).unwrap().len(), 1);
+
+        assert!(loaded.remove_agent(&id).is_some());
+        assert!(matches!(
+            loaded.run_agent(&id),
+            Err(NotebookError::AgentNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,449 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Text analysis - normalization, folding, stemming and tokenization
+//!
+//! Every search path runs note text and queries through the same pipeline,
+//! configured per notebook:
+//!
+//! 1. Unicode compatibility normalization (NFKC), so ligatures, full-width
+//!    forms and composed/decomposed accents compare equal
+//! 2. Lowercasing, then folding diacritics and a few special letters, so
+//!    "cafe" finds "café" and "strasse" finds "Straße"
+//! 3. Splitting into words; runs of Chinese, Japanese or Korean characters,
+//!    which are not separated by spaces, become overlapping bigrams
+//! 4. Dropping stop-words and stemming words for a language, so inflected
+//!    forms ("notes", "noting") find each other
+//!
+//! Substring search uses steps 1 and 2; ranked search uses all four.
+//! Token positions always refer to the original, unnormalized text.
+
+use rust_stemmers::{Algorithm, Stemmer};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Language used for stemming and stop-words
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Language {
+    Arabic,
+    Danish,
+    Dutch,
+    English,
+    Finnish,
+    French,
+    German,
+    Greek,
+    Hungarian,
+    Italian,
+    Norwegian,
+    Portuguese,
+    Romanian,
+    Russian,
+    Spanish,
+    Swedish,
+    Tamil,
+    Turkish,
+}
+
+impl Language {
+    fn algorithm(self) -> Algorithm {
+        match self {
+            Language::Arabic => Algorithm::Arabic,
+            Language::Danish => Algorithm::Danish,
+            Language::Dutch => Algorithm::Dutch,
+            Language::English => Algorithm::English,
+            Language::Finnish => Algorithm::Finnish,
+            Language::French => Algorithm::French,
+            Language::German => Algorithm::German,
+            Language::Greek => Algorithm::Greek,
+            Language::Hungarian => Algorithm::Hungarian,
+            Language::Italian => Algorithm::Italian,
+            Language::Norwegian => Algorithm::Norwegian,
+            Language::Portuguese => Algorithm::Portuguese,
+            Language::Romanian => Algorithm::Romanian,
+            Language::Russian => Algorithm::Russian,
+            Language::Spanish => Algorithm::Spanish,
+            Language::Swedish => Algorithm::Swedish,
+            Language::Tamil => Algorithm::Tamil,
+            Language::Turkish => Algorithm::Turkish,
+        }
+    }
+
+    /// Built-in stop-words; empty for languages without a list
+    pub fn stop_words(self) -> &'static [&'static str] {
+        match self {
+            Language::English => &[
+                "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into",
+                "is", "it", "no", "not", "of", "on", "or", "such", "that", "the", "their", "then",
+                "there", "these", "they", "this", "to", "was", "will", "with",
+            ],
+            Language::German => &[
+                "aber", "als", "am", "an", "auch", "auf", "aus", "bei", "das", "dass", "dem",
+                "den", "der", "des", "die", "ein", "eine", "einen", "einer", "es", "für", "im",
+                "in", "ist", "mit", "nicht", "oder", "sich", "sie", "und", "von", "zu",
+            ],
+            Language::French => &[
+                "au", "aux", "avec", "ce", "ces", "dans", "de", "des", "du", "elle", "en", "et",
+                "il", "la", "le", "les", "leur", "mais", "ne", "pas", "par", "pour", "qui", "que",
+                "sur", "un", "une",
+            ],
+            Language::Spanish => &[
+                "al", "como", "con", "de", "del", "el", "en", "es", "la", "las", "lo", "los", "no",
+                "o", "para", "por", "que", "se", "su", "un", "una", "y",
+            ],
+            Language::Italian => &[
+                "a", "che", "con", "da", "del", "della", "di", "e", "gli", "il", "in", "la", "le",
+                "non", "per", "si", "su", "un", "una",
+            ],
+            Language::Portuguese => &[
+                "a", "as", "com", "da", "de", "do", "dos", "e", "em", "na", "no", "o", "os",
+                "para", "por", "que", "se", "um", "uma",
+            ],
+            Language::Dutch => &[
+                "de", "een", "en", "het", "in", "is", "met", "niet", "of", "op", "te", "van",
+                "voor", "zijn",
+            ],
+            _ => &[],
+        }
+    }
+}
+
+/// Per-notebook text analysis settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnalysisConfig {
+    /// Apply Unicode compatibility normalization (NFKC)
+    pub normalize: bool,
+
+    /// Ignore accents and other diacritics
+    pub fold_diacritics: bool,
+
+    /// Language for stemming and built-in stop-words; None disables both
+    pub language: Option<Language>,
+
+    /// Leave the language's stop-words out of ranked search
+    pub stop_words: bool,
+
+    /// Additional words left out of ranked search
+    pub custom_stop_words: Vec<String>,
+
+    /// Split Chinese, Japanese and Korean text into overlapping bigrams
+    pub cjk_bigrams: bool,
+}
+
+impl Default for AnalysisConfig {
+    fn default() -> Self {
+        Self {
+            normalize: true,
+            fold_diacritics: true,
+            language: None,
+            stop_words: false,
+            custom_stop_words: Vec::new(),
+            cjk_bigrams: true,
+        }
+    }
+}
+
+impl AnalysisConfig {
+    /// Check if these are the default settings
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// A word in a piece of text, with its position in characters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    /// Normalized word, as indexed
+    pub term: String,
+
+    /// Char offset of the first character
+    pub start: usize,
+
+    /// Char offset one past the last character
+    pub end: usize,
+}
+
+/// Check if a character belongs to a script written without spaces
+/// between words
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{1100}'..='\u{11FF}'     // Hangul Jamo
+        | '\u{3040}'..='\u{30FF}'   // Hiragana, Katakana
+        | '\u{3130}'..='\u{318F}'   // Hangul Compatibility Jamo
+        | '\u{31F0}'..='\u{31FF}'   // Katakana Phonetic Extensions
+        | '\u{3400}'..='\u{4DBF}'   // CJK Extension A
+        | '\u{4E00}'..='\u{9FFF}'   // CJK Unified Ideographs
+        | '\u{AC00}'..='\u{D7AF}'   // Hangul Syllables
+        | '\u{F900}'..='\u{FAFF}'   // CJK Compatibility Ideographs
+        | '\u{FF66}'..='\u{FF9F}'   // Halfwidth Katakana
+        | '\u{20000}'..='\u{2EBEF}' // CJK Extensions B-F
+    )
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || is_combining_mark(c)
+}
+
+/// Check if a combining mark is a diacritic on Latin, Greek or Cyrillic
+/// letters, as opposed to a vowel sign that is part of a letter elsewhere
+fn is_diacritic(c: char) -> bool {
+    matches!(c,
+        '\u{0300}'..='\u{036F}'
+        | '\u{1AB0}'..='\u{1AFF}'
+        | '\u{1DC0}'..='\u{1DFF}'
+        | '\u{20D0}'..='\u{20FF}'
+        | '\u{FE20}'..='\u{FE2F}'
+    )
+}
+
+/// Base letter of a lowercase letter that does not decompose
+fn fold_special(c: char) -> Option<&'static str> {
+    Some(match c {
+        'ß' => "ss",
+        'æ' => "ae",
+        'œ' => "oe",
+        'ø' => "o",
+        'ł' => "l",
+        'đ' => "d",
+        'ð' => "d",
+        'þ' => "th",
+        'ı' => "i",
+        'ħ' => "h",
+        _ => return None,
+    })
+}
+
+/// Strip diacritics from lowercase text
+fn fold_diacritics(text: &str) -> String {
+    if text.is_ascii() {
+        return text.to_string();
+    }
+    let mut stripped = String::with_capacity(text.len());
+    for c in text.nfd().filter(|c| !is_diacritic(*c)) {
+        match fold_special(c) {
+            Some(folded) => stripped.push_str(folded),
+            None => stripped.push(c),
+        }
+    }
+    // Recompose what remains, such as Hangul syllables and kana
+    stripped.nfc().collect()
+}
+
+/// Lowercase a character and strip its diacritics, keeping one char per
+/// char so positions line up
+pub fn fold_char(c: char) -> char {
+    if c.is_ascii() {
+        return c.to_ascii_lowercase();
+    }
+    let lower = c.to_lowercase().next().unwrap_or(c);
+    match fold_special(lower) {
+        Some(folded) if folded.len() == 1 => return folded.chars().next().unwrap_or(lower),
+        _ => {}
+    }
+    let mut decomposed = std::iter::once(lower).nfd();
+    match decomposed.next() {
+        Some(base) if decomposed.all(is_diacritic) => base,
+        _ => lower,
+    }
+}
+
+/// Text analysis pipeline built from an [`AnalysisConfig`]
+#[derive(Debug, Clone, Default)]
+pub struct Analyzer {
+    config: AnalysisConfig,
+    stop_words: HashSet<String>,
+}
+
+impl Analyzer {
+    pub fn new(config: AnalysisConfig) -> Self {
+        let mut analyzer = Self {
+            config,
+            stop_words: HashSet::new(),
+        };
+        let mut stop_words: HashSet<String> = analyzer
+            .config
+            .custom_stop_words
+            .iter()
+            .map(|word| analyzer.normalize(word))
+            .collect();
+        if analyzer.config.stop_words {
+            if let Some(language) = analyzer.config.language {
+                stop_words.extend(language.stop_words().iter().map(|w| analyzer.normalize(w)));
+            }
+        }
+        analyzer.stop_words = stop_words;
+        analyzer
+    }
+
+    /// The settings this analyzer was built from
+    pub fn config(&self) -> &AnalysisConfig {
+        &self.config
+    }
+
+    /// Normalize text for substring matching: NFKC, lowercase and fold
+    /// diacritics as configured
+    pub fn normalize(&self, text: &str) -> String {
+        let lower = if self.config.normalize && !text.is_ascii() {
+            text.nfkc().collect::<String>().to_lowercase()
+        } else {
+            text.to_lowercase()
+        };
+        if self.config.fold_diacritics {
+            fold_diacritics(&lower)
+        } else {
+            lower
+        }
+    }
+
+    /// Split text into normalized words and CJK bigrams, without removing
+    /// stop-words or stemming
+    pub fn words(&self, text: &str) -> Vec<Token> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let cjk = self.config.cjk_bigrams && is_cjk(chars[i]);
+            if !cjk && !is_word_char(chars[i]) {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < chars.len()
+                && if cjk {
+                    is_cjk(chars[i])
+                } else {
+                    is_word_char(chars[i]) && !(self.config.cjk_bigrams && is_cjk(chars[i]))
+                }
+            {
+                i += 1;
+            }
+            if cjk && i - start > 1 {
+                for j in start..i - 1 {
+                    let pair: String = chars[j..j + 2].iter().collect();
+                    tokens.push(Token {
+                        term: self.normalize(&pair),
+                        start: j,
+                        end: j + 2,
+                    });
+                }
+            } else {
+                let word: String = chars[start..i].iter().collect();
+                tokens.push(Token {
+                    term: self.normalize(&word),
+                    start,
+                    end: i,
+                });
+            }
+        }
+        tokens
+    }
+
+    /// Split text into index terms: words without stop-words, stemmed for
+    /// the configured language
+    pub fn tokenize(&self, text: &str) -> Vec<Token> {
+        let stemmer = self
+            .config
+            .language
+            .map(|language| Stemmer::create(language.algorithm()));
+        let mut tokens = self.words(text);
+        tokens.retain(|token| !self.stop_words.contains(&token.term));
+        if let Some(stemmer) = stemmer {
+            for token in &mut tokens {
+                if !token.term.chars().any(is_cjk) {
+                    token.term = stemmer.stem(&token.term).into_owned();
+                }
+            }
+        }
+        tokens
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(analyzer: &Analyzer, text: &str) -> Vec<String> {
+        analyzer
+            .tokenize(text)
+            .into_iter()
+            .map(|token| token.term)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize_offsets() {
+        let analyzer = Analyzer::default();
+        let tokens = analyzer.tokenize("Héllo, wörld!");
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].term, "hello");
+        assert_eq!((tokens[0].start, tokens[0].end), (0, 5));
+        assert_eq!(tokens[1].term, "world");
+        assert_eq!((tokens[1].start, tokens[1].end), (7, 12));
+    }
+
+    #[test]
+    fn test_normalization_and_folding() {
+        let analyzer = Analyzer::default();
+        assert_eq!(analyzer.normalize("Café"), "cafe");
+        // Decomposed accent, ligature and full-width letters
+        assert_eq!(analyzer.normalize("Cafe\u{301}"), "cafe");
+        assert_eq!(analyzer.normalize("ﬁle ＡＢＣ"), "file abc");
+        assert_eq!(
+            analyzer.normalize("Hauptstraße Ørsted"),
+            "hauptstrasse orsted"
+        );
+        // Kana voicing marks are letters, not diacritics
+        assert_eq!(analyzer.normalize("がっこう"), "がっこう");
+
+        let strict = Analyzer::new(AnalysisConfig {
+            fold_diacritics: false,
+            ..AnalysisConfig::default()
+        });
+        assert_eq!(strict.normalize("Café"), "café");
+
+        assert_eq!(fold_char('É'), 'e');
+        assert_eq!(fold_char('ø'), 'o');
+        assert_eq!(fold_char('ß'), 'ß');
+    }
+
+    #[test]
+    fn test_cjk_bigrams() {
+        let analyzer = Analyzer::default();
+        let tokens = analyzer.tokenize("東京都に住む, Tokyo");
+        let terms: Vec<&str> = tokens.iter().map(|t| t.term.as_str()).collect();
+        assert_eq!(terms, vec!["東京", "京都", "都に", "に住", "住む", "tokyo"]);
+        assert_eq!((tokens[1].start, tokens[1].end), (1, 3));
+        assert_eq!((tokens[5].start, tokens[5].end), (8, 13));
+
+        // A lone character is a token of its own
+        assert_eq!(self::terms(&analyzer, "猫 cat"), vec!["猫", "cat"]);
+    }
+
+    #[test]
+    fn test_stemming_and_stop_words() {
+        let english = Analyzer::new(AnalysisConfig {
+            language: Some(Language::English),
+            stop_words: true,
+            custom_stop_words: vec!["Nexia".into()],
+            ..AnalysisConfig::default()
+        });
+        assert_eq!(
+            terms(&english, "The running of nexia notes"),
+            vec!["run", "note"]
+        );
+
+        let german = Analyzer::new(AnalysisConfig {
+            language: Some(Language::German),
+            ..AnalysisConfig::default()
+        });
+        assert_eq!(terms(&german, "Häuser"), terms(&german, "Haus"));
+
+        // Without a language, words are kept as written
+        assert_eq!(
+            terms(&Analyzer::default(), "the notes"),
+            vec!["the", "notes"]
+        );
+    }
+}
@@ -0,0 +1,304 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Arrange - align, distribute, size-match and grid-snap selected notes
+//!
+//! Operations plan new bounding boxes for a selection; apply them with
+//! [`Notebook::arrange_notes`](crate::notebook::Notebook::arrange_notes).
+
+use crate::note::{Note, NoteId, Rect};
+use crate::notebook::Notebook;
+use serde::{Deserialize, Serialize};
+
+/// Edge or centre line to align a selection on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Alignment {
+    /// Left edges line up with the leftmost note
+    Left,
+    /// Right edges line up with the rightmost note
+    Right,
+    /// Top edges line up with the topmost note
+    Top,
+    /// Bottom edges line up with the bottommost note
+    Bottom,
+    /// Centres share one x coordinate (a vertical column)
+    CenterX,
+    /// Centres share one y coordinate (a horizontal row)
+    CenterY,
+}
+
+/// Canvas axis
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Axis {
+    Horizontal,
+    Vertical,
+}
+
+/// Which dimensions to make equal when matching sizes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dimension {
+    Width,
+    Height,
+    Both,
+}
+
+/// An arrangement applied to a selection of notes
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ArrangeOp {
+    /// Line notes up on an edge or centre
+    Align { alignment: Alignment },
+
+    /// Space notes evenly between the two outermost ones, keeping equal
+    /// gaps between neighbours
+    Distribute { axis: Axis },
+
+    /// Resize notes to the largest width and/or height in the selection
+    MatchSize { dimension: Dimension },
+
+    /// Round each note's top-left corner to the nearest grid intersection
+    SnapToGrid { grid: f64 },
+}
+
+/// Plan new bounding boxes for the placed notes among `ids`
+///
+/// Notes that are not placed on the canvas are skipped. Only notes whose
+/// bounds change are returned.
+pub fn plan(notebook: &Notebook, ids: &[NoteId], op: &ArrangeOp) -> Vec<(NoteId, Rect)> {
+    let mut seen = std::collections::HashSet::new();
+    let current: Vec<(NoteId, Rect)> = ids
+        .iter()
+        .filter(|id| seen.insert(**id))
+        .filter_map(|id| Some((*id, notebook.get_note(id).and_then(Note::bounds)?)))
+        .collect();
+    if current.is_empty() {
+        return Vec::new();
+    }
+
+    let planned = match op {
+        ArrangeOp::Align { alignment } => align(&current, *alignment),
+        ArrangeOp::Distribute { axis } => distribute(&current, *axis),
+        ArrangeOp::MatchSize { dimension } => match_size(&current, *dimension),
+        ArrangeOp::SnapToGrid { grid } => snap(&current, *grid),
+    };
+
+    planned
+        .into_iter()
+        .zip(&current)
+        .filter(|(new, (_, old))| new.1 != *old)
+        .map(|(new, _)| new)
+        .collect()
+}
+
+/// Reduce one coordinate of every rectangle with `f`
+fn fold(
+    rects: &[(NoteId, Rect)],
+    init: f64,
+    f: fn(f64, f64) -> f64,
+    key: fn(&Rect) -> f64,
+) -> f64 {
+    rects.iter().map(|(_, r)| key(r)).fold(init, f)
+}
+
+fn align(rects: &[(NoteId, Rect)], alignment: Alignment) -> Vec<(NoteId, Rect)> {
+    let left = fold(rects, f64::INFINITY, f64::min, |r| r.x);
+    let top = fold(rects, f64::INFINITY, f64::min, |r| r.y);
+    let right = fold(rects, f64::NEG_INFINITY, f64::max, |r| r.x + r.width);
+    let bottom = fold(rects, f64::NEG_INFINITY, f64::max, |r| r.y + r.height);
+
+    rects
+        .iter()
+        .map(|(id, r)| {
+            let mut r = *r;
+            match alignment {
+                Alignment::Left => r.x = left,
+                Alignment::Right => r.x = right - r.width,
+                Alignment::Top => r.y = top,
+                Alignment::Bottom => r.y = bottom - r.height,
+                Alignment::CenterX => r.x = (left + right) / 2.0 - r.width / 2.0,
+                Alignment::CenterY => r.y = (top + bottom) / 2.0 - r.height / 2.0,
+            }
+            (*id, r)
+        })
+        .collect()
+}
+
+fn distribute(rects: &[(NoteId, Rect)], axis: Axis) -> Vec<(NoteId, Rect)> {
+    if rects.len() < 3 {
+        return rects.to_vec();
+    }
+    let start = |r: &Rect| match axis {
+        Axis::Horizontal => r.x,
+        Axis::Vertical => r.y,
+    };
+    let extent = |r: &Rect| match axis {
+        Axis::Horizontal => r.width,
+        Axis::Vertical => r.height,
+    };
+
+    let mut order: Vec<usize> = (0..rects.len()).collect();
+    order.sort_by(|&a, &b| {
+        start(&rects[a].1)
+            .total_cmp(&start(&rects[b].1))
+            .then(rects[a].0.cmp(&rects[b].0))
+    });
+
+    let first = &rects[order[0]].1;
+    let last = &rects[order[order.len() - 1]].1;
+    let span = start(last) + extent(last) - start(first);
+    let occupied: f64 = rects.iter().map(|(_, r)| extent(r)).sum();
+    let gap = (span - occupied) / (rects.len() - 1) as f64;
+
+    let mut result = rects.to_vec();
+    let mut cursor = start(first);
+    for &i in &order {
+        let r = &mut result[i].1;
+        match axis {
+            Axis::Horizontal => r.x = cursor,
+            Axis::Vertical => r.y = cursor,
+        }
+        cursor += extent(r) + gap;
+    }
+    // Keep the outermost note exactly where it was despite rounding
+    result[order[order.len() - 1]].1 = *last;
+    result
+}
+
+fn match_size(rects: &[(NoteId, Rect)], dimension: Dimension) -> Vec<(NoteId, Rect)> {
+    let width = fold(rects, 0.0, f64::max, |r| r.width);
+    let height = fold(rects, 0.0, f64::max, |r| r.height);
+    rects
+        .iter()
+        .map(|(id, r)| {
+            let mut r = *r;
+            if matches!(dimension, Dimension::Width | Dimension::Both) {
+                r.width = width;
+            }
+            if matches!(dimension, Dimension::Height | Dimension::Both) {
+                r.height = height;
+            }
+            (*id, r)
+        })
+        .collect()
+}
+
+fn snap(rects: &[(NoteId, Rect)], grid: f64) -> Vec<(NoteId, Rect)> {
+    if !(grid.is_finite() && grid > 0.0) {
+        return rects.to_vec();
+    }
+    rects
+        .iter()
+        .map(|(id, r)| {
+            let mut r = *r;
+            r.x = (r.x / grid).round() * grid;
+            r.y = (r.y / grid).round() * grid;
+            (*id, r)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::note::Point2D;
+
+    fn boxed(notebook: &mut Notebook, x: f64, y: f64, w: f64, h: f64) -> NoteId {
+        let mut note = Note::new("Box").with_position(x, y);
+        note.size = Some((w, h));
+        notebook.add_note(note)
+    }
+
+    fn bounds(notebook: &Notebook, id: &NoteId) -> Rect {
+        notebook.get_note(id).unwrap().bounds().unwrap()
+    }
+
+    #[test]
+    fn test_align() {
+        let mut notebook = Notebook::new("Test");
+        let a = boxed(&mut notebook, 10.0, 0.0, 100.0, 50.0);
+        let b = boxed(&mut notebook, 40.0, 100.0, 40.0, 50.0);
+
+        let op = ArrangeOp::Align {
+            alignment: Alignment::Right,
+        };
+        notebook.arrange_notes(&[a, b], &op).unwrap();
+        assert_eq!(bounds(&notebook, &b).x, 70.0);
+        assert_eq!(bounds(&notebook, &a).x, 10.0);
+
+        let op = ArrangeOp::Align {
+            alignment: Alignment::CenterX,
+        };
+        notebook.arrange_notes(&[a, b], &op).unwrap();
+        assert_eq!(
+            bounds(&notebook, &a).center().x,
+            bounds(&notebook, &b).center().x
+        );
+    }
+
+    #[test]
+    fn test_distribute_equal_gaps() {
+        let mut notebook = Notebook::new("Test");
+        let a = boxed(&mut notebook, 0.0, 0.0, 100.0, 50.0);
+        let b = boxed(&mut notebook, 110.0, 0.0, 20.0, 50.0);
+        let c = boxed(&mut notebook, 300.0, 0.0, 60.0, 50.0);
+
+        let op = ArrangeOp::Distribute {
+            axis: Axis::Horizontal,
+        };
+        notebook.arrange_notes(&[c, a, b], &op).unwrap();
+
+        // Span 360, occupied 180, so two gaps of 90
+        assert_eq!(bounds(&notebook, &a).x, 0.0);
+        assert_eq!(bounds(&notebook, &b).x, 190.0);
+        assert_eq!(bounds(&notebook, &c).x, 300.0);
+    }
+
+    #[test]
+    fn test_match_size_and_snap() {
+        let mut notebook = Notebook::new("Test");
+        let a = boxed(&mut notebook, 12.0, 27.0, 100.0, 30.0);
+        let b = boxed(&mut notebook, 48.0, 61.0, 40.0, 80.0);
+
+        let op = ArrangeOp::MatchSize {
+            dimension: Dimension::Both,
+        };
+        notebook.arrange_notes(&[a, b], &op).unwrap();
+        assert_eq!(notebook.get_note(&b).unwrap().size, Some((100.0, 80.0)));
+
+        let op = ArrangeOp::SnapToGrid { grid: 25.0 };
+        notebook.arrange_notes(&[a, b], &op).unwrap();
+        assert_eq!(
+            notebook.get_note(&a).unwrap().position,
+            Some(Point2D::new(0.0, 25.0))
+        );
+        assert_eq!(
+            notebook.get_note(&b).unwrap().position,
+            Some(Point2D::new(50.0, 50.0))
+        );
+    }
+
+    #[test]
+    fn test_unplaced_notes_skipped() {
+        let mut notebook = Notebook::new("Test");
+        let placed = boxed(&mut notebook, 5.0, 5.0, 10.0, 10.0);
+        let unplaced = notebook.create_note("Floating");
+
+        let op = ArrangeOp::SnapToGrid { grid: 10.0 };
+        let changed = notebook.arrange_notes(&[placed, unplaced], &op).unwrap();
+        assert_eq!(changed, vec![placed]);
+        assert!(notebook.get_note(&unplaced).unwrap().position.is_none());
+    }
+
+    #[test]
+    fn test_op_serde() {
+        let op: ArrangeOp =
+            serde_json::from_str(r#"{"op":"align","alignment":"center_y"}"#).unwrap();
+        assert_eq!(
+            op,
+            ArrangeOp::Align {
+                alignment: Alignment::CenterY
+            }
+        );
+    }
+}
@@ -0,0 +1,251 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Binary storage - a compact, optionally compressed notebook file
+//!
+//! A binary notebook file is a header followed by the notebook encoded as
+//! MessagePack, compressed with zstd unless compression is turned off. The
+//! header is an 8-byte magic number, chosen like PNG's so that text-mode
+//! transfers and truncation to 7 bits show up as a bad header, then a byte
+//! for the layout version and a byte for the compression used.
+//!
+//! The encoded notebook has the same fields, format version and migrations
+//! as a JSON file (see [`crate::format`]), and loading goes through the same
+//! path, so [`JsonStorage`] reads binary files as well: the header tells
+//! the two apart. Saving keeps backups and replaces the file atomically in
+//! the same way.
+
+use crate::format::{self, Versioned};
+use crate::notebook::Notebook;
+use crate::storage::{self, FileStorage, Storage, StorageError, DEFAULT_BACKUPS};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// First bytes of every binary notebook file
+pub const MAGIC: [u8; 8] = *b"\x89NXB\r\n\x1a\n";
+
+/// Version of the header and encoding, after the magic number
+const LAYOUT_VERSION: u8 = 1;
+
+/// Length of the header
+const HEADER_LEN: usize = MAGIC.len() + 2;
+
+/// zstd level used unless another is chosen; higher is smaller but slower
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// How the encoded notebook is compressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Compression {
+    /// Stored as encoded, for the fastest saves and loads
+    None,
+
+    /// zstd at a level from 1 (fastest) to 22 (smallest)
+    Zstd { level: i32 },
+}
+
+impl Compression {
+    fn tag(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zstd { .. } => 1,
+        }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::Zstd {
+            level: DEFAULT_ZSTD_LEVEL,
+        }
+    }
+}
+
+/// Check if file contents start like a binary notebook
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// Encode a notebook as a binary file
+pub fn encode(notebook: &Notebook, compression: Compression) -> Result<Vec<u8>, StorageError> {
+    let mut encoded = Vec::new();
+    // Ids and times are written as text, as in JSON, so the same
+    // migrations apply to both
+    let mut serializer = rmp_serde::Serializer::new(&mut encoded)
+        .with_struct_map()
+        .with_human_readable();
+    Versioned::new(notebook).serialize(&mut serializer)?;
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + encoded.len());
+    bytes.extend_from_slice(&MAGIC);
+    bytes.push(LAYOUT_VERSION);
+    bytes.push(compression.tag());
+    match compression {
+        Compression::None => bytes.extend_from_slice(&encoded),
+        Compression::Zstd { level } => {
+            bytes.extend_from_slice(&zstd::encode_all(encoded.as_slice(), level)?)
+        }
+    }
+    Ok(bytes)
+}
+
+/// Decode a binary file into a saved document, before migration
+pub fn decode_document(bytes: &[u8]) -> Result<Value, StorageError> {
+    if !is_binary(bytes) || bytes.len() < HEADER_LEN {
+        return Err(StorageError::InvalidFormat(
+            "not a binary notebook file".into(),
+        ));
+    }
+    let layout = bytes[MAGIC.len()];
+    if layout != LAYOUT_VERSION {
+        return Err(StorageError::InvalidFormat(format!(
+            "unknown binary layout version {layout}"
+        )));
+    }
+    let payload = &bytes[HEADER_LEN..];
+    match bytes[MAGIC.len() + 1] {
+        0 => Ok(rmp_serde::from_slice(payload)?),
+        1 => {
+            let decompressed = zstd::decode_all(payload)
+                .map_err(|e| StorageError::Corrupted(format!("compressed data: {e}")))?;
+            Ok(rmp_serde::from_slice(&decompressed)?)
+        }
+        other => Err(StorageError::InvalidFormat(format!(
+            "unknown compression {other}"
+        ))),
+    }
+}
+
+/// Decode a binary file into a notebook
+pub fn decode(bytes: &[u8]) -> Result<Notebook, StorageError> {
+    format::from_document(decode_document(bytes)?)
+}
+
+/// Binary file storage implementation
+pub struct BinaryStorage {
+    compression: Compression,
+    backups: usize,
+}
+
+impl BinaryStorage {
+    pub fn new() -> Self {
+        Self {
+            compression: Compression::default(),
+            backups: DEFAULT_BACKUPS,
+        }
+    }
+
+    /// Compress saved files this way
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Keep this many backups of each file; zero keeps none
+    pub fn with_backups(mut self, backups: usize) -> Self {
+        self.backups = backups;
+        self
+    }
+}
+
+impl Default for BinaryStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Storage for BinaryStorage {
+    fn save(&self, notebook: &Notebook, path: &Path) -> Result<(), StorageError> {
+        let bytes = encode(notebook, self.compression)?;
+        storage::back_up(path, self.backups)?;
+        storage::write_atomic(path, &bytes)
+    }
+
+    fn load(&self, path: &Path) -> Result<Notebook, StorageError> {
+        if !path.exists() {
+            return Err(StorageError::NotFound(path.display().to_string()));
+        }
+        decode(&fs::read(path)?)
+    }
+}
+
+impl FileStorage for BinaryStorage {
+    fn encode(&self, notebook: &Notebook) -> Result<Vec<u8>, StorageError> {
+        encode(notebook, self.compression)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Notebook, StorageError> {
+        decode(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::JsonStorage;
+    use crate::testing::{sample, saved_form};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_round_trip_is_identical() {
+        let (notebook, ..) = sample();
+        for compression in [Compression::None, Compression::default()] {
+            let bytes = encode(&notebook, compression).unwrap();
+            assert!(is_binary(&bytes));
+            let loaded = decode(&bytes).unwrap();
+            assert_eq!(saved_form(&loaded), saved_form(&notebook));
+            assert_eq!(loaded.search("milestones").len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_smaller_than_json() {
+        let (mut notebook, plan, ..) = sample();
+        notebook.get_note_mut(&plan).unwrap().content =
+            "Milestones for the first release. ".repeat(200);
+        let json = serde_json::to_vec_pretty(&Versioned::new(&notebook)).unwrap();
+        let plain = encode(&notebook, Compression::None).unwrap();
+        let compressed = encode(&notebook, Compression::default()).unwrap();
+        assert!(plain.len() < json.len());
+        assert!(compressed.len() * 10 < plain.len());
+    }
+
+    #[test]
+    fn test_json_storage_detects_binary_files() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.nxb");
+        let (notebook, ..) = sample();
+        let storage = BinaryStorage::new().with_backups(1);
+        storage.save(&notebook, &path).unwrap();
+        storage.save(&notebook, &path).unwrap();
+        assert_eq!(storage::list_backups(&path).unwrap().len(), 1);
+
+        assert_eq!(JsonStorage::new().load(&path).unwrap().name, "Sample");
+        assert_eq!(storage.load(&path).unwrap().len(), 3);
+
+        // A damaged file is recovered from its backup like a JSON one
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+        assert!(storage.load(&path).is_err());
+        let loaded = JsonStorage::new().load_or_recover(&path).unwrap();
+        assert!(loaded.recovered_from.is_some());
+    }
+
+    #[test]
+    fn test_bad_headers_are_refused() {
+        let bytes = encode(&sample().0, Compression::None).unwrap();
+        for (index, value) in [(MAGIC.len(), 9), (MAGIC.len() + 1, 9)] {
+            let mut bad = bytes.clone();
+            bad[index] = value;
+            assert!(matches!(decode(&bad), Err(StorageError::InvalidFormat(_))));
+        }
+        assert!(matches!(
+            decode(b"{\"notes\": {}}"),
+            Err(StorageError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            decode(&MAGIC),
+            Err(StorageError::InvalidFormat(_))
+        ));
+    }
+}
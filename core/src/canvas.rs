@@ -0,0 +1,267 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Canvas maps - several named spatial views of one notebook
+//!
+//! Every notebook has a default map backed by each note's own `position`
+//! and `size`, so files written before maps existed open with their layout
+//! intact and all canvas operations on notes act on it. Further maps keep
+//! their own per-note placements, letting the same note sit in different
+//! places (or be absent) on different maps.
+
+use crate::note::{NoteId, Point2D, Rect, DEFAULT_NOTE_SIZE};
+use crate::spatial::SpatialIndex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Unique identifier for a canvas map
+pub type MapId = Uuid;
+
+/// Visible region of a map: the canvas point at the centre of the view and
+/// the zoom factor
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Viewport {
+    pub center: Point2D,
+    pub zoom: f64,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self {
+            center: Point2D::origin(),
+            zoom: 1.0,
+        }
+    }
+}
+
+/// Where a note sits on a map
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Placement {
+    pub position: Point2D,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<(f64, f64)>,
+}
+
+impl Placement {
+    pub fn new(position: Point2D) -> Self {
+        Self {
+            position,
+            size: None,
+        }
+    }
+
+    /// Bounding box, using [`DEFAULT_NOTE_SIZE`] when no size is set
+    pub fn bounds(&self) -> Rect {
+        let (width, height) = self.size.unwrap_or(DEFAULT_NOTE_SIZE);
+        Rect::new(self.position.x, self.position.y, width, height)
+    }
+}
+
+/// A named spatial view of the notebook
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CanvasMap {
+    /// Unique identifier
+    pub id: MapId,
+
+    /// Map name
+    pub name: String,
+
+    /// Last viewed region
+    #[serde(default)]
+    pub viewport: Viewport,
+
+    /// Per-note placements (always empty for the default map, whose
+    /// placements live on the notes)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    placements: HashMap<NoteId, Placement>,
+
+    /// Spatial index over `placements` (rebuilt on load)
+    #[serde(skip)]
+    index: SpatialIndex,
+}
+
+impl CanvasMap {
+    /// Create an empty map
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            name: name.into(),
+            viewport: Viewport::default(),
+            placements: HashMap::new(),
+            index: SpatialIndex::new(),
+        }
+    }
+
+    /// Get a note's placement on this map
+    pub fn placement(&self, note: &NoteId) -> Option<Placement> {
+        self.placements.get(note).copied()
+    }
+
+    /// Iterate over all placements on this map
+    pub fn placements(&self) -> impl Iterator<Item = (&NoteId, &Placement)> {
+        self.placements.iter()
+    }
+
+    /// IDs of notes placed on this map whose bounds intersect the rectangle
+    pub fn notes_in_rect(&self, rect: &Rect) -> impl Iterator<Item = NoteId> + '_ {
+        self.index.intersecting(rect)
+    }
+
+    /// Place, move or (with None) remove a note on this map
+    pub(crate) fn set_placement(&mut self, note: NoteId, placement: Option<Placement>) {
+        match placement {
+            Some(placement) => {
+                self.index.insert(note, placement.bounds());
+                self.placements.insert(note, placement);
+            }
+            None => {
+                self.index.remove(&note);
+                self.placements.remove(&note);
+            }
+        }
+    }
+
+    /// Rebuild the spatial index from the placements
+    pub(crate) fn rebuild_index(&mut self) {
+        self.index = SpatialIndex::bulk_load(
+            self.placements
+                .iter()
+                .map(|(id, placement)| (*id, placement.bounds())),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::note::Note;
+    use crate::notebook::{Notebook, NotebookError};
+
+    #[test]
+    fn test_default_map_uses_note_positions() {
+        let mut notebook = Notebook::new("Test");
+        let default = notebook.default_map_id();
+        let id = notebook.add_note(Note::new("Placed").with_position(5.0, 6.0));
+
+        assert_eq!(
+            notebook.map_placement(&default, &id),
+            Some(Placement::new(Point2D::new(5.0, 6.0)))
+        );
+
+        let placement = Placement {
+            position: Point2D::new(50.0, 60.0),
+            size: Some((10.0, 10.0)),
+        };
+        notebook
+            .set_map_placement(&default, &id, Some(placement))
+            .unwrap();
+        let note = notebook.get_note(&id).unwrap();
+        assert_eq!(note.position, Some(Point2D::new(50.0, 60.0)));
+        assert_eq!(note.size, Some((10.0, 10.0)));
+    }
+
+    #[test]
+    fn test_note_on_several_maps() {
+        let mut notebook = Notebook::new("Test");
+        let overview = notebook.default_map_id();
+        let literature = notebook.create_map("Literature");
+        let id = notebook.add_note(Note::new("Shared").with_position(0.0, 0.0));
+
+        notebook
+            .set_map_placement(
+                &literature,
+                &id,
+                Some(Placement::new(Point2D::new(900.0, 900.0))),
+            )
+            .unwrap();
+
+        let on_overview = notebook.notes_in_rect_on_map(&overview, Rect::new(0.0, 0.0, 10.0, 10.0));
+        assert_eq!(on_overview.len(), 1);
+        assert!(notebook
+            .notes_in_rect_on_map(&literature, Rect::new(0.0, 0.0, 10.0, 10.0))
+            .is_empty());
+        assert_eq!(
+            notebook
+                .notes_in_rect_on_map(&literature, Rect::new(890.0, 890.0, 20.0, 20.0))
+                .len(),
+            1
+        );
+        // The note's own position is the default map's and is unchanged
+        assert_eq!(
+            notebook.get_note(&id).unwrap().position,
+            Some(Point2D::origin())
+        );
+
+        notebook.remove_note(&id);
+        assert!(notebook
+            .get_map(&literature)
+            .unwrap()
+            .placement(&id)
+            .is_none());
+    }
+
+    #[test]
+    fn test_map_management() {
+        let mut notebook = Notebook::new("Test");
+        let default = notebook.default_map_id();
+        let extra = notebook.create_map("Scratch");
+        assert_eq!(notebook.all_maps().count(), 2);
+
+        notebook.rename_map(&extra, "Drafts").unwrap();
+        let viewport = Viewport {
+            center: Point2D::new(10.0, 10.0),
+            zoom: 2.0,
+        };
+        notebook.set_map_viewport(&extra, viewport).unwrap();
+        assert_eq!(notebook.get_map(&extra).unwrap().name, "Drafts");
+        assert_eq!(notebook.get_map(&extra).unwrap().viewport, viewport);
+
+        assert!(matches!(
+            notebook.remove_map(&default),
+            Err(NotebookError::DefaultMapRemoval)
+        ));
+        assert!(notebook.remove_map(&extra).is_ok());
+        assert!(matches!(
+            notebook.remove_map(&extra),
+            Err(NotebookError::MapNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_legacy_file_gets_default_map() {
+        let mut notebook = Notebook::new("Test");
+        let id = notebook.add_note(Note::new("Old").with_position(1.0, 2.0));
+        let mut value = serde_json::to_value(&notebook).unwrap();
+        let object = value.as_object_mut().unwrap();
+        object.remove("maps");
+        object.remove("default_map");
+
+        let loaded: Notebook = serde_json::from_value(value).unwrap();
+        let default = loaded.default_map_id();
+        assert!(loaded.get_map(&default).is_some());
+        assert_eq!(
+            loaded.map_placement(&default, &id).map(|p| p.position),
+            Some(Point2D::new(1.0, 2.0))
+        );
+    }
+
+    #[test]
+    fn test_maps_survive_serialization() {
+        let mut notebook = Notebook::new("Test");
+        let map = notebook.create_map("Second");
+        let id = notebook.create_note("Note");
+        notebook
+            .set_map_placement(&map, &id, Some(Placement::new(Point2D::new(3.0, 4.0))))
+            .unwrap();
+
+        let json = serde_json::to_string(&notebook).unwrap();
+        let loaded: Notebook = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.default_map_id(), notebook.default_map_id());
+        assert_eq!(
+            loaded
+                .notes_in_rect_on_map(&map, Rect::new(0.0, 0.0, 5.0, 5.0))
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,644 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Embeddings - vector representations of notes for similarity search
+//!
+//! An [`Embedder`] turns text into a vector so that notes about the same
+//! things end up close together even when they share few words. Two are
+//! built in:
+//!
+//! - [`HashedTfIdf`], the default, hashes analyzed words into a fixed number
+//!   of dimensions weighted by TF-IDF. It works offline on any notebook but
+//!   only knows about words the notebook itself uses.
+//! - [`WordVectors`] averages pre-trained word vectors loaded from a local
+//!   file in the common text format (fastText `.vec`, GloVe), so related
+//!   words count as similar.
+//!
+//! Other providers can implement the trait. Note vectors are kept in a
+//! [`VectorIndex`], saved in a file next to the notebook and reused as long
+//! as the model and each note's text are unchanged.
+
+use crate::analysis::{AnalysisConfig, Analyzer};
+use crate::note::{Note, NoteId};
+use crate::storage::{self, StorageError};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Dimensions of the built-in hashed embedding
+pub const DEFAULT_DIMENSIONS: usize = 512;
+
+/// Errors that can occur while embedding text
+#[derive(Debug, Error)]
+pub enum EmbeddingError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid model file at line {line}: {reason}")]
+    InvalidModel { line: usize, reason: String },
+
+    #[error("Expected a vector of {expected} dimensions, got {found}")]
+    DimensionMismatch { expected: usize, found: usize },
+
+    #[error("Embedding provider failed: {0}")]
+    Provider(String),
+}
+
+/// Turns text into fixed-length vectors
+pub trait Embedder: fmt::Debug + Send + Sync {
+    /// Identifies the model; vectors from different models are not
+    /// comparable
+    fn model(&self) -> String;
+
+    /// Length of every vector this embedder returns
+    fn dimensions(&self) -> usize;
+
+    /// Embed a piece of text
+    fn embed(&self, text: &str) -> Result<Vec<f32>, EmbeddingError>;
+}
+
+/// FNV-1a, a hash that is stable across platforms and releases, unlike
+/// the standard library's
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// Text of a note as embedded
+pub(crate) fn note_text(note: &Note) -> String {
+    format!("{}\n\n{}", note.title, note.content)
+}
+
+/// Fingerprint of embedded text, to tell when a stored vector is stale
+pub(crate) fn fingerprint(text: &str) -> u64 {
+    fnv1a(text.as_bytes())
+}
+
+/// Scale a vector to unit length; zero vectors are left as they are
+fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        for x in vector {
+            *x /= norm;
+        }
+    }
+}
+
+/// Built-in embedding: analyzed words hashed into buckets, weighted by
+/// sublinear term frequency and by inverse document frequency fitted to
+/// the notebook
+#[derive(Debug, Clone)]
+pub struct HashedTfIdf {
+    analyzer: Analyzer,
+    idf: Vec<f32>,
+}
+
+impl HashedTfIdf {
+    /// An embedder with uniform word weights
+    pub fn new(analyzer: Analyzer, dimensions: usize) -> Self {
+        Self {
+            analyzer,
+            idf: vec![1.0; dimensions.max(1)],
+        }
+    }
+
+    /// An embedder with word weights from an earlier fit
+    pub fn with_weights(analyzer: Analyzer, weights: Vec<f32>) -> Self {
+        if weights.is_empty() {
+            return Self::new(analyzer, 1);
+        }
+        Self {
+            analyzer,
+            idf: weights,
+        }
+    }
+
+    /// Word weights, one per dimension
+    pub fn weights(&self) -> &[f32] {
+        &self.idf
+    }
+
+    /// An embedder with word weights fitted to a collection of texts
+    pub fn fit<'a>(
+        analyzer: Analyzer,
+        dimensions: usize,
+        texts: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        let mut embedder = Self::new(analyzer, dimensions);
+        let mut frequencies = vec![0u32; embedder.idf.len()];
+        let mut documents = 0u32;
+        for text in texts {
+            documents += 1;
+            let buckets: HashSet<usize> = embedder
+                .analyzer
+                .tokenize(text)
+                .iter()
+                .map(|token| embedder.bucket(&token.term).0)
+                .collect();
+            for bucket in buckets {
+                frequencies[bucket] += 1;
+            }
+        }
+        for (idf, df) in embedder.idf.iter_mut().zip(frequencies) {
+            *idf = ((1.0 + documents as f32) / (1.0 + df as f32)).ln() + 1.0;
+        }
+        embedder
+    }
+
+    /// Bucket and sign of a word; the sign keeps colliding words from
+    /// adding up systematically
+    fn bucket(&self, term: &str) -> (usize, f32) {
+        let hash = fnv1a(term.as_bytes());
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        ((hash % self.idf.len() as u64) as usize, sign)
+    }
+}
+
+impl Default for HashedTfIdf {
+    fn default() -> Self {
+        Self::new(Analyzer::default(), DEFAULT_DIMENSIONS)
+    }
+}
+
+impl Embedder for HashedTfIdf {
+    fn model(&self) -> String {
+        // Different analysis settings produce different words, and
+        // different weights different vectors
+        let settings = serde_json::to_string(self.analyzer.config()).unwrap_or_default();
+        let weights: Vec<u8> = self.idf.iter().flat_map(|w| w.to_le_bytes()).collect();
+        format!(
+            "hashed-tfidf-{}-{:016x}-{:016x}",
+            self.idf.len(),
+            fnv1a(settings.as_bytes()),
+            fnv1a(&weights)
+        )
+    }
+
+    fn dimensions(&self) -> usize {
+        self.idf.len()
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>, EmbeddingError> {
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for token in self.analyzer.tokenize(text) {
+            *counts.entry(token.term).or_default() += 1;
+        }
+        let mut vector = vec![0.0; self.idf.len()];
+        for (term, count) in counts {
+            let (bucket, sign) = self.bucket(&term);
+            vector[bucket] += sign * (1.0 + (count as f32).ln()) * self.idf[bucket];
+        }
+        normalize(&mut vector);
+        Ok(vector)
+    }
+}
+
+/// Local model of pre-trained word vectors, averaged over a text's words
+#[derive(Debug, Clone)]
+pub struct WordVectors {
+    name: String,
+    dimensions: usize,
+    vectors: HashMap<String, Vec<f32>>,
+    analyzer: Analyzer,
+}
+
+impl WordVectors {
+    /// Load word vectors from a file
+    pub fn load(path: &Path) -> Result<Self, EmbeddingError> {
+        let file = std::fs::File::open(path)?;
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self::from_reader(name, std::io::BufReader::new(file))
+    }
+
+    /// Read word vectors, one word per line followed by its components,
+    /// optionally after a `<count> <dimensions>` header line
+    pub fn from_reader(
+        name: impl Into<String>,
+        reader: impl BufRead,
+    ) -> Result<Self, EmbeddingError> {
+        // Vocabularies are cased but may include accents
+        let analyzer = Analyzer::new(AnalysisConfig {
+            fold_diacritics: false,
+            cjk_bigrams: false,
+            ..AnalysisConfig::default()
+        });
+        let mut dimensions = 0;
+        let mut vectors = HashMap::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let mut fields = line.split_whitespace();
+            let Some(word) = fields.next() else {
+                continue;
+            };
+            let components: Vec<&str> = fields.collect();
+            if index == 0 && components.len() == 1 && word.parse::<usize>().is_ok() {
+                continue;
+            }
+            let invalid = |reason: String| EmbeddingError::InvalidModel {
+                line: index + 1,
+                reason,
+            };
+            let vector = components
+                .iter()
+                .map(|c| c.parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>()
+                .map_err(|e| invalid(e.to_string()))?;
+            if dimensions == 0 {
+                dimensions = vector.len();
+            }
+            if vector.is_empty() || vector.len() != dimensions {
+                return Err(invalid(format!(
+                    "expected {dimensions} components, found {}",
+                    vector.len()
+                )));
+            }
+            // Keep the first, usually most frequent, of words that
+            // normalize alike
+            vectors.entry(analyzer.normalize(word)).or_insert(vector);
+        }
+        if vectors.is_empty() {
+            return Err(EmbeddingError::InvalidModel {
+                line: 0,
+                reason: "no word vectors".into(),
+            });
+        }
+        Ok(Self {
+            name: name.into(),
+            dimensions,
+            vectors,
+            analyzer,
+        })
+    }
+
+    /// Number of words in the vocabulary
+    pub fn len(&self) -> usize {
+        self.vectors.len()
+    }
+
+    /// Check if the vocabulary is empty
+    pub fn is_empty(&self) -> bool {
+        self.vectors.is_empty()
+    }
+}
+
+impl Embedder for WordVectors {
+    fn model(&self) -> String {
+        format!(
+            "word-vectors-{}-{}-{}",
+            self.name,
+            self.dimensions,
+            self.vectors.len()
+        )
+    }
+
+    fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>, EmbeddingError> {
+        let mut sum = vec![0.0; self.dimensions];
+        for token in self.analyzer.words(text) {
+            if let Some(vector) = self.vectors.get(&token.term) {
+                let mut vector = vector.clone();
+                // Each word counts equally, however long its vector
+                normalize(&mut vector);
+                for (total, x) in sum.iter_mut().zip(vector) {
+                    *total += x;
+                }
+            }
+        }
+        normalize(&mut sum);
+        Ok(sum)
+    }
+}
+
+/// A note similar to a query or another note
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimilarNote {
+    pub id: NoteId,
+    pub title: String,
+
+    /// Cosine similarity, from 0 to 1 for practical purposes
+    pub score: f32,
+}
+
+/// A stored note vector
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct VectorEntry {
+    /// Fingerprint of the text the vector was computed from
+    fingerprint: u64,
+
+    /// Unit-length vector
+    vector: Vec<f32>,
+}
+
+/// Note vectors from one embedding model
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VectorIndex {
+    model: String,
+    dimensions: usize,
+    /// Fitted weights of the built-in embedder, so it can be restored
+    /// instead of refitted to notes that have changed since
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    weights: Option<Vec<f32>>,
+    entries: HashMap<NoteId, VectorEntry>,
+}
+
+impl VectorIndex {
+    pub fn new(model: impl Into<String>, dimensions: usize) -> Self {
+        Self {
+            model: model.into(),
+            dimensions,
+            weights: None,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Keep the fitted weights of the built-in embedder with the vectors
+    pub fn with_weights(mut self, weights: Vec<f32>) -> Self {
+        self.weights = Some(weights);
+        self
+    }
+
+    /// Fitted weights of the built-in embedder the vectors come from
+    pub fn weights(&self) -> Option<&[f32]> {
+        self.weights.as_deref()
+    }
+
+    /// Path of the vector file kept next to a notebook file
+    ///
+    /// The suffix is added to the whole file name, so notebooks differing
+    /// only in extension keep vectors of their own.
+    pub fn path_for(notebook_path: &Path) -> PathBuf {
+        let mut name = notebook_path.file_name().unwrap_or_default().to_os_string();
+        name.push(".vectors.json");
+        notebook_path.with_file_name(name)
+    }
+
+    /// The model the vectors come from
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Length of every vector
+    pub fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    /// Number of stored vectors
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if the index is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Check if the vectors come from the embedder
+    pub fn is_compatible(&self, embedder: &dyn Embedder) -> bool {
+        self.model == embedder.model() && self.dimensions == embedder.dimensions()
+    }
+
+    /// Check if a note's vector was computed from text with the fingerprint
+    pub(crate) fn is_current(&self, id: &NoteId, fingerprint: u64) -> bool {
+        self.entries
+            .get(id)
+            .is_some_and(|entry| entry.fingerprint == fingerprint)
+    }
+
+    /// Store a note's vector, scaled to unit length
+    pub fn insert(
+        &mut self,
+        id: NoteId,
+        fingerprint: u64,
+        mut vector: Vec<f32>,
+    ) -> Result<(), EmbeddingError> {
+        if vector.len() != self.dimensions {
+            return Err(EmbeddingError::DimensionMismatch {
+                expected: self.dimensions,
+                found: vector.len(),
+            });
+        }
+        normalize(&mut vector);
+        self.entries.insert(
+            id,
+            VectorEntry {
+                fingerprint,
+                vector,
+            },
+        );
+        Ok(())
+    }
+
+    /// Remove a note's vector
+    pub fn remove(&mut self, id: &NoteId) {
+        self.entries.remove(id);
+    }
+
+    /// Keep only the vectors of the given notes
+    pub(crate) fn retain(&mut self, keep: impl Fn(&NoteId) -> bool) {
+        self.entries.retain(|id, _| keep(id));
+    }
+
+    /// A note's unit-length vector
+    pub fn get(&self, id: &NoteId) -> Option<&[f32]> {
+        self.entries.get(id).map(|entry| entry.vector.as_slice())
+    }
+
+    /// Notes whose vectors are most similar to the vector, best first,
+    /// leaving out `exclude` and notes with nothing in common
+    pub fn nearest(
+        &self,
+        vector: &[f32],
+        limit: usize,
+        exclude: Option<&NoteId>,
+    ) -> Vec<(NoteId, f32)> {
+        let mut query = vector.to_vec();
+        normalize(&mut query);
+        let mut scored: Vec<(NoteId, f32)> = self
+            .entries
+            .iter()
+            .filter(|(id, _)| Some(*id) != exclude)
+            .map(|(id, entry)| {
+                let score = entry.vector.iter().zip(&query).map(|(a, b)| a * b).sum();
+                (*id, score)
+            })
+            .filter(|(_, score)| *score > 0.0)
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        scored.truncate(limit);
+        scored
+    }
+
+    /// Save the vectors to a file
+    pub fn save(&self, path: &Path) -> Result<(), StorageError> {
+        let json = serde_json::to_string(self)?;
+        storage::write_atomic(path, json.as_bytes())
+    }
+
+    /// Load vectors from a file
+    pub fn load(path: &Path) -> Result<Self, StorageError> {
+        if !path.exists() {
+            return Err(StorageError::NotFound(path.display().to_string()));
+        }
+        let json = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notebook::Notebook;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    fn add(notebook: &mut Notebook, title: &str, content: &str) -> NoteId {
+        let mut note = Note::new(title);
+        note.content = content.into();
+        notebook.add_note(note)
+    }
+
+    fn garden_notebook() -> (Notebook, [NoteId; 3]) {
+        let mut notebook = Notebook::new("Test");
+        let roses = add(
+            &mut notebook,
+            "Roses",
+            "Prune roses in spring and feed the soil with compost",
+        );
+        let compost = add(
+            &mut notebook,
+            "Compost",
+            "Compost improves garden soil; turn the heap in spring",
+        );
+        let taxes = add(
+            &mut notebook,
+            "Taxes",
+            "File the annual tax return before the deadline",
+        );
+        (notebook, [roses, compost, taxes])
+    }
+
+    #[test]
+    fn test_hashed_tfidf_similarity() {
+        let (mut notebook, [roses, compost, taxes]) = garden_notebook();
+
+        let similar = notebook.similar_notes(&roses, 10).unwrap();
+        assert_eq!(similar[0].id, compost);
+        assert!(similar.iter().all(|hit| hit.id != roses));
+        // Only "the" in common
+        assert!(similar.iter().all(|hit| hit.id != taxes || hit.score < 0.2));
+
+        let hits = notebook.semantic_search("tax deadline", 10).unwrap();
+        assert_eq!(hits[0].id, taxes);
+        assert!(notebook.similar_notes(&NoteId::new_v4(), 10).is_err());
+    }
+
+    #[test]
+    fn test_model_depends_on_weights() {
+        let fit = |texts: &[&str]| {
+            HashedTfIdf::fit(Analyzer::default(), 64, texts.iter().copied()).model()
+        };
+        assert_eq!(fit(&["roses", "taxes"]), fit(&["roses", "taxes"]));
+        assert_ne!(fit(&["roses", "taxes"]), fit(&["roses", "roses"]));
+    }
+
+    #[test]
+    fn test_vectors_follow_content() {
+        let (mut notebook, [roses, _, taxes]) = garden_notebook();
+        assert_eq!(notebook.refresh_vectors().unwrap(), 3);
+        assert_eq!(notebook.refresh_vectors().unwrap(), 0);
+
+        notebook.get_note_mut(&taxes).unwrap().content = "Roses need pruning in spring".into();
+        assert_eq!(notebook.refresh_vectors().unwrap(), 1);
+        let similar = notebook.similar_notes(&roses, 10).unwrap();
+        assert!(similar.iter().any(|hit| hit.id == taxes));
+
+        notebook.remove_note(&taxes);
+        assert_eq!(notebook.vector_index().len(), 2);
+    }
+
+    #[test]
+    fn test_vectors_are_named_after_the_whole_file() {
+        assert_eq!(
+            VectorIndex::path_for(Path::new("dir/notes.json")),
+            Path::new("dir/notes.json.vectors.json")
+        );
+        assert_ne!(
+            VectorIndex::path_for(Path::new("notes.json")),
+            VectorIndex::path_for(Path::new("notes.nxb"))
+        );
+    }
+
+    #[test]
+    fn test_vector_index_saved_next_to_notebook() {
+        let dir = tempdir().unwrap();
+        let path = VectorIndex::path_for(&dir.path().join("garden.nexia.json"));
+        assert!(path.ends_with("garden.nexia.json.vectors.json"));
+
+        let (mut notebook, [roses, ..]) = garden_notebook();
+        notebook.refresh_vectors().unwrap();
+        notebook.vector_index().save(&path).unwrap();
+
+        let mut loaded = notebook.clone();
+        loaded.set_embedder(None);
+        assert!(loaded.load_vector_index(VectorIndex::load(&path).unwrap()));
+        assert_eq!(loaded.refresh_vectors().unwrap(), 0);
+        assert_eq!(
+            loaded.vector_index().get(&roses),
+            notebook.vector_index().get(&roses)
+        );
+
+        // Notes added before the vectors are loaded don't change the
+        // weights the saved vectors were computed with
+        let mut grown = notebook.clone();
+        grown.set_embedder(None);
+        add(&mut grown, "Invoices", "Send invoices for the tax year");
+        assert!(grown.load_vector_index(VectorIndex::load(&path).unwrap()));
+        assert_eq!(grown.embedder().model(), notebook.vector_index().model());
+        assert_eq!(grown.refresh_vectors().unwrap(), 1);
+
+        // Vectors from another model are discarded
+        assert!(!loaded.load_vector_index(VectorIndex::new("other", 3)));
+        assert!(matches!(
+            VectorIndex::load(&dir.path().join("missing.json")),
+            Err(StorageError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_word_vectors() {
+        let model = "4 3\n\
+                     garden 1.0 0.1 0.0\n\
+                     roses 0.9 0.2 0.0\n\
+                     tax 0.0 0.1 1.0\n\
+                     Café 0.0 1.0 0.0\n";
+        let vectors = WordVectors::from_reader("tiny", model.as_bytes()).unwrap();
+        assert_eq!((vectors.len(), vectors.dimensions()), (4, 3));
+        assert!(vectors.embed("CAFÉ").unwrap()[1] > 0.99);
+        assert!(vectors.embed("unknown").unwrap().iter().all(|x| *x == 0.0));
+
+        let mut notebook = Notebook::new("Test");
+        let garden = add(&mut notebook, "Garden", "");
+        let roses = add(&mut notebook, "Roses", "");
+        add(&mut notebook, "Tax", "");
+        notebook.set_embedder(Some(Arc::new(vectors)));
+        let similar = notebook.similar_notes(&garden, 1).unwrap();
+        assert_eq!(similar[0].id, roses);
+
+        let ragged = WordVectors::from_reader("bad", "a 1 2\nb 1\n".as_bytes());
+        assert!(matches!(
+            ragged,
+            Err(EmbeddingError::InvalidModel { line: 2, .. })
+        ));
+    }
+}
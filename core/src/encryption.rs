@@ -0,0 +1,617 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Encryption - passphrase-protected notebook files
+//!
+//! [`EncryptedStorage`] wraps a storage that keeps a notebook in a single
+//! file (JSON or binary) and encrypts the file's contents. Folder and
+//! SQLite storage are read in place rather than as one file, so they can't
+//! be wrapped.
+//!
+//! The contents are encrypted with XChaCha20-Poly1305 under a random data
+//! key. The data key is itself encrypted under a key derived from the
+//! passphrase with Argon2id, and kept in the header along with the salt and
+//! Argon2 costs. Changing the passphrase only re-encrypts the data key, so
+//! the notebook is neither decoded nor re-encrypted.
+//!
+//! Backups are kept encrypted as well: saving over a file that was not
+//! encrypted yet also encrypts the backups made of it before.
+//!
+//! The header ends with a checksum, so damage to the header is reported as
+//! a corrupted file rather than a wrong passphrase; damage to the contents
+//! fails their authentication.
+
+use crate::embedding::fnv1a;
+use crate::notebook::Notebook;
+use crate::storage::{self, FileStorage, Loaded, Storage, StorageError, DEFAULT_BACKUPS};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// First bytes of every encrypted notebook file
+pub const MAGIC: [u8; 8] = *b"\x89NXE\r\n\x1a\n";
+
+/// Version of the header layout, after the magic number
+const LAYOUT_VERSION: u8 = 1;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+
+// Header fields, in order: magic, layout version, Argon2 costs, salt,
+// nonce and encrypted data key, nonce for the contents, checksum
+const PARAMS_AT: usize = MAGIC.len() + 1;
+const SALT_AT: usize = PARAMS_AT + 12;
+const KEY_NONCE_AT: usize = SALT_AT + SALT_LEN;
+const KEY_AT: usize = KEY_NONCE_AT + NONCE_LEN;
+const NONCE_AT: usize = KEY_AT + KEY_LEN + TAG_LEN;
+const CHECKSUM_AT: usize = NONCE_AT + NONCE_LEN;
+const HEADER_LEN: usize = CHECKSUM_AT + 8;
+
+/// Most memory a file may ask Argon2 to use, in KiB, so a damaged or
+/// hostile header can't exhaust memory
+const MAX_MEMORY_KIB: u32 = 2 * 1024 * 1024;
+
+/// Argon2id costs for deriving a key from a passphrase
+///
+/// The costs are stored in each file, so files saved with different costs
+/// all open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    /// Memory used, in KiB
+    pub memory_kib: u32,
+
+    /// Number of passes over the memory
+    pub iterations: u32,
+
+    /// Degree of parallelism
+    pub parallelism: u32,
+}
+
+impl KdfParams {
+    fn argon2(self) -> Result<Argon2<'static>, StorageError> {
+        if self.memory_kib > MAX_MEMORY_KIB {
+            return Err(StorageError::InvalidFormat(format!(
+                "key derivation memory of {} KiB is too large",
+                self.memory_kib
+            )));
+        }
+        let params = Params::new(
+            self.memory_kib,
+            self.iterations,
+            self.parallelism,
+            Some(KEY_LEN),
+        )
+        .map_err(|e| StorageError::InvalidFormat(format!("key derivation: {e}")))?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+
+    fn derive(self, passphrase: &str, salt: &[u8]) -> Result<[u8; KEY_LEN], StorageError> {
+        let mut key = [0; KEY_LEN];
+        self.argon2()?
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| StorageError::InvalidFormat(format!("key derivation: {e}")))?;
+        Ok(key)
+    }
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// Check if file contents start like an encrypted notebook
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// Encrypt file contents with a passphrase
+pub fn encrypt(
+    plaintext: &[u8],
+    passphrase: &str,
+    params: KdfParams,
+) -> Result<Vec<u8>, StorageError> {
+    let data_key = XChaCha20Poly1305::generate_key(&mut OsRng);
+    let mut bytes = header(&data_key, passphrase, params)?;
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    bytes[NONCE_AT..CHECKSUM_AT].copy_from_slice(&nonce);
+    seal_header(&mut bytes);
+
+    let contents = XChaCha20Poly1305::new(&data_key)
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad: &bytes[..PARAMS_AT],
+            },
+        )
+        .map_err(|_| StorageError::InvalidFormat("encryption failed".into()))?;
+    bytes.extend_from_slice(&contents);
+    Ok(bytes)
+}
+
+/// Decrypt file contents with a passphrase
+pub fn decrypt(bytes: &[u8], passphrase: &str) -> Result<Vec<u8>, StorageError> {
+    let data_key = unwrap_key(bytes, passphrase)?;
+    XChaCha20Poly1305::new(&data_key.into())
+        .decrypt(
+            XNonce::from_slice(&bytes[NONCE_AT..CHECKSUM_AT]),
+            Payload {
+                msg: &bytes[HEADER_LEN..],
+                aad: &bytes[..PARAMS_AT],
+            },
+        )
+        .map_err(|_| StorageError::Corrupted("contents fail authentication".into()))
+}
+
+/// Re-encrypt file contents for a new passphrase
+///
+/// Only the header changes; the encrypted notebook is copied as it is.
+pub fn change_passphrase(
+    bytes: &[u8],
+    old: &str,
+    new: &str,
+    params: KdfParams,
+) -> Result<Vec<u8>, StorageError> {
+    let data_key = unwrap_key(bytes, old)?;
+    let mut changed = header(&data_key.into(), new, params)?;
+    changed[NONCE_AT..CHECKSUM_AT].copy_from_slice(&bytes[NONCE_AT..CHECKSUM_AT]);
+    seal_header(&mut changed);
+    changed.extend_from_slice(&bytes[HEADER_LEN..]);
+    Ok(changed)
+}
+
+/// Build a header holding the data key encrypted for a passphrase, without
+/// the contents nonce or checksum
+fn header(
+    data_key: &chacha20poly1305::Key,
+    passphrase: &str,
+    params: KdfParams,
+) -> Result<Vec<u8>, StorageError> {
+    let mut bytes = vec![0; HEADER_LEN];
+    bytes[..MAGIC.len()].copy_from_slice(&MAGIC);
+    bytes[MAGIC.len()] = LAYOUT_VERSION;
+    for (i, cost) in [params.memory_kib, params.iterations, params.parallelism]
+        .into_iter()
+        .enumerate()
+    {
+        bytes[PARAMS_AT + i * 4..PARAMS_AT + i * 4 + 4].copy_from_slice(&cost.to_le_bytes());
+    }
+    OsRng.fill_bytes(&mut bytes[SALT_AT..KEY_NONCE_AT]);
+    let key_nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    bytes[KEY_NONCE_AT..KEY_AT].copy_from_slice(&key_nonce);
+
+    let kek = params.derive(passphrase, &bytes[SALT_AT..KEY_NONCE_AT])?;
+    let wrapped = XChaCha20Poly1305::new(&kek.into())
+        .encrypt(
+            &key_nonce,
+            Payload {
+                msg: data_key.as_slice(),
+                aad: &bytes[..KEY_AT],
+            },
+        )
+        .map_err(|_| StorageError::InvalidFormat("encryption failed".into()))?;
+    bytes[KEY_AT..NONCE_AT].copy_from_slice(&wrapped);
+    Ok(bytes)
+}
+
+fn seal_header(bytes: &mut [u8]) {
+    let checksum = fnv1a(&bytes[..CHECKSUM_AT]);
+    bytes[CHECKSUM_AT..HEADER_LEN].copy_from_slice(&checksum.to_le_bytes());
+}
+
+/// Check the header and decrypt the data key in it
+fn unwrap_key(bytes: &[u8], passphrase: &str) -> Result<[u8; KEY_LEN], StorageError> {
+    if !is_encrypted(bytes) {
+        return Err(StorageError::InvalidFormat(
+            "not an encrypted notebook file".into(),
+        ));
+    }
+    if bytes.len() < HEADER_LEN + TAG_LEN {
+        return Err(StorageError::Corrupted("file is truncated".into()));
+    }
+    let layout = bytes[MAGIC.len()];
+    if layout != LAYOUT_VERSION {
+        return Err(StorageError::InvalidFormat(format!(
+            "unknown encryption layout version {layout}"
+        )));
+    }
+    let checksum = u64::from_le_bytes(bytes[CHECKSUM_AT..HEADER_LEN].try_into().unwrap());
+    if fnv1a(&bytes[..CHECKSUM_AT]) != checksum {
+        return Err(StorageError::Corrupted("header checksum mismatch".into()));
+    }
+
+    let cost = |i: usize| {
+        u32::from_le_bytes(
+            bytes[PARAMS_AT + i * 4..PARAMS_AT + i * 4 + 4]
+                .try_into()
+                .unwrap(),
+        )
+    };
+    let params = KdfParams {
+        memory_kib: cost(0),
+        iterations: cost(1),
+        parallelism: cost(2),
+    };
+    let kek = params.derive(passphrase, &bytes[SALT_AT..KEY_NONCE_AT])?;
+    // The header is intact, so a key that fails to decrypt was derived
+    // from the wrong passphrase
+    let data_key = XChaCha20Poly1305::new(&kek.into())
+        .decrypt(
+            XNonce::from_slice(&bytes[KEY_NONCE_AT..KEY_AT]),
+            Payload {
+                msg: &bytes[KEY_AT..NONCE_AT],
+                aad: &bytes[..KEY_AT],
+            },
+        )
+        .map_err(|_| StorageError::WrongPassphrase)?;
+    data_key
+        .try_into()
+        .map_err(|_| StorageError::Corrupted("data key has the wrong length".into()))
+}
+
+/// Storage that encrypts the files of another storage with a passphrase
+pub struct EncryptedStorage<S> {
+    inner: S,
+    passphrase: String,
+    params: KdfParams,
+    backups: usize,
+}
+
+impl<S: FileStorage> EncryptedStorage<S> {
+    pub fn new(inner: S, passphrase: impl Into<String>) -> Self {
+        Self {
+            inner,
+            passphrase: passphrase.into(),
+            params: KdfParams::default(),
+            backups: DEFAULT_BACKUPS,
+        }
+    }
+
+    /// Derive keys for saved files with these Argon2 costs
+    pub fn with_params(mut self, params: KdfParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// Keep this many backups of each file; zero keeps none
+    pub fn with_backups(mut self, backups: usize) -> Self {
+        self.backups = backups;
+        self
+    }
+
+    /// Load a notebook, falling back to the newest backup that opens if
+    /// the file is missing or damaged
+    ///
+    /// A wrong passphrase is not damage and is returned as it is, as are
+    /// other errors that don't mean damage; see
+    /// [`JsonStorage::load_or_recover`](crate::storage::JsonStorage::load_or_recover).
+    pub fn load_or_recover(&self, path: &Path) -> Result<Loaded, StorageError> {
+        let error = match self.load(path) {
+            Ok(notebook) => {
+                return Ok(Loaded {
+                    notebook,
+                    recovered_from: None,
+                })
+            }
+            Err(e) if e.is_damage() => e,
+            Err(e) => return Err(e),
+        };
+        for backup in storage::list_backups(path).unwrap_or_default() {
+            if let Ok(notebook) = fs::read(&backup)
+                .map_err(StorageError::from)
+                .and_then(|bytes| self.decode(&bytes))
+            {
+                return Ok(Loaded {
+                    notebook,
+                    recovered_from: Some(backup),
+                });
+            }
+        }
+        Err(error)
+    }
+
+    /// Change the passphrase of a saved file and use the new one from now
+    ///
+    /// Backups that open with the current passphrase are changed too, so
+    /// they still open if the file has to be recovered, and backups still
+    /// in plain text are encrypted with the new passphrase.
+    pub fn change_passphrase(
+        &mut self,
+        path: &Path,
+        new_passphrase: impl Into<String>,
+    ) -> Result<(), StorageError> {
+        if !path.exists() {
+            return Err(StorageError::NotFound(path.display().to_string()));
+        }
+        let new_passphrase = new_passphrase.into();
+        let bytes = fs::read(path)?;
+        let changed = change_passphrase(&bytes, &self.passphrase, &new_passphrase, self.params)?;
+        storage::write_atomic(path, &changed)?;
+
+        for backup in storage::list_backups(path)? {
+            let bytes = fs::read(&backup)?;
+            if let Ok(changed) =
+                change_passphrase(&bytes, &self.passphrase, &new_passphrase, self.params)
+            {
+                storage::write_atomic(&backup, &changed)?;
+            }
+        }
+        self.passphrase = new_passphrase;
+        self.encrypt_plain_backups(path)
+    }
+
+    /// Encrypt backups of a file that are still in plain text, so that
+    /// encrypting a notebook leaves no readable copies of it behind
+    fn encrypt_plain_backups(&self, path: &Path) -> Result<(), StorageError> {
+        for backup in storage::list_backups(path)? {
+            let bytes = fs::read(&backup)?;
+            if !is_encrypted(&bytes) {
+                let encrypted = encrypt(&bytes, &self.passphrase, self.params)?;
+                storage::write_atomic(&backup, &encrypted)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S: FileStorage> Storage for EncryptedStorage<S> {
+    fn save(&self, notebook: &Notebook, path: &Path) -> Result<(), StorageError> {
+        let bytes = self.encode(notebook)?;
+        storage::back_up(path, self.backups)?;
+        // Includes the copy just made when the file was not encrypted yet
+        self.encrypt_plain_backups(path)?;
+        storage::write_atomic(path, &bytes)
+    }
+
+    fn load(&self, path: &Path) -> Result<Notebook, StorageError> {
+        if !path.exists() {
+            return Err(StorageError::NotFound(path.display().to_string()));
+        }
+        self.decode(&fs::read(path)?)
+    }
+}
+
+impl<S: FileStorage> FileStorage for EncryptedStorage<S> {
+    fn encode(&self, notebook: &Notebook) -> Result<Vec<u8>, StorageError> {
+        encrypt(&self.inner.encode(notebook)?, &self.passphrase, self.params)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Notebook, StorageError> {
+        self.inner.decode(&decrypt(bytes, &self.passphrase)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::JsonStorage;
+    use tempfile::tempdir;
+
+    // Cheap costs keep the tests fast
+    const FAST: KdfParams = KdfParams {
+        memory_kib: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    fn sample() -> Notebook {
+        let mut notebook = Notebook::new("Secrets");
+        let plan = notebook.create_note("Launch codename");
+        let budget = notebook.create_note("Budget");
+        notebook.link_notes(plan, budget).unwrap();
+        notebook
+    }
+
+    fn storage(passphrase: &str) -> EncryptedStorage<JsonStorage> {
+        EncryptedStorage::new(JsonStorage::new(), passphrase).with_params(FAST)
+    }
+
+    #[test]
+    fn test_round_trip_hides_contents() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("secrets.nexia.json");
+        storage("correct horse").save(&sample(), &path).unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        assert!(is_encrypted(&bytes));
+        let text = String::from_utf8_lossy(&bytes);
+        assert!(!text.contains("Launch codename"));
+        assert!(!text.contains("Secrets"));
+
+        let loaded = storage("correct horse").load(&path).unwrap();
+        assert_eq!(loaded.name, "Secrets");
+        assert_eq!(loaded.len(), 2);
+
+        // Saving twice never reuses a nonce or salt
+        let again = storage("correct horse").encode(&sample()).unwrap();
+        assert_ne!(again[SALT_AT..NONCE_AT], bytes[SALT_AT..NONCE_AT]);
+        assert_ne!(again[NONCE_AT..CHECKSUM_AT], bytes[NONCE_AT..CHECKSUM_AT]);
+    }
+
+    #[cfg(feature = "binary")]
+    #[test]
+    fn test_wraps_binary_storage() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("secrets.nxb");
+        let binary =
+            EncryptedStorage::new(crate::binary::BinaryStorage::new(), "pw").with_params(FAST);
+        binary.save(&sample(), &path).unwrap();
+        assert_eq!(binary.load(&path).unwrap().len(), 2);
+        // JSON storage decodes binary contents too
+        assert_eq!(storage("pw").load(&path).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_wrong_passphrase_and_corruption_are_distinct() {
+        let bytes = storage("right").encode(&sample()).unwrap();
+        assert!(matches!(
+            storage("wrong").decode(&bytes),
+            Err(StorageError::WrongPassphrase)
+        ));
+
+        for index in [SALT_AT, KEY_AT, NONCE_AT, HEADER_LEN, bytes.len() - 1] {
+            let mut damaged = bytes.clone();
+            damaged[index] ^= 1;
+            assert!(
+                matches!(
+                    storage("right").decode(&damaged),
+                    Err(StorageError::Corrupted(_))
+                ),
+                "byte {index}"
+            );
+        }
+        assert!(matches!(
+            storage("right").decode(&bytes[..HEADER_LEN]),
+            Err(StorageError::Corrupted(_))
+        ));
+        assert!(matches!(
+            storage("right").decode(b"{}"),
+            Err(StorageError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_plain_storage_asks_for_a_passphrase() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("secrets.nexia.json");
+        storage("pw").save(&sample(), &path).unwrap();
+        assert!(matches!(
+            JsonStorage::new().load(&path),
+            Err(StorageError::PassphraseRequired)
+        ));
+    }
+
+    #[test]
+    fn test_encrypting_leaves_no_plain_copies() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("secrets.nexia.json");
+        let plain = JsonStorage::new();
+        plain.save(&sample(), &path).unwrap();
+        plain.save(&sample(), &path).unwrap();
+
+        storage("pw").save(&sample(), &path).unwrap();
+        let backups = storage::list_backups(&path).unwrap();
+        assert_eq!(backups.len(), 2);
+        for file in fs::read_dir(dir.path()).unwrap() {
+            let bytes = fs::read(file.unwrap().path()).unwrap();
+            assert!(!String::from_utf8_lossy(&bytes).contains("Launch codename"));
+        }
+        // Backups still recover the notebook with the passphrase
+        assert_eq!(storage("pw").load(&backups[1]).unwrap().len(), 2);
+
+        // Without the passphrase no backup is opened instead
+        assert!(matches!(
+            plain.load_or_recover(&path),
+            Err(StorageError::PassphraseRequired)
+        ));
+
+        // A plain backup left by another save is encrypted with the new
+        // passphrase when it changes
+        fs::write(&backups[1], plain.encode(&sample()).unwrap()).unwrap();
+        storage("pw").change_passphrase(&path, "new").unwrap();
+        assert!(is_encrypted(&fs::read(&backups[1]).unwrap()));
+        assert_eq!(storage("new").load(&backups[1]).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_damaged_files_recover_from_encrypted_backups() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("secrets.nexia.json");
+        let encrypted = storage("pw");
+        let mut notebook = sample();
+        encrypted.save(&notebook, &path).unwrap();
+        notebook.create_note("Newer");
+        encrypted.save(&notebook, &path).unwrap();
+        let backups = storage::list_backups(&path).unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(matches!(
+            encrypted.load(&path),
+            Err(StorageError::Corrupted(_))
+        ));
+        let loaded = encrypted.load_or_recover(&path).unwrap();
+        assert_eq!(loaded.notebook.len(), 2);
+        assert_eq!(loaded.recovered_from, Some(backups[0].clone()));
+
+        // A wrong passphrase never falls back to a backup
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            storage("wrong").load_or_recover(&path),
+            Err(StorageError::WrongPassphrase)
+        ));
+    }
+
+    #[test]
+    fn test_backups_of_sibling_files_stay_as_they_are() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("secrets.nexia.json");
+        let sibling = dir.path().join("secrets.nexia.json.old.json");
+        let plain = JsonStorage::new();
+        plain.save(&sample(), &sibling).unwrap();
+        plain.save(&sample(), &sibling).unwrap();
+        let unrelated = dir.path().join("secrets.nexia.json.draft.bak");
+        fs::write(&unrelated, "draft").unwrap();
+
+        let mut encrypted = storage("pw");
+        encrypted.save(&sample(), &path).unwrap();
+        encrypted.save(&sample(), &path).unwrap();
+        encrypted.change_passphrase(&path, "new").unwrap();
+
+        let sibling_backup = &storage::list_backups(&sibling).unwrap()[0];
+        assert!(!is_encrypted(&fs::read(sibling_backup).unwrap()));
+        assert_eq!(plain.load(sibling_backup).unwrap().len(), 2);
+        assert_eq!(fs::read_to_string(&unrelated).unwrap(), "draft");
+    }
+
+    #[test]
+    fn test_change_passphrase_keeps_contents() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("secrets.nexia.json");
+        let mut encrypted = storage("old").with_backups(2);
+        encrypted.save(&sample(), &path).unwrap();
+        encrypted.save(&sample(), &path).unwrap();
+        let before = fs::read(&path).unwrap();
+
+        encrypted.change_passphrase(&path, "new").unwrap();
+        let after = fs::read(&path).unwrap();
+        assert_eq!(after[HEADER_LEN..], before[HEADER_LEN..]);
+        assert_eq!(after[NONCE_AT..CHECKSUM_AT], before[NONCE_AT..CHECKSUM_AT]);
+
+        assert_eq!(encrypted.load(&path).unwrap().name, "Secrets");
+        assert!(matches!(
+            storage("old").load(&path),
+            Err(StorageError::WrongPassphrase)
+        ));
+        let backup = &storage::list_backups(&path).unwrap()[0];
+        assert!(storage("new").load(backup).is_ok());
+
+        // A wrong current passphrase leaves the file alone
+        let mut wrong = storage("guess");
+        assert!(matches!(
+            wrong.change_passphrase(&path, "other"),
+            Err(StorageError::WrongPassphrase)
+        ));
+        assert_eq!(fs::read(&path).unwrap(), after);
+    }
+
+    #[test]
+    fn test_refuses_excessive_costs() {
+        let mut bytes = storage("pw").encode(&sample()).unwrap();
+        bytes[PARAMS_AT..PARAMS_AT + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        seal_header(&mut bytes);
+        assert!(matches!(
+            storage("pw").decode(&bytes),
+            Err(StorageError::InvalidFormat(_))
+        ));
+    }
+}
@@ -0,0 +1,564 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Facets - attribute summaries, filters and groupings for browsing notes
+//!
+//! A facet summarizes one attribute key across a set of notes: how many
+//! notes set it, its most common values, and for numbers and dates their
+//! range and distribution. Array values count once per element, so a
+//! `tags` attribute facets by individual tag. Facet selections filter notes
+//! (values within one selection are alternatives; separate selections must
+//! all hold), and notes can be grouped by an attribute for list and kanban
+//! views.
+
+use crate::note::{Note, NoteId};
+use chrono::{DateTime, Datelike, Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+
+/// Period covered by each date bucket
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DateGranularity {
+    Day,
+    /// Weeks starting on Monday
+    Week,
+    #[default]
+    Month,
+    Year,
+}
+
+impl DateGranularity {
+    /// First day of the period containing the date
+    fn bucket(self, date: NaiveDate) -> NaiveDate {
+        match self {
+            DateGranularity::Day => date,
+            DateGranularity::Week => {
+                date - Duration::days(i64::from(date.weekday().num_days_from_monday()))
+            }
+            DateGranularity::Month => date.with_day(1).unwrap_or(date),
+            DateGranularity::Year => date.with_ordinal(1).unwrap_or(date),
+        }
+    }
+}
+
+/// Most buckets a numeric histogram is split into
+pub const MAX_HISTOGRAM_BUCKETS: usize = 1000;
+
+/// Options for computing facets
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FacetOptions {
+    /// Most values listed per facet, most common first
+    pub max_values: usize,
+
+    /// Number of equal-width buckets in numeric histograms, at most
+    /// [`MAX_HISTOGRAM_BUCKETS`]
+    pub histogram_buckets: usize,
+
+    /// Period of date buckets
+    pub date_granularity: DateGranularity,
+}
+
+impl Default for FacetOptions {
+    fn default() -> Self {
+        Self {
+            max_values: 50,
+            histogram_buckets: 10,
+            date_granularity: DateGranularity::Month,
+        }
+    }
+}
+
+/// A value and the number of notes having it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValueCount {
+    pub value: Value,
+    pub count: usize,
+}
+
+/// One histogram bucket; `end` is exclusive except in the last bucket
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistogramBucket {
+    pub start: f64,
+    pub end: f64,
+    pub count: usize,
+}
+
+/// Range and distribution of an attribute's numeric values
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NumericSummary {
+    pub min: f64,
+    pub max: f64,
+    pub histogram: Vec<HistogramBucket>,
+}
+
+/// Number of date values falling in the period starting on `start`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DateBucket {
+    pub start: NaiveDate,
+    pub count: usize,
+}
+
+/// Summary of one attribute key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Facet {
+    /// Attribute key
+    pub key: String,
+
+    /// Number of notes that set the attribute
+    pub notes: usize,
+
+    /// Number of distinct values
+    pub distinct: usize,
+
+    /// Most common values, most common first
+    pub values: Vec<ValueCount>,
+
+    /// Numeric range and histogram, if any values are numbers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub numeric: Option<NumericSummary>,
+
+    /// Date buckets in order, if any values are dates
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dates: Vec<DateBucket>,
+}
+
+/// A filter on one attribute
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FacetSelection {
+    /// The attribute has any of the values
+    Values { key: String, values: Vec<Value> },
+
+    /// The attribute has a number in the inclusive range
+    Range {
+        key: String,
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+
+    /// The attribute has a date in the inclusive range
+    Dates {
+        key: String,
+        start: Option<NaiveDate>,
+        end: Option<NaiveDate>,
+    },
+
+    /// The attribute is set
+    Present { key: String },
+
+    /// The attribute is not set
+    Missing { key: String },
+}
+
+impl FacetSelection {
+    /// Check if a note satisfies the selection
+    pub fn matches(&self, note: &Note) -> bool {
+        match self {
+            FacetSelection::Values { key, values } => note
+                .get_attribute(key)
+                .is_some_and(|v| elements(v).any(|element| values.contains(element))),
+            FacetSelection::Range { key, min, max } => note.get_attribute(key).is_some_and(|v| {
+                elements(v).filter_map(Value::as_f64).any(|number| {
+                    min.is_none_or(|min| number >= min) && max.is_none_or(|max| number <= max)
+                })
+            }),
+            FacetSelection::Dates { key, start, end } => note.get_attribute(key).is_some_and(|v| {
+                elements(v).filter_map(as_date).any(|date| {
+                    start.is_none_or(|start| date >= start) && end.is_none_or(|end| date <= end)
+                })
+            }),
+            FacetSelection::Present { key } => note.attributes.contains_key(key),
+            FacetSelection::Missing { key } => !note.attributes.contains_key(key),
+        }
+    }
+}
+
+/// Notes sharing one value of the grouping attribute
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NoteGroup {
+    /// The shared value, or None for notes without the attribute
+    pub value: Option<Value>,
+
+    /// Notes in the group, ordered by title
+    pub notes: Vec<NoteId>,
+}
+
+/// The value itself, or each element of an array value; nulls are skipped
+fn elements(value: &Value) -> impl Iterator<Item = &Value> {
+    let items = match value {
+        Value::Array(items) => items.as_slice(),
+        value => std::slice::from_ref(value),
+    };
+    items.iter().filter(|v| !v.is_null())
+}
+
+/// Calendar date of a `YYYY-MM-DD` or RFC 3339 string
+fn as_date(value: &Value) -> Option<NaiveDate> {
+    let text = value.as_str()?;
+    NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .ok()
+        .or_else(|| {
+            DateTime::parse_from_rfc3339(text)
+                .ok()
+                .map(|t| t.date_naive())
+        })
+}
+
+/// Order values by type (booleans, numbers, text, then anything else) and
+/// naturally within a type
+pub fn compare_values(a: &Value, b: &Value) -> Ordering {
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Number(_) => 2,
+            Value::String(_) => 3,
+            Value::Array(_) => 4,
+            Value::Object(_) => 5,
+        }
+    }
+    match (a, b) {
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Number(a), Value::Number(b)) => {
+            let (a, b) = (a.as_f64().unwrap_or(0.0), b.as_f64().unwrap_or(0.0));
+            a.total_cmp(&b)
+        }
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        _ => rank(a)
+            .cmp(&rank(b))
+            .then_with(|| a.to_string().cmp(&b.to_string())),
+    }
+}
+
+fn histogram(numbers: &[f64], buckets: usize) -> NumericSummary {
+    let min = numbers.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = numbers.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let buckets = if max > min {
+        buckets.clamp(1, MAX_HISTOGRAM_BUCKETS)
+    } else {
+        1
+    };
+    let width = (max - min) / buckets as f64;
+
+    let mut counts = vec![0; buckets];
+    for number in numbers {
+        let index = if width > 0.0 {
+            (((number - min) / width) as usize).min(buckets - 1)
+        } else {
+            0
+        };
+        counts[index] += 1;
+    }
+    let histogram = counts
+        .into_iter()
+        .enumerate()
+        .map(|(i, count)| HistogramBucket {
+            start: min + width * i as f64,
+            end: if i + 1 == buckets {
+                max
+            } else {
+                min + width * (i + 1) as f64
+            },
+            count,
+        })
+        .collect();
+    NumericSummary {
+        min,
+        max,
+        histogram,
+    }
+}
+
+/// Summarize every attribute key set on the notes, ordered by key
+pub fn compute_facets<'a>(
+    notes: impl IntoIterator<Item = &'a Note>,
+    options: &FacetOptions,
+) -> Vec<Facet> {
+    #[derive(Default)]
+    struct Tally<'a> {
+        notes: usize,
+        values: Vec<&'a Value>,
+    }
+
+    let mut tallies: BTreeMap<&str, Tally> = BTreeMap::new();
+    for note in notes {
+        for (key, value) in &note.attributes {
+            let tally = tallies.entry(key.as_str()).or_default();
+            tally.notes += 1;
+            tally.values.extend(elements(value));
+        }
+    }
+
+    tallies
+        .into_iter()
+        .map(|(key, tally)| {
+            // Values are not hashable, so count them by their JSON text
+            let mut counts: HashMap<String, ValueCount> = HashMap::new();
+            for value in &tally.values {
+                counts
+                    .entry(value.to_string())
+                    .or_insert_with(|| ValueCount {
+                        value: (*value).clone(),
+                        count: 0,
+                    })
+                    .count += 1;
+            }
+            let distinct = counts.len();
+            let mut values: Vec<ValueCount> = counts.into_values().collect();
+            values.sort_by(|a, b| {
+                b.count
+                    .cmp(&a.count)
+                    .then_with(|| compare_values(&a.value, &b.value))
+            });
+            values.truncate(options.max_values);
+
+            let numbers: Vec<f64> = tally.values.iter().filter_map(|v| v.as_f64()).collect();
+            let numeric =
+                (!numbers.is_empty()).then(|| histogram(&numbers, options.histogram_buckets));
+
+            let mut buckets: BTreeMap<NaiveDate, usize> = BTreeMap::new();
+            for date in tally.values.iter().filter_map(|v| as_date(v)) {
+                *buckets
+                    .entry(options.date_granularity.bucket(date))
+                    .or_default() += 1;
+            }
+            let dates = buckets
+                .into_iter()
+                .map(|(start, count)| DateBucket { start, count })
+                .collect();
+
+            Facet {
+                key: key.to_string(),
+                notes: tally.notes,
+                distinct,
+                values,
+                numeric,
+                dates,
+            }
+        })
+        .collect()
+}
+
+/// Notes satisfying every selection
+pub fn filter_notes<'a>(
+    notes: impl IntoIterator<Item = &'a Note>,
+    selections: &[FacetSelection],
+) -> Vec<&'a Note> {
+    notes
+        .into_iter()
+        .filter(|note| selections.iter().all(|selection| selection.matches(note)))
+        .collect()
+}
+
+/// Group notes by an attribute's value, in value order, with notes lacking
+/// it last
+///
+/// A note whose value is an array appears in one group per element.
+pub fn group_notes<'a>(notes: impl IntoIterator<Item = &'a Note>, key: &str) -> Vec<NoteGroup> {
+    let mut groups: HashMap<String, (Value, Vec<&Note>)> = HashMap::new();
+    let mut missing: Vec<&Note> = Vec::new();
+    for note in notes {
+        let mut grouped = false;
+        if let Some(value) = note.get_attribute(key) {
+            for element in elements(value) {
+                groups
+                    .entry(element.to_string())
+                    .or_insert_with(|| (element.clone(), Vec::new()))
+                    .1
+                    .push(note);
+                grouped = true;
+            }
+        }
+        if !grouped {
+            missing.push(note);
+        }
+    }
+
+    let by_title = |a: &&Note, b: &&Note| a.title.cmp(&b.title).then(a.id.cmp(&b.id));
+    let mut groups: Vec<(Value, Vec<&Note>)> = groups.into_values().collect();
+    groups.sort_by(|a, b| compare_values(&a.0, &b.0));
+    let mut result: Vec<NoteGroup> = groups
+        .into_iter()
+        .map(|(value, mut notes)| {
+            notes.sort_by(by_title);
+            NoteGroup {
+                value: Some(value),
+                notes: notes.iter().map(|note| note.id).collect(),
+            }
+        })
+        .collect();
+    if !missing.is_empty() {
+        missing.sort_by(by_title);
+        result.push(NoteGroup {
+            value: None,
+            notes: missing.iter().map(|note| note.id).collect(),
+        });
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notebook::Notebook;
+    use serde_json::json;
+
+    fn sample() -> Notebook {
+        let mut notebook = Notebook::new("Test");
+        for (title, status, priority, due, tags) in [
+            ("A", "todo", 1, "2024-01-15", json!(["work"])),
+            ("B", "todo", 5, "2024-01-20", json!(["work", "urgent"])),
+            ("C", "done", 10, "2024-03-01", json!([])),
+        ] {
+            let mut note = Note::new(title);
+            note.set_attribute("status", json!(status));
+            note.set_attribute("priority", json!(priority));
+            note.set_attribute("due", json!(due));
+            note.set_attribute("tags", tags);
+            notebook.add_note(note);
+        }
+        notebook.create_note("Bare");
+        notebook
+    }
+
+    fn facet<'a>(facets: &'a [Facet], key: &str) -> &'a Facet {
+        facets.iter().find(|facet| facet.key == key).unwrap()
+    }
+
+    #[test]
+    fn test_value_counts() {
+        let notebook = sample();
+        let facets = notebook.facets(&[], &FacetOptions::default());
+        let keys: Vec<&str> = facets.iter().map(|f| f.key.as_str()).collect();
+        assert_eq!(keys, vec!["due", "priority", "status", "tags"]);
+
+        let status = facet(&facets, "status");
+        assert_eq!(status.notes, 3);
+        assert_eq!(
+            status.values,
+            vec![
+                ValueCount {
+                    value: json!("todo"),
+                    count: 2
+                },
+                ValueCount {
+                    value: json!("done"),
+                    count: 1
+                },
+            ]
+        );
+        let tags = facet(&facets, "tags");
+        assert_eq!(tags.notes, 3);
+        assert_eq!(tags.distinct, 2);
+        assert_eq!(tags.values[0].value, json!("work"));
+    }
+
+    #[test]
+    fn test_numeric_and_date_summaries() {
+        let notebook = sample();
+        let options = FacetOptions {
+            histogram_buckets: 3,
+            ..FacetOptions::default()
+        };
+        let facets = notebook.facets(&[], &options);
+
+        let numeric = facet(&facets, "priority").numeric.clone().unwrap();
+        assert_eq!((numeric.min, numeric.max), (1.0, 10.0));
+        let counts: Vec<usize> = numeric.histogram.iter().map(|b| b.count).collect();
+        assert_eq!(counts, vec![1, 1, 1]);
+        assert_eq!(numeric.histogram[2].end, 10.0);
+
+        let options = FacetOptions {
+            histogram_buckets: usize::MAX,
+            ..FacetOptions::default()
+        };
+        let facets = notebook.facets(&[], &options);
+        let numeric = facet(&facets, "priority").numeric.clone().unwrap();
+        assert_eq!(numeric.histogram.len(), MAX_HISTOGRAM_BUCKETS);
+
+        let dates = &facet(&facets, "due").dates;
+        assert_eq!(
+            dates,
+            &vec![
+                DateBucket {
+                    start: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+                    count: 2
+                },
+                DateBucket {
+                    start: NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+                    count: 1
+                },
+            ]
+        );
+        assert!(facet(&facets, "status").numeric.is_none());
+    }
+
+    #[test]
+    fn test_filtering_and_drill_down() {
+        let notebook = sample();
+        let titles = |notes: Vec<&Note>| {
+            let mut titles: Vec<String> = notes.iter().map(|n| n.title.clone()).collect();
+            titles.sort();
+            titles
+        };
+
+        let todo = FacetSelection::Values {
+            key: "status".into(),
+            values: vec![json!("todo")],
+        };
+        let high = FacetSelection::Range {
+            key: "priority".into(),
+            min: Some(5.0),
+            max: None,
+        };
+        assert_eq!(
+            titles(notebook.filter_by_facets(std::slice::from_ref(&todo))),
+            vec!["A", "B"]
+        );
+        assert_eq!(
+            titles(notebook.filter_by_facets(&[todo.clone(), high])),
+            vec!["B"]
+        );
+        assert_eq!(
+            titles(notebook.filter_by_facets(&[FacetSelection::Dates {
+                key: "due".into(),
+                start: NaiveDate::from_ymd_opt(2024, 1, 16),
+                end: NaiveDate::from_ymd_opt(2024, 12, 31),
+            }])),
+            vec!["B", "C"]
+        );
+        assert_eq!(
+            titles(notebook.filter_by_facets(&[FacetSelection::Missing {
+                key: "status".into()
+            }])),
+            vec!["Bare"]
+        );
+
+        // Facets over the selected notes only
+        let facets = notebook.facets(&[todo], &FacetOptions::default());
+        assert_eq!(facet(&facets, "status").distinct, 1);
+    }
+
+    #[test]
+    fn test_grouping() {
+        let notebook = sample();
+        let groups = notebook.group_by_attribute("tags");
+        let values: Vec<Option<Value>> = groups.iter().map(|g| g.value.clone()).collect();
+        assert_eq!(
+            values,
+            vec![Some(json!("urgent")), Some(json!("work")), None]
+        );
+        assert_eq!(groups[1].notes.len(), 2);
+        // The note with an empty tag list and the one without tags
+        assert_eq!(groups[2].notes.len(), 2);
+
+        let groups = notebook.group_by_attribute("priority");
+        let values: Vec<Option<Value>> = groups.iter().map(|g| g.value.clone()).collect();
+        assert_eq!(
+            values,
+            vec![Some(json!(1)), Some(json!(5)), Some(json!(10)), None]
+        );
+    }
+}
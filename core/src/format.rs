@@ -0,0 +1,172 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! File format - version number and migrations for saved notebooks
+//!
+//! Saved notebooks carry a top-level `format_version`. Loading reads the
+//! document as plain JSON first and runs it through one migration per
+//! version it is behind, each upgrading version `n` to `n + 1`, before
+//! deserializing the result. Files written before versioning count as
+//! version 0. Files from a newer version than this build understands are
+//! refused rather than half-read.
+//!
+//! Changing the saved form of `Notebook` or `Note` in a way older files do
+//! not already satisfy means bumping [`FORMAT_VERSION`], appending a
+//! migration and adding a fixture of the previous version to the tests.
+//! Migrations also upgrade the entries of journals left by older builds
+//! (see [`crate::journal`]), each as a document holding only the notes or
+//! field the entry changes, so they must leave missing fields alone.
+
+use crate::notebook::Notebook;
+use crate::storage::StorageError;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Version of the format written by this build
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Name of the top-level version field
+pub const VERSION_FIELD: &str = "format_version";
+
+/// An upgrade of a document from one version to the next
+type Migration = fn(&mut Map<String, Value>) -> Result<(), StorageError>;
+
+/// Migrations in order; `MIGRATIONS[n]` upgrades version `n` to `n + 1`
+const MIGRATIONS: [Migration; FORMAT_VERSION as usize] = [v0_to_v1];
+
+/// Version 1 adds the version field itself; unversioned files already have
+/// everything else version 1 needs
+fn v0_to_v1(_document: &mut Map<String, Value>) -> Result<(), StorageError> {
+    Ok(())
+}
+
+/// A notebook as saved, with its format version first
+#[derive(Serialize)]
+pub struct Versioned<'a> {
+    format_version: u32,
+    #[serde(flatten)]
+    notebook: &'a Notebook,
+}
+
+impl<'a> Versioned<'a> {
+    pub fn new(notebook: &'a Notebook) -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            notebook,
+        }
+    }
+}
+
+/// Format version of a saved document
+pub fn document_version(document: &Value) -> Result<u32, StorageError> {
+    let object = document
+        .as_object()
+        .ok_or_else(|| StorageError::InvalidFormat("expected a JSON object".into()))?;
+    match object.get(VERSION_FIELD) {
+        None => Ok(0),
+        Some(version) => version
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| {
+                StorageError::InvalidFormat(format!("invalid {VERSION_FIELD}: {version}"))
+            }),
+    }
+}
+
+/// Upgrade a saved document to the current format version
+pub fn migrate(mut document: Value) -> Result<Value, StorageError> {
+    let version = document_version(&document)?;
+    if version > FORMAT_VERSION {
+        return Err(StorageError::UnsupportedVersion {
+            found: version,
+            supported: FORMAT_VERSION,
+        });
+    }
+    let Some(object) = document.as_object_mut() else {
+        return Err(StorageError::InvalidFormat("expected a JSON object".into()));
+    };
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        migration(object)?;
+        object.insert(VERSION_FIELD.into(), Value::from(from as u32 + 1));
+    }
+    Ok(document)
+}
+
+/// Read a notebook from a saved document of any supported version
+pub fn from_document(document: Value) -> Result<Notebook, StorageError> {
+    Ok(serde_json::from_value(migrate(document)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::note::NoteId;
+    use std::str::FromStr;
+
+    /// Saved notebooks from each historical version of the format
+    const FIXTURES: [(u32, &str); 3] = [
+        (0, include_str!("../tests/fixtures/format-v0-baseline.json")),
+        (0, include_str!("../tests/fixtures/format-v0.json")),
+        (1, include_str!("../tests/fixtures/format-v1.json")),
+    ];
+
+    fn id(text: &str) -> NoteId {
+        NoteId::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn test_fixtures_load() {
+        for (version, json) in FIXTURES {
+            let document: Value = serde_json::from_str(json).unwrap();
+            assert_eq!(document_version(&document).unwrap(), version);
+            assert_eq!(
+                document_version(&migrate(document.clone()).unwrap()).unwrap(),
+                FORMAT_VERSION
+            );
+
+            let notebook = from_document(document).unwrap();
+            assert_eq!(notebook.name, "Fixture");
+            let plan = notebook
+                .get_note(&id("6d2c8f1e-3a47-4b8e-9a51-0c7e5f2d9b13"))
+                .unwrap();
+            assert_eq!(plan.title, "Project plan");
+            assert!(plan.links_to(&id("b4e1a9c2-7f35-4d60-8e2b-91c3d5a7f048")));
+            assert_eq!(
+                notebook
+                    .get_backlinks(&id("b4e1a9c2-7f35-4d60-8e2b-91c3d5a7f048"))
+                    .len(),
+                1
+            );
+            assert_eq!(notebook.search("milestones").len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_round_trip_is_current_version() {
+        let (_, json) = FIXTURES[FIXTURES.len() - 1];
+        let notebook = from_document(serde_json::from_str(json).unwrap()).unwrap();
+        let saved = serde_json::to_string_pretty(&Versioned::new(&notebook)).unwrap();
+        assert!(saved.starts_with(&format!("{{\n  \"{VERSION_FIELD}\": {FORMAT_VERSION},")));
+        let document: Value = serde_json::from_str(&saved).unwrap();
+        assert_eq!(document_version(&document).unwrap(), FORMAT_VERSION);
+        assert_eq!(from_document(document).unwrap().len(), notebook.len());
+    }
+
+    #[test]
+    fn test_newer_and_invalid_versions_are_refused() {
+        let newer = serde_json::json!({ "format_version": FORMAT_VERSION + 1, "notes": {} });
+        assert!(matches!(
+            from_document(newer),
+            Err(StorageError::UnsupportedVersion { found, supported })
+                if found == FORMAT_VERSION + 1 && supported == FORMAT_VERSION
+        ));
+        for invalid in [
+            serde_json::json!({ "format_version": "1" }),
+            serde_json::json!({ "format_version": -1 }),
+            serde_json::json!([]),
+        ] {
+            assert!(matches!(
+                migrate(invalid),
+                Err(StorageError::InvalidFormat(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,433 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Fuzzy matching - typo-tolerant title matching for a quick switcher
+//!
+//! A pattern matches a title when its characters appear in the title in
+//! order, ignoring case and whitespace in the pattern, so "prj pln" finds
+//! "Project Plan". Matches score higher for hitting word starts and runs
+//! of consecutive characters and lower for gaps. Patterns that are not a
+//! subsequence still match, at a penalty, if dropping a single character
+//! makes them one, which covers most one-letter typos and transpositions.
+//! Recently modified notes get a boost. Diacritics can be ignored, as in
+//! the notebook's other searches.
+
+use crate::analysis;
+use crate::note::{Note, NoteId};
+use serde::{Deserialize, Serialize};
+
+/// Score for each matched character
+const SCORE_MATCH: i32 = 16;
+
+/// Bonus for matching the first character of a word
+const BONUS_BOUNDARY: i32 = 10;
+
+/// Bonus for matching the character right after the previous match
+const BONUS_CONSECUTIVE: i32 = 8;
+
+/// Penalty per unmatched title character between two matches
+const PENALTY_GAP: i32 = 1;
+
+/// Multiplier for matches that needed a typo
+const TYPO_FACTOR: f64 = 0.5;
+
+/// Shortest pattern, in characters, for which typos are tolerated
+const MIN_TYPO_PATTERN: usize = 3;
+
+/// Options for the quick switcher
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FuzzyOptions {
+    /// Maximum number of matches returned
+    pub limit: usize,
+
+    /// How much recency can raise a score: a note modified just now scores
+    /// up to `1 + recency_weight` times one not touched in a long time
+    pub recency_weight: f64,
+
+    /// Age in days after which the recency boost has halved
+    pub recency_half_life_days: f64,
+
+    /// Whether patterns with a one-character typo still match
+    pub typo_tolerance: bool,
+}
+
+impl Default for FuzzyOptions {
+    fn default() -> Self {
+        Self {
+            limit: 20,
+            recency_weight: 0.25,
+            recency_half_life_days: 14.0,
+            typo_tolerance: true,
+        }
+    }
+}
+
+/// How a pattern matched a piece of text
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuzzyScore {
+    /// Match quality; higher is better
+    pub score: f64,
+
+    /// Char offsets of the matched characters in the text
+    pub positions: Vec<usize>,
+
+    /// Whether a pattern character had to be dropped to match
+    pub typo: bool,
+}
+
+/// One quick-switcher result
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FuzzyMatch {
+    /// The matching note
+    pub id: NoteId,
+
+    /// The note's title
+    pub title: String,
+
+    /// Match quality including recency; higher is better
+    pub score: f64,
+
+    /// Char offsets of the matched characters in `title`
+    pub positions: Vec<usize>,
+}
+
+/// Lowercase a character, keeping one char per char so positions line up
+fn fold(c: char) -> char {
+    if c.is_ascii() {
+        c.to_ascii_lowercase()
+    } else {
+        c.to_lowercase().next().unwrap_or(c)
+    }
+}
+
+/// Check if `pattern` is a subsequence of `text`
+fn is_subsequence(pattern: &[char], text: &[char]) -> bool {
+    let mut rest = text.iter();
+    pattern.iter().all(|p| rest.any(|t| t == p))
+}
+
+/// Reusable matcher for one pattern, keeping its buffers between texts so
+/// that scoring many titles does not allocate per title
+#[derive(Debug, Default)]
+pub struct FuzzyMatcher {
+    pattern: Vec<char>,
+    typo_tolerance: bool,
+    fold_diacritics: bool,
+    chars: Vec<char>,
+    folded: Vec<char>,
+    bonuses: Vec<i32>,
+    reduced: Vec<char>,
+    matched: Vec<i32>,
+    from: Vec<usize>,
+}
+
+impl FuzzyMatcher {
+    pub fn new(pattern: &str, typo_tolerance: bool) -> Self {
+        Self {
+            pattern: pattern
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .map(fold)
+                .collect(),
+            typo_tolerance,
+            ..Self::default()
+        }
+    }
+
+    /// Set whether diacritics are ignored, so "cafe" matches "Café"
+    pub fn with_diacritics_folded(mut self, fold_diacritics: bool) -> Self {
+        self.fold_diacritics = fold_diacritics;
+        if fold_diacritics {
+            for c in &mut self.pattern {
+                *c = analysis::fold_char(*c);
+            }
+        }
+        self
+    }
+
+    /// Fuzzy-match the pattern against text
+    ///
+    /// Returns None if the pattern does not match, even allowing for a
+    /// typo when tolerated. An empty pattern matches everything with score
+    /// zero.
+    pub fn score(&mut self, text: &str) -> Option<FuzzyScore> {
+        if self.pattern.is_empty() {
+            return Some(FuzzyScore {
+                score: 0.0,
+                positions: Vec::new(),
+                typo: false,
+            });
+        }
+
+        self.chars.clear();
+        self.chars.extend(text.chars());
+        self.folded.clear();
+        let fold = if self.fold_diacritics {
+            analysis::fold_char
+        } else {
+            fold
+        };
+        self.folded.extend(self.chars.iter().copied().map(fold));
+        // Shorter texts win ties between otherwise equal matches
+        let length_penalty = self.chars.len() as f64 / 100.0;
+
+        if is_subsequence(&self.pattern, &self.folded) {
+            self.compute_bonuses();
+            let pattern = std::mem::take(&mut self.pattern);
+            let (score, positions) = self.align(&pattern);
+            self.pattern = pattern;
+            return Some(FuzzyScore {
+                score: f64::from(score) - length_penalty,
+                positions,
+                typo: false,
+            });
+        }
+
+        if !self.typo_tolerance || self.pattern.len() < MIN_TYPO_PATTERN {
+            return None;
+        }
+        let mut best: Option<(i32, Vec<usize>)> = None;
+        let mut reduced = std::mem::take(&mut self.reduced);
+        for skip in 0..self.pattern.len() {
+            reduced.clear();
+            reduced.extend(
+                self.pattern
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| *i != skip)
+                    .map(|(_, c)| *c),
+            );
+            if is_subsequence(&reduced, &self.folded) {
+                if best.is_none() {
+                    self.compute_bonuses();
+                }
+                let (score, positions) = self.align(&reduced);
+                if best.as_ref().is_none_or(|(b, _)| score > *b) {
+                    best = Some((score, positions));
+                }
+            }
+        }
+        self.reduced = reduced;
+        best.map(|(score, positions)| FuzzyScore {
+            score: f64::from(score) * TYPO_FACTOR - length_penalty,
+            positions,
+            typo: true,
+        })
+    }
+
+    /// Bonus for matching at each text position
+    fn compute_bonuses(&mut self) {
+        self.bonuses.clear();
+        let mut previous: Option<char> = None;
+        for &c in &self.chars {
+            let boundary = match previous {
+                None => true,
+                Some(p) => !p.is_alphanumeric() || (p.is_lowercase() && c.is_uppercase()),
+            };
+            previous = Some(c);
+            self.bonuses.push(if boundary && c.is_alphanumeric() {
+                BONUS_BOUNDARY
+            } else {
+                0
+            });
+        }
+    }
+
+    /// Best alignment of a pattern that is known to be a subsequence of
+    /// the folded text
+    fn align(&mut self, pattern: &[char]) -> (i32, Vec<usize>) {
+        const NONE: i32 = i32::MIN / 2;
+        let n = self.folded.len();
+        let m = pattern.len();
+
+        // matched[i * n + j]: best score with pattern[i] matched at text[j]
+        // from[i * n + j]: where pattern[i - 1] was matched on that path
+        self.matched.clear();
+        self.matched.resize(m * n, NONE);
+        self.from.clear();
+        self.from.resize(m * n, 0);
+        let matched = &mut self.matched;
+
+        for i in 0..m {
+            // Best earlier match of pattern[i - 1] to continue from after
+            // a gap; gap cost grows by one per character, so the best start
+            // is the one maximising score + position whatever j is
+            let mut best_previous: Option<usize> = None;
+            for j in i..n {
+                if i > 0 {
+                    let p = j - 1;
+                    let previous = matched[(i - 1) * n + p];
+                    if previous > NONE
+                        && best_previous.is_none_or(|b| {
+                            previous + p as i32 >= matched[(i - 1) * n + b] + b as i32
+                        })
+                    {
+                        best_previous = Some(p);
+                    }
+                }
+                if self.folded[j] != pattern[i] {
+                    continue;
+                }
+
+                let score = SCORE_MATCH + self.bonuses[j];
+                if i == 0 {
+                    matched[j] = score;
+                    continue;
+                }
+                let consecutive = matched[(i - 1) * n + j - 1] + BONUS_CONSECUTIVE;
+                let gapped = best_previous.map_or(NONE, |p| {
+                    matched[(i - 1) * n + p] - PENALTY_GAP * (j - 1 - p) as i32
+                });
+                if consecutive > NONE && consecutive >= gapped {
+                    matched[i * n + j] = score + consecutive;
+                    self.from[i * n + j] = j - 1;
+                } else if let Some(p) = best_previous.filter(|_| gapped > NONE) {
+                    matched[i * n + j] = score + gapped;
+                    self.from[i * n + j] = p;
+                }
+            }
+        }
+
+        let last = &matched[(m - 1) * n..];
+        let mut j = (0..n)
+            .max_by_key(|&j| (last[j], std::cmp::Reverse(j)))
+            .unwrap_or(0);
+        let score = last[j];
+        let mut positions = vec![0; m];
+        for i in (0..m).rev() {
+            positions[i] = j;
+            if i > 0 {
+                j = self.from[i * n + j];
+            }
+        }
+        (score, positions)
+    }
+}
+
+/// Fuzzy-match a pattern against text
+///
+/// Convenience wrapper around [`FuzzyMatcher`] for a single text.
+pub fn fuzzy_score(pattern: &str, text: &str, typo_tolerance: bool) -> Option<FuzzyScore> {
+    FuzzyMatcher::new(pattern, typo_tolerance).score(text)
+}
+
+/// Rank notes by how well their titles fuzzy-match the pattern, boosted by
+/// how recently they were modified
+///
+/// An empty pattern lists the most recently modified notes.
+pub fn quick_switch<'a>(
+    notes: impl IntoIterator<Item = &'a Note>,
+    pattern: &str,
+    options: &FuzzyOptions,
+    fold_diacritics: bool,
+) -> Vec<FuzzyMatch> {
+    let now = chrono::Utc::now();
+    let half_life = options.recency_half_life_days.max(f64::EPSILON);
+    let mut matcher =
+        FuzzyMatcher::new(pattern, options.typo_tolerance).with_diacritics_folded(fold_diacritics);
+    let mut scored: Vec<(f64, Vec<usize>, &Note)> = notes
+        .into_iter()
+        .filter_map(|note| {
+            let found = matcher.score(&note.title)?;
+            let age_days = (now - note.modified_at).num_seconds().max(0) as f64 / 86_400.0;
+            let recency = 0.5f64.powf(age_days / half_life);
+            let score = found.score.max(1.0) * (1.0 + options.recency_weight * recency);
+            Some((score, found.positions, note))
+        })
+        .collect();
+
+    let order = |a: &(f64, Vec<usize>, &Note), b: &(f64, Vec<usize>, &Note)| {
+        b.0.total_cmp(&a.0)
+            .then_with(|| a.2.title.cmp(&b.2.title))
+            .then_with(|| a.2.id.cmp(&b.2.id))
+    };
+    if scored.len() > options.limit && options.limit > 0 {
+        scored.select_nth_unstable_by(options.limit - 1, order);
+    }
+    scored.truncate(options.limit);
+    scored.sort_by(order);
+    scored
+        .into_iter()
+        .map(|(score, positions, note)| FuzzyMatch {
+            id: note.id,
+            title: note.title.clone(),
+            score,
+            positions,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notebook::Notebook;
+
+    #[test]
+    fn test_abbreviation_matches() {
+        let found = fuzzy_score("prj pln", "Project Plan", true).unwrap();
+        assert!(!found.typo);
+        assert_eq!(found.positions, vec![0, 1, 3, 8, 9, 11]);
+        assert!(fuzzy_score("xyz", "Project Plan", false).is_none());
+    }
+
+    #[test]
+    fn test_word_starts_score_higher() {
+        let initials = fuzzy_score("pp", "Project Plan", false).unwrap();
+        let inside = fuzzy_score("pp", "Apple pie", false).unwrap();
+        assert!(initials.score > inside.score);
+        assert_eq!(initials.positions, vec![0, 8]);
+
+        let camel = fuzzy_score("nb", "NoteBook", false).unwrap();
+        assert_eq!(camel.positions, vec![0, 4]);
+    }
+
+    #[test]
+    fn test_consecutive_run_preferred() {
+        let found = fuzzy_score("plan", "Planning plan", false).unwrap();
+        assert_eq!(found.positions, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_typo_tolerance() {
+        // Transposed letters
+        let found = fuzzy_score("porject", "Project Plan", true).unwrap();
+        assert!(found.typo);
+        let exact = fuzzy_score("project", "Project Plan", true).unwrap();
+        assert!(exact.score > found.score);
+
+        // Wrong letter
+        assert!(fuzzy_score("meetimg", "Meeting Notes", true).is_some());
+        assert!(fuzzy_score("meetimg", "Meeting Notes", false).is_none());
+        // Too short to guess at
+        assert!(fuzzy_score("qz", "Meeting Notes", true).is_none());
+    }
+
+    #[test]
+    fn test_diacritics_folded() {
+        let mut matcher = FuzzyMatcher::new("cafe", false).with_diacritics_folded(true);
+        assert_eq!(
+            matcher.score("Café Ölmühle").unwrap().positions,
+            vec![0, 1, 2, 3]
+        );
+        let mut matcher = FuzzyMatcher::new("olm", false).with_diacritics_folded(true);
+        assert!(matcher.score("Café Ölmühle").is_some());
+        assert!(fuzzy_score("cafe", "Café", false).is_none());
+    }
+
+    #[test]
+    fn test_quick_switch_ranking_and_recency() {
+        let mut notebook = Notebook::new("Test");
+        let plan = notebook.create_note("Project Plan");
+        notebook.create_note("Shopping list");
+        let mut old = Note::new("Project Plans archive");
+        old.modified_at = chrono::Utc::now() - chrono::Duration::days(365);
+        let old = notebook.add_note(old);
+
+        let results = notebook.quick_switch("prj pln", &FuzzyOptions::default());
+        let ids: Vec<NoteId> = results.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![plan, old]);
+
+        let recent = notebook.quick_switch("", &FuzzyOptions::default());
+        assert_eq!(recent.len(), 3);
+        assert_eq!(recent.last().unwrap().id, old);
+    }
+}
@@ -0,0 +1,812 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Graph interchange - GraphML, GEXF and Graphviz DOT
+//!
+//! Exports the note graph for analysis in tools such as Gephi, yEd and
+//! Graphviz, and imports GraphML files back into a notebook.
+
+use crate::note::{Note, NoteId, Point2D};
+use crate::notebook::{Notebook, NotebookError};
+use chrono::{DateTime, Utc};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use thiserror::Error;
+use uuid::Uuid;
+
+/// Errors that can occur while importing a graph file
+#[derive(Debug, Error)]
+pub enum GraphIoError {
+    #[error("XML error: {0}")]
+    Xml(#[from] quick_xml::Error),
+
+    #[error("Malformed graph file: {0}")]
+    Malformed(String),
+
+    #[error("Edge refers to unknown node: {0}")]
+    UnknownNode(String),
+
+    #[error(transparent)]
+    Notebook(#[from] NotebookError),
+}
+
+/// Prefix used for custom note attributes in exported key names
+const ATTR_PREFIX: &str = "attr.";
+
+/// Attribute value type, as declared by GraphML and GEXF
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AttrType {
+    Boolean,
+    Long,
+    Double,
+    String,
+}
+
+impl AttrType {
+    fn of(value: &Value) -> Self {
+        match value {
+            Value::Bool(_) => AttrType::Boolean,
+            Value::Number(n) if n.is_i64() || n.is_u64() => AttrType::Long,
+            Value::Number(_) => AttrType::Double,
+            _ => AttrType::String,
+        }
+    }
+
+    /// Widen two types so a single declaration covers both
+    fn merge(self, other: Self) -> Self {
+        use AttrType::*;
+        match (self, other) {
+            (a, b) if a == b => a,
+            (Long, Double) | (Double, Long) => Double,
+            _ => String,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            AttrType::Boolean => "boolean",
+            AttrType::Long => "long",
+            AttrType::Double => "double",
+            AttrType::String => "string",
+        }
+    }
+
+    fn parse(name: &str) -> Self {
+        match name {
+            "boolean" => AttrType::Boolean,
+            "int" | "long" | "integer" => AttrType::Long,
+            "float" | "double" => AttrType::Double,
+            _ => AttrType::String,
+        }
+    }
+}
+
+/// Notes in a stable order, so exports are reproducible
+fn sorted_notes(notebook: &Notebook) -> Vec<&Note> {
+    let mut notes: Vec<&Note> = notebook.all_notes().collect();
+    notes.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+    notes
+}
+
+/// Collect custom attribute keys and their widest value type
+fn attribute_schema(notes: &[&Note]) -> BTreeMap<String, AttrType> {
+    let mut schema: BTreeMap<String, AttrType> = BTreeMap::new();
+    for note in notes {
+        for (key, value) in &note.attributes {
+            let ty = AttrType::of(value);
+            schema
+                .entry(key.clone())
+                .and_modify(|existing| *existing = existing.merge(ty))
+                .or_insert(ty);
+        }
+    }
+    schema
+}
+
+/// Render an attribute value as text
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Escape text for use in XML content or attribute values
+pub(crate) fn xml_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            // Control characters other than tab/newline are not valid XML 1.0
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Escape text for use inside a double-quoted DOT string
+fn dot_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Export the notebook as GraphML
+///
+/// Built-in fields use the keys `title`, `content`, `x`, `y`, `width`,
+/// `height`, `created_at`, `modified_at` and `prototype`; custom attributes
+/// are declared as `attr.<name>`. Non-scalar attribute values are written
+/// as JSON strings.
+pub fn to_graphml(notebook: &Notebook) -> String {
+    let notes = sorted_notes(notebook);
+    let schema = attribute_schema(&notes);
+    let mut out = String::new();
+
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(
+        "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\" \
+         xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" \
+         xsi:schemaLocation=\"http://graphml.graphdrawing.org/xmlns \
+         http://graphml.graphdrawing.org/xmlns/1.0/graphml.xsd\">\n",
+    );
+
+    let builtin = [
+        ("title", "string"),
+        ("content", "string"),
+        ("x", "double"),
+        ("y", "double"),
+        ("width", "double"),
+        ("height", "double"),
+        ("created_at", "string"),
+        ("modified_at", "string"),
+        ("prototype", "string"),
+    ];
+    for (name, ty) in builtin {
+        let _ = writeln!(
+            out,
+            "  <key id=\"{name}\" for=\"node\" attr.name=\"{name}\" attr.type=\"{ty}\"/>"
+        );
+    }
+    for (name, ty) in &schema {
+        // Prefixed so a custom attribute can never shadow a built-in key
+        let id = xml_escape(&format!("{ATTR_PREFIX}{name}"));
+        let _ = writeln!(
+            out,
+            "  <key id=\"{id}\" for=\"node\" attr.name=\"{id}\" attr.type=\"{}\"/>",
+            ty.name()
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "  <graph id=\"{}\" edgedefault=\"directed\">",
+        xml_escape(&notebook.name)
+    );
+
+    for note in &notes {
+        let _ = writeln!(out, "    <node id=\"{}\">", note.id);
+        let mut data = |key: &str, value: &str| {
+            let _ = writeln!(
+                out,
+                "      <data key=\"{}\">{}</data>",
+                xml_escape(key),
+                xml_escape(value)
+            );
+        };
+        data("title", &note.title);
+        if !note.content.is_empty() {
+            data("content", &note.content);
+        }
+        if let Some(pos) = note.position {
+            data("x", &pos.x.to_string());
+            data("y", &pos.y.to_string());
+        }
+        if let Some((w, h)) = note.size {
+            data("width", &w.to_string());
+            data("height", &h.to_string());
+        }
+        data("created_at", &note.created_at.to_rfc3339());
+        data("modified_at", &note.modified_at.to_rfc3339());
+        if let Some(proto) = note.prototype {
+            data("prototype", &proto.to_string());
+        }
+        let attrs: BTreeMap<_, _> = note.attributes.iter().collect();
+        for (key, value) in attrs {
+            data(&format!("{ATTR_PREFIX}{key}"), &value_text(value));
+        }
+        out.push_str("    </node>\n");
+    }
+
+    let mut edge = 0;
+    for note in &notes {
+        for target in &note.links {
+            let _ = writeln!(
+                out,
+                "    <edge id=\"e{edge}\" source=\"{}\" target=\"{target}\"/>",
+                note.id
+            );
+            edge += 1;
+        }
+    }
+
+    out.push_str("  </graph>\n</graphml>\n");
+    out
+}
+
+/// Export the notebook as GEXF 1.3
+///
+/// Canvas positions and sizes are written with the `viz` extension so
+/// Gephi opens the graph with the same layout as the canvas.
+pub fn to_gexf(notebook: &Notebook) -> String {
+    let notes = sorted_notes(notebook);
+    let schema = attribute_schema(&notes);
+    let mut out = String::new();
+
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(
+        "<gexf xmlns=\"http://gexf.net/1.3\" \
+         xmlns:viz=\"http://gexf.net/1.3/viz\" version=\"1.3\">\n",
+    );
+    let _ = writeln!(
+        out,
+        "  <meta lastmodifieddate=\"{}\">\n    <creator>Nexia {}</creator>\n    \
+         <description>{}</description>\n  </meta>",
+        notebook.modified_at.format("%Y-%m-%d"),
+        crate::VERSION,
+        xml_escape(&notebook.name)
+    );
+    out.push_str("  <graph defaultedgetype=\"directed\" mode=\"static\">\n");
+
+    out.push_str("    <attributes class=\"node\">\n");
+    let builtin = [
+        ("content", "string"),
+        ("created_at", "string"),
+        ("modified_at", "string"),
+        ("prototype", "string"),
+    ];
+    for (name, ty) in builtin {
+        let _ = writeln!(
+            out,
+            "      <attribute id=\"{name}\" title=\"{name}\" type=\"{ty}\"/>"
+        );
+    }
+    for (name, ty) in &schema {
+        let _ = writeln!(
+            out,
+            "      <attribute id=\"{}\" title=\"{}\" type=\"{}\"/>",
+            xml_escape(&format!("{ATTR_PREFIX}{name}")),
+            xml_escape(name),
+            ty.name()
+        );
+    }
+    out.push_str("    </attributes>\n");
+
+    out.push_str("    <nodes>\n");
+    for note in &notes {
+        let _ = writeln!(
+            out,
+            "      <node id=\"{}\" label=\"{}\">",
+            note.id,
+            xml_escape(&note.title)
+        );
+        out.push_str("        <attvalues>\n");
+        let mut attvalue = |key: &str, value: &str| {
+            let _ = writeln!(
+                out,
+                "          <attvalue for=\"{}\" value=\"{}\"/>",
+                xml_escape(key),
+                xml_escape(value)
+            );
+        };
+        if !note.content.is_empty() {
+            attvalue("content", &note.content);
+        }
+        attvalue("created_at", &note.created_at.to_rfc3339());
+        attvalue("modified_at", &note.modified_at.to_rfc3339());
+        if let Some(proto) = note.prototype {
+            attvalue("prototype", &proto.to_string());
+        }
+        let attrs: BTreeMap<_, _> = note.attributes.iter().collect();
+        for (key, value) in attrs {
+            attvalue(&format!("{ATTR_PREFIX}{key}"), &value_text(value));
+        }
+        out.push_str("        </attvalues>\n");
+        if let Some(pos) = note.position {
+            let _ = writeln!(
+                out,
+                "        <viz:position x=\"{}\" y=\"{}\" z=\"0\"/>",
+                pos.x, pos.y
+            );
+        }
+        if let Some((w, h)) = note.size {
+            let _ = writeln!(out, "        <viz:size value=\"{}\"/>", w.max(h));
+        }
+        out.push_str("      </node>\n");
+    }
+    out.push_str("    </nodes>\n");
+
+    out.push_str("    <edges>\n");
+    let mut edge = 0;
+    for note in &notes {
+        for target in &note.links {
+            let _ = writeln!(
+                out,
+                "      <edge id=\"e{edge}\" source=\"{}\" target=\"{target}\"/>",
+                note.id
+            );
+            edge += 1;
+        }
+    }
+    out.push_str("    </edges>\n");
+
+    out.push_str("  </graph>\n</gexf>\n");
+    out
+}
+
+/// Export the notebook as a Graphviz DOT digraph
+///
+/// Canvas positions are emitted as pinned `pos` attributes in points so
+/// `neato -n` reproduces the canvas layout; custom attributes are written
+/// as quoted `attr.<name>` attributes, which Graphviz ignores when drawing.
+pub fn to_dot(notebook: &Notebook) -> String {
+    let notes = sorted_notes(notebook);
+    let mut out = String::new();
+
+    let _ = writeln!(out, "digraph \"{}\" {{", dot_escape(&notebook.name));
+    out.push_str("  node [shape=box];\n");
+
+    for note in &notes {
+        let mut attrs = vec![format!("label=\"{}\"", dot_escape(&note.title))];
+        if let Some(pos) = note.position {
+            // DOT's y axis points up, the canvas y axis points down
+            attrs.push(format!("pos=\"{},{}!\"", pos.x, -pos.y));
+        }
+        if let Some((w, h)) = note.size {
+            // Graphviz sizes are in inches at 72 points per inch
+            attrs.push(format!("width={}", w / 72.0));
+            attrs.push(format!("height={}", h / 72.0));
+            attrs.push("fixedsize=true".to_string());
+        }
+        if let Some(proto) = note.prototype {
+            attrs.push(format!("prototype=\"{proto}\""));
+        }
+        let custom: BTreeMap<_, _> = note.attributes.iter().collect();
+        for (key, value) in custom {
+            attrs.push(format!(
+                "\"{}\"=\"{}\"",
+                dot_escape(&format!("{ATTR_PREFIX}{key}")),
+                dot_escape(&value_text(value))
+            ));
+        }
+        let _ = writeln!(out, "  \"{}\" [{}];", note.id, attrs.join(", "));
+    }
+
+    for note in &notes {
+        for target in &note.links {
+            let _ = writeln!(out, "  \"{}\" -> \"{target}\";", note.id);
+        }
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+/// A `<key>` declaration from a GraphML file
+struct KeyDecl {
+    name: String,
+    ty: AttrType,
+    default: Option<String>,
+    /// Whether the key applies to nodes, rather than only to edges or
+    /// graphs
+    for_nodes: bool,
+}
+
+/// A node as read from a GraphML file, before it becomes a note
+#[derive(Default)]
+struct NodeDecl {
+    id: String,
+    data: HashMap<String, String>,
+}
+
+/// Where character data currently being read belongs
+enum TextTarget {
+    None,
+    KeyDefault(String),
+    NodeData(String),
+}
+
+fn attr_value(start: &BytesStart, name: &[u8]) -> Result<Option<String>, GraphIoError> {
+    for attr in start.attributes() {
+        let attr = attr.map_err(|e| GraphIoError::Malformed(e.to_string()))?;
+        if attr.key.local_name().as_ref() == name {
+            return Ok(Some(attr.unescape_value()?.into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+fn required_attr(start: &BytesStart, name: &[u8]) -> Result<String, GraphIoError> {
+    attr_value(start, name)?.ok_or_else(|| {
+        GraphIoError::Malformed(format!(
+            "<{}> is missing the {} attribute",
+            String::from_utf8_lossy(start.local_name().as_ref()),
+            String::from_utf8_lossy(name)
+        ))
+    })
+}
+
+/// Convert GraphML text to a JSON attribute value of the declared type
+fn typed_value(text: &str, ty: AttrType) -> Value {
+    let parsed = match ty {
+        AttrType::Boolean => text.trim().parse::<bool>().ok().map(Value::Bool),
+        AttrType::Long => text.trim().parse::<i64>().ok().map(Value::from),
+        AttrType::Double => text.trim().parse::<f64>().ok().map(Value::from),
+        AttrType::String => None,
+    };
+    parsed.unwrap_or_else(|| Value::String(text.to_string()))
+}
+
+fn parse_f64(text: Option<&String>) -> Option<f64> {
+    text.and_then(|t| t.trim().parse::<f64>().ok()).filter(|v| v.is_finite())
+}
+
+fn parse_time(text: Option<&String>) -> Option<DateTime<Utc>> {
+    text.and_then(|t| DateTime::parse_from_rfc3339(t.trim()).ok())
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// Import a GraphML document into the notebook
+///
+/// Every node becomes a new note and every edge a link. Node IDs that are
+/// UUIDs not already used in the notebook are kept, so a file produced by
+/// [`to_graphml`] round-trips with the same note IDs. Keys named `title`
+/// or `label` set the title, `content` or `description` the content, and
+/// `x`/`y`/`width`/`height` the canvas geometry; other keys become
+/// attributes typed according to their `attr.type`. Defaults of keys
+/// declared for edges or graphs are not applied to notes.
+///
+/// If an edge names a node that isn't in the file, nothing is imported.
+///
+/// Returns the IDs of the created notes in document order.
+pub fn import_graphml(notebook: &mut Notebook, xml: &str) -> Result<Vec<NoteId>, GraphIoError> {
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+
+    let mut keys: HashMap<String, KeyDecl> = HashMap::new();
+    let mut nodes: Vec<NodeDecl> = Vec::new();
+    let mut edges: Vec<(String, String)> = Vec::new();
+    let mut current: Option<NodeDecl> = None;
+    let mut target = TextTarget::None;
+    let mut saw_graphml = false;
+
+    loop {
+        let event = reader.read_event()?;
+        let empty = matches!(event, Event::Empty(_));
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) => match e.local_name().as_ref() {
+                b"graphml" => saw_graphml = true,
+                b"key" => {
+                    let id = required_attr(e, b"id")?;
+                    let name = attr_value(e, b"attr.name")?.unwrap_or_else(|| id.clone());
+                    let ty = attr_value(e, b"attr.type")?
+                        .map(|t| AttrType::parse(&t))
+                        .unwrap_or(AttrType::String);
+                    let for_nodes = attr_value(e, b"for")?
+                        .is_none_or(|domain| matches!(domain.as_str(), "node" | "all"));
+                    keys.insert(
+                        id.clone(),
+                        KeyDecl {
+                            name,
+                            ty,
+                            default: None,
+                            for_nodes,
+                        },
+                    );
+                    if !empty {
+                        target = TextTarget::KeyDefault(id);
+                    }
+                }
+                b"node" => {
+                    let node = NodeDecl {
+                        id: required_attr(e, b"id")?,
+                        data: HashMap::new(),
+                    };
+                    if empty {
+                        nodes.push(node);
+                    } else {
+                        current = Some(node);
+                    }
+                }
+                b"data" if current.is_some() && !empty => {
+                    target = TextTarget::NodeData(required_attr(e, b"key")?);
+                }
+                b"edge" => {
+                    edges.push((required_attr(e, b"source")?, required_attr(e, b"target")?));
+                }
+                _ => {}
+            },
+            Event::Text(t) => {
+                let text = t.unescape()?.into_owned();
+                match &target {
+                    TextTarget::KeyDefault(id) => {
+                        if let Some(key) = keys.get_mut(id) {
+                            key.default = Some(text);
+                        }
+                    }
+                    TextTarget::NodeData(key) => {
+                        if let Some(node) = current.as_mut() {
+                            node.data.insert(key.clone(), text);
+                        }
+                    }
+                    TextTarget::None => {}
+                }
+            }
+            Event::CData(t) => {
+                if let (TextTarget::NodeData(key), Some(node)) = (&target, current.as_mut()) {
+                    node.data
+                        .insert(key.clone(), String::from_utf8_lossy(&t).into_owned());
+                }
+            }
+            Event::End(ref e) => match e.local_name().as_ref() {
+                b"key" | b"data" => target = TextTarget::None,
+                b"node" => {
+                    if let Some(node) = current.take() {
+                        nodes.push(node);
+                    }
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    if !saw_graphml {
+        return Err(GraphIoError::Malformed(
+            "missing <graphml> root element".into(),
+        ));
+    }
+
+    // Check edges before adding anything, so a bad file leaves the
+    // notebook unchanged
+    let node_ids: HashSet<&str> = nodes.iter().map(|node| node.id.as_str()).collect();
+    for (source, target) in &edges {
+        for end in [source, target] {
+            if !node_ids.contains(end.as_str()) {
+                return Err(GraphIoError::UnknownNode(end.clone()));
+            }
+        }
+    }
+
+    // Resolve key IDs to attribute names and apply key defaults
+    let mut ids: HashMap<String, NoteId> = HashMap::new();
+    let mut created = Vec::with_capacity(nodes.len());
+    let mut prototypes: Vec<(NoteId, String)> = Vec::new();
+
+    for node in nodes {
+        let mut fields: HashMap<String, (String, AttrType)> = HashMap::new();
+        for (key_id, key) in &keys {
+            if let Some(default) = key.default.as_ref().filter(|_| key.for_nodes) {
+                fields.insert(key.name.clone(), (default.clone(), key.ty));
+            }
+            if let Some(value) = node.data.get(key_id) {
+                fields.insert(key.name.clone(), (value.clone(), key.ty));
+            }
+        }
+        // Data elements that reference undeclared keys are kept as strings
+        for (key_id, value) in &node.data {
+            if !keys.contains_key(key_id) {
+                fields.insert(key_id.clone(), (value.clone(), AttrType::String));
+            }
+        }
+
+        let take = |fields: &mut HashMap<String, (String, AttrType)>, names: &[&str]| {
+            names.iter().find_map(|n| fields.remove(*n)).map(|(v, _)| v)
+        };
+
+        let title = take(&mut fields, &["title", "label"]).unwrap_or_else(|| node.id.clone());
+        let mut note = Note::new(title);
+        if let Some(id) = Uuid::parse_str(&node.id)
+            .ok()
+            .filter(|id| notebook.get_note(id).is_none() && !ids.values().any(|v| v == id))
+        {
+            note.id = id;
+        }
+        if let Some(content) = take(&mut fields, &["content", "description"]) {
+            note.content = content;
+        }
+        let x = parse_f64(take(&mut fields, &["x"]).as_ref());
+        let y = parse_f64(take(&mut fields, &["y"]).as_ref());
+        if let (Some(x), Some(y)) = (x, y) {
+            note.position = Some(Point2D::new(x, y));
+        }
+        let w = parse_f64(take(&mut fields, &["width"]).as_ref());
+        let h = parse_f64(take(&mut fields, &["height"]).as_ref());
+        if let (Some(w), Some(h)) = (w, h) {
+            note.size = Some((w, h));
+        }
+        if let Some(created_at) = parse_time(take(&mut fields, &["created_at"]).as_ref()) {
+            note.created_at = created_at;
+        }
+        if let Some(modified_at) = parse_time(take(&mut fields, &["modified_at"]).as_ref()) {
+            note.modified_at = modified_at;
+        }
+        if let Some(proto) = take(&mut fields, &["prototype"]) {
+            prototypes.push((note.id, proto));
+        }
+        for (name, (value, ty)) in fields {
+            let name = name.strip_prefix(ATTR_PREFIX).unwrap_or(&name).to_string();
+            note.attributes.insert(name, typed_value(&value, ty));
+        }
+
+        ids.insert(node.id, note.id);
+        created.push(notebook.add_note(note));
+    }
+
+    for (source, target) in edges {
+        notebook.link_notes(ids[&source], ids[&target])?;
+    }
+
+    // Prototypes may name a node from the file or an existing note
+    for (note_id, proto) in prototypes {
+        let proto_id = ids
+            .get(&proto)
+            .copied()
+            .or_else(|| Uuid::parse_str(&proto).ok())
+            .filter(|id| notebook.get_note(id).is_some())
+            .filter(|id| !notebook.prototype_chain_contains(id, &note_id));
+        if let (Some(proto_id), Some(note)) = (proto_id, notebook.get_note_mut(&note_id)) {
+            note.prototype = Some(proto_id);
+        }
+    }
+
+    Ok(created)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn sample() -> (Notebook, NoteId, NoteId) {
+        let mut notebook = Notebook::new("Sample & Co");
+        let mut a = Note::new("Alpha <one>").with_position(10.0, 20.0);
+        a.content = "First \"note\"".into();
+        a.size = Some((200.0, 100.0));
+        a.attributes.insert("status".into(), json!("done"));
+        a.attributes.insert("priority".into(), json!(3));
+        let b = Note::new("Beta");
+        let a = notebook.add_note(a);
+        let b = notebook.add_note(b);
+        notebook.link_notes(a, b).unwrap();
+        (notebook, a, b)
+    }
+
+    #[test]
+    fn test_graphml_round_trip() {
+        let (notebook, a, b) = sample();
+        let xml = to_graphml(&notebook);
+
+        let mut imported = Notebook::new("Imported");
+        let ids = import_graphml(&mut imported, &xml).unwrap();
+        assert_eq!(ids.len(), 2);
+
+        let alpha = imported.get_note(&a).unwrap();
+        assert_eq!(alpha.title, "Alpha <one>");
+        assert_eq!(alpha.content, "First \"note\"");
+        assert_eq!(alpha.position, Some(Point2D::new(10.0, 20.0)));
+        assert_eq!(alpha.size, Some((200.0, 100.0)));
+        assert_eq!(alpha.get_attribute("status"), Some(&json!("done")));
+        assert_eq!(alpha.get_attribute("priority"), Some(&json!(3)));
+        assert!(alpha.links_to(&b));
+        assert_eq!(imported.get_backlinks(&b), vec![a]);
+    }
+
+    #[test]
+    fn test_import_foreign_graphml() {
+        let xml = r#"<?xml version="1.0"?>
+            <graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+              <key id="d0" for="node" attr.name="label" attr.type="string"/>
+              <key id="d1" for="node" attr.name="weight" attr.type="double">
+                <default>1.5</default>
+              </key>
+              <key id="d2" for="edge" attr.name="color" attr.type="string">
+                <default>black</default>
+              </key>
+              <graph edgedefault="directed">
+                <node id="n0"><data key="d0">Root</data></node>
+                <node id="n1"><data key="d1">2.0</data></node>
+                <edge source="n0" target="n1"/>
+              </graph>
+            </graphml>"#;
+
+        let mut notebook = Notebook::new("Test");
+        let ids = import_graphml(&mut notebook, xml).unwrap();
+
+        let root = notebook.get_note(&ids[0]).unwrap();
+        assert_eq!(root.title, "Root");
+        assert_eq!(root.get_attribute("weight"), Some(&json!(1.5)));
+        assert!(root.links_to(&ids[1]));
+
+        // Nodes without a label fall back to their file ID
+        let other = notebook.get_note(&ids[1]).unwrap();
+        assert_eq!(other.title, "n1");
+        assert_eq!(other.get_attribute("weight"), Some(&json!(2.0)));
+
+        // Defaults of edge keys don't apply to notes
+        assert_eq!(root.get_attribute("color"), None);
+    }
+
+    #[test]
+    fn test_import_unknown_edge_endpoint() {
+        let xml =
+            r#"<graphml><graph><node id="a"/><edge source="a" target="b"/></graph></graphml>"#;
+        let mut notebook = Notebook::new("Test");
+        let result = import_graphml(&mut notebook, xml);
+        assert!(matches!(result, Err(GraphIoError::UnknownNode(id)) if id == "b"));
+        assert!(notebook.is_empty());
+    }
+
+    #[test]
+    fn test_import_skips_bad_positions_and_prototype_cycles() {
+        let xml = r#"<graphml>
+              <key id="x" for="node" attr.name="x" attr.type="double"/>
+              <key id="y" for="node" attr.name="y" attr.type="double"/>
+              <key id="p" for="node" attr.name="prototype" attr.type="string"/>
+              <graph>
+                <node id="a"><data key="x">NaN</data><data key="y">1</data>
+                  <data key="p">b</data></node>
+                <node id="b"><data key="x">inf</data><data key="y">1</data>
+                  <data key="p">a</data></node>
+                <node id="c"><data key="x">1</data><data key="y">2</data>
+                  <data key="p">c</data></node>
+              </graph>
+            </graphml>"#;
+        let mut notebook = Notebook::new("Test");
+        let ids = import_graphml(&mut notebook, xml).unwrap();
+        let [a, b, c] = [ids[0], ids[1], ids[2]];
+
+        assert_eq!(notebook.get_note(&a).unwrap().position, None);
+        assert_eq!(notebook.get_note(&b).unwrap().position, None);
+        assert_eq!(notebook.get_note(&c).unwrap().position, Some(Point2D::new(1.0, 2.0)));
+
+        // Only the first of a→b, b→a is kept, and c can't be its own prototype
+        assert_eq!(notebook.get_note(&a).unwrap().prototype, Some(b));
+        assert_eq!(notebook.get_note(&b).unwrap().prototype, None);
+        assert_eq!(notebook.get_note(&c).unwrap().prototype, None);
+        assert!(serde_json::to_string(&notebook).is_ok());
+    }
+
+    #[test]
+    fn test_gexf_positions() {
+        let (notebook, a, _) = sample();
+        let gexf = to_gexf(&notebook);
+        assert!(gexf.contains(&format!("<node id=\"{a}\" label=\"Alpha &lt;one&gt;\">")));
+        assert!(gexf.contains("<viz:position x=\"10\" y=\"20\" z=\"0\"/>"));
+        assert!(gexf.contains("<attvalue for=\"attr.priority\" value=\"3\"/>"));
+    }
+
+    #[test]
+    fn test_dot_output() {
+        let (notebook, a, b) = sample();
+        let dot = to_dot(&notebook);
+        assert!(dot.starts_with("digraph \"Sample & Co\" {"));
+        assert!(dot.contains("label=\"Alpha <one>\""));
+        assert!(dot.contains("pos=\"10,-20!\""));
+        assert!(dot.contains(&format!("\"{a}\" -> \"{b}\";")));
+    }
+}
@@ -0,0 +1,552 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Journal - write-ahead log of changes made since a notebook was saved
+//!
+//! A journal sits next to a saved notebook and holds, one JSON line each,
+//! the changes made to it since: the full new state of each note added or
+//! changed, the ids of notes removed, and the new value of each other saved
+//! field that changed. Changes are appended and flushed to disk as they are
+//! recorded, so a crash loses at most the change being written. Loading
+//! replays the journal on top of the saved notebook; saving writes the
+//! replayed changes into the notebook file and starts an empty journal.
+//!
+//! Entries carry whole values rather than operations, so replaying one
+//! twice does no harm - a crash between saving the notebook and emptying
+//! its journal only replays changes the file already has. A last line cut
+//! short by a crash is ignored, as is anything after a damaged line, and
+//! dropped when recording resumes so that new changes follow the last
+//! whole line. Journals left by a build writing an older format version
+//! are upgraded as they are read.
+
+use crate::format::{self, Versioned, FORMAT_VERSION, VERSION_FIELD};
+use crate::note::{Note, NoteId};
+use crate::notebook::Notebook;
+use crate::storage::{self, StorageError};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// One line of a journal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Entry {
+    /// First line, naming the format the entries are written in
+    Begin { format_version: u32 },
+
+    /// A note was added or changed
+    PutNote { note: Note },
+
+    /// A note was removed
+    RemoveNote { id: NoteId },
+
+    /// A saved field other than notes changed
+    Set { key: String, value: Value },
+
+    /// A saved field other than notes was dropped from the file
+    Unset { key: String },
+}
+
+/// A notebook with its journal replayed
+#[derive(Debug)]
+pub struct Replayed {
+    pub notebook: Notebook,
+
+    /// Number of changes replayed
+    pub entries: usize,
+}
+
+/// An open journal recording changes to a notebook
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+
+    /// The saved fields other than notes as last recorded
+    manifest: Map<String, Value>,
+
+    /// Number of changes in the journal
+    entries: usize,
+}
+
+impl Journal {
+    /// Path of the journal kept next to a notebook file or folder
+    ///
+    /// The suffix is added to the whole file name, so notebooks differing
+    /// only in extension have journals of their own.
+    pub fn path_for(notebook_path: &Path) -> PathBuf {
+        let mut name = notebook_path.file_name().unwrap_or_default().to_os_string();
+        name.push(".journal");
+        notebook_path.with_file_name(name)
+    }
+
+    /// Start an empty journal for a notebook that has just been saved,
+    /// replacing any earlier one
+    pub fn create(path: impl Into<PathBuf>, notebook: &mut Notebook) -> Result<Self, StorageError> {
+        let path = path.into();
+        let mut file = File::create(&path)?;
+        append(
+            &mut file,
+            &[Entry::Begin {
+                format_version: FORMAT_VERSION,
+            }],
+        )?;
+        notebook.take_changed_notes();
+        Ok(Self {
+            path,
+            file,
+            manifest: notebook.manifest()?,
+            entries: 0,
+        })
+    }
+
+    /// Go on recording to the journal of a notebook that has just been
+    /// loaded and had the journal replayed, keeping the changes in it
+    pub fn resume(path: impl Into<PathBuf>, notebook: &mut Notebook) -> Result<Self, StorageError> {
+        let path = path.into();
+        let entries = Self::read(&path)?;
+        // An empty journal may be missing or cut short before its first
+        // line, so it is started afresh
+        if entries.is_empty() {
+            return Self::create(path, notebook);
+        }
+        // Rewrite the journal without any damaged tail, which would
+        // otherwise hide every change appended after it
+        let mut lines = lines(&[Entry::Begin {
+            format_version: FORMAT_VERSION,
+        }])?;
+        lines.extend(self::lines(&entries)?);
+        storage::write_atomic(&path, &lines)?;
+
+        let file = OpenOptions::new().append(true).open(&path)?;
+        notebook.take_changed_notes();
+        Ok(Self {
+            path,
+            file,
+            manifest: notebook.manifest()?,
+            entries: entries.len(),
+        })
+    }
+
+    /// Path of the journal file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of changes in the journal
+    pub fn len(&self) -> usize {
+        self.entries
+    }
+
+    /// Check if the journal has no changes
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0
+    }
+
+    /// Append the changes made since the last call, returning how many
+    /// there were
+    ///
+    /// If writing fails, the changes are kept for the next call.
+    pub fn record(&mut self, notebook: &mut Notebook) -> Result<usize, StorageError> {
+        let mut changed = notebook.changed_notes();
+        changed.sort();
+        let mut entries: Vec<Entry> = changed
+            .into_iter()
+            .map(|id| match notebook.get_note(&id) {
+                Some(note) => Entry::PutNote { note: note.clone() },
+                None => Entry::RemoveNote { id },
+            })
+            .collect();
+
+        let manifest = notebook.manifest()?;
+        for (key, value) in &manifest {
+            if self.manifest.get(key) != Some(value) {
+                entries.push(Entry::Set {
+                    key: key.clone(),
+                    value: value.clone(),
+                });
+            }
+        }
+        for key in self.manifest.keys() {
+            if !manifest.contains_key(key) {
+                entries.push(Entry::Unset { key: key.clone() });
+            }
+        }
+
+        append(&mut self.file, &entries)?;
+        notebook.take_changed_notes();
+        self.manifest = manifest;
+        self.entries += entries.len();
+        Ok(entries.len())
+    }
+
+    /// Read a journal
+    ///
+    /// A missing journal has no entries. Entries written in an older
+    /// format version are upgraded by the format's migrations; a journal
+    /// from a newer version is refused like a newer notebook file.
+    pub fn read(path: &Path) -> Result<Vec<Entry>, StorageError> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut lines = text.lines().map(serde_json::from_str::<Value>);
+        let version = match lines.next() {
+            None | Some(Err(_)) => return Ok(Vec::new()),
+            Some(Ok(first)) => match serde_json::from_value(first) {
+                Ok(RawEntry::Begin { format_version }) => format_version,
+                _ => {
+                    return Err(StorageError::InvalidFormat(
+                        "journal does not start with a begin entry".into(),
+                    ))
+                }
+            },
+        };
+        if version > FORMAT_VERSION {
+            return Err(StorageError::UnsupportedVersion {
+                found: version,
+                supported: FORMAT_VERSION,
+            });
+        }
+        Ok(lines
+            .map_while(|line| upgrade(line.ok()?, version).ok())
+            .collect())
+    }
+
+    /// Apply a journal to the notebook it was recorded against
+    ///
+    /// State that is not saved, such as a custom embedder or loaded
+    /// similarity vectors, is not kept when there are changes to apply.
+    pub fn replay(path: &Path, notebook: Notebook) -> Result<Replayed, StorageError> {
+        let entries = Self::read(path)?;
+        if entries.is_empty() {
+            return Ok(Replayed {
+                notebook,
+                entries: 0,
+            });
+        }
+
+        let mut document = match serde_json::to_value(Versioned::new(&notebook))? {
+            Value::Object(document) => document,
+            _ => Map::new(),
+        };
+        let mut notes = match document.remove("notes") {
+            Some(Value::Object(notes)) => notes,
+            _ => Map::new(),
+        };
+        for entry in &entries {
+            match entry {
+                Entry::Begin { .. } => {}
+                Entry::PutNote { note } => {
+                    notes.insert(note.id.to_string(), serde_json::to_value(note)?);
+                }
+                Entry::RemoveNote { id } => {
+                    notes.remove(&id.to_string());
+                }
+                Entry::Set { key, value } => {
+                    document.insert(key.clone(), value.clone());
+                }
+                Entry::Unset { key } => {
+                    document.remove(key);
+                }
+            }
+        }
+
+        // Links are replayed with their notes; backlinks follow from them
+        let mut backlinks: HashMap<NoteId, HashSet<NoteId>> = HashMap::new();
+        for note in notes.values() {
+            let note: Note = serde_json::from_value(note.clone())?;
+            for target in note.links {
+                backlinks.entry(target).or_default().insert(note.id);
+            }
+        }
+        document.insert("notes".into(), Value::Object(notes));
+        document.insert("backlinks".into(), serde_json::to_value(&backlinks)?);
+        Ok(Replayed {
+            notebook: format::from_document(Value::Object(document))?,
+            entries: entries.len(),
+        })
+    }
+}
+
+/// A journal line with notes and field values left as plain JSON
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum RawEntry {
+    Begin { format_version: u32 },
+    PutNote { note: Value },
+    RemoveNote { id: NoteId },
+    Set { key: String, value: Value },
+    Unset { key: String },
+}
+
+/// Read an entry written in a format version, running what an older one
+/// changes through the migrations as a document of its own
+fn upgrade(entry: Value, version: u32) -> Result<Entry, StorageError> {
+    if version == FORMAT_VERSION {
+        return Ok(serde_json::from_value(entry)?);
+    }
+    let migrate = |key: &str, value: Value| {
+        let mut document = Map::new();
+        document.insert(VERSION_FIELD.into(), Value::from(version));
+        document.insert(key.to_string(), value);
+        match format::migrate(Value::Object(document))? {
+            Value::Object(document) => Ok::<_, StorageError>(document),
+            _ => Ok(Map::new()),
+        }
+    };
+    Ok(match serde_json::from_value(entry)? {
+        RawEntry::Begin { .. } => {
+            return Err(StorageError::InvalidFormat(
+                "begin entry inside a journal".into(),
+            ))
+        }
+        RawEntry::PutNote { note } => {
+            let id = note["id"].as_str().unwrap_or_default().to_string();
+            let notes = Map::from_iter([(id.clone(), note)]);
+            let mut document = migrate("notes", Value::Object(notes))?;
+            let note = document
+                .get_mut("notes")
+                .and_then(|notes| notes.get_mut(&id))
+                .map(Value::take)
+                .unwrap_or_default();
+            Entry::PutNote {
+                note: serde_json::from_value(note)?,
+            }
+        }
+        RawEntry::RemoveNote { id } => Entry::RemoveNote { id },
+        RawEntry::Set { key, value } => match migrate(&key, value)?.remove(&key) {
+            Some(value) => Entry::Set { key, value },
+            None => Entry::Unset { key },
+        },
+        RawEntry::Unset { key } => Entry::Unset { key },
+    })
+}
+
+/// Entries as JSON lines
+fn lines(entries: &[Entry]) -> Result<Vec<u8>, StorageError> {
+    let mut lines = Vec::new();
+    for entry in entries {
+        serde_json::to_writer(&mut lines, entry)?;
+        lines.push(b'\n');
+    }
+    Ok(lines)
+}
+
+/// Write entries as lines and flush them to disk
+///
+/// A failed write is cut back off, so the journal never ends in part of
+/// a line that later entries would follow.
+fn append(file: &mut File, entries: &[Entry]) -> Result<(), StorageError> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let lines = lines(entries)?;
+    let end = file.metadata()?.len();
+    if let Err(e) = file.write_all(&lines).and_then(|_| file.sync_data()) {
+        let _ = file.set_len(end);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{JsonStorage, Storage};
+    use crate::testing::saved_form;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_unsaved_changes_survive_a_crash() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.nexia.json");
+        let storage = JsonStorage::new();
+
+        let mut notebook = Notebook::new("Journaled");
+        let plan = notebook.create_note("Plan");
+        let budget = notebook.create_note("Budget");
+        let draft = notebook.create_note("Draft");
+        notebook.link_notes(plan, draft).unwrap();
+        storage.save(&notebook, &path).unwrap();
+        let mut journal = Journal::create(Journal::path_for(&path), &mut notebook).unwrap();
+        assert_eq!(journal.record(&mut notebook).unwrap(), 0);
+
+        notebook.get_note_mut(&plan).unwrap().content = "Milestones".into();
+        notebook.link_notes(plan, budget).unwrap();
+        assert!(journal.record(&mut notebook).unwrap() > 0);
+        let added = notebook.create_note("Added");
+        notebook
+            .get_note_mut(&added)
+            .unwrap()
+            .set_attribute("status", json!("new"));
+        notebook.remove_note(&draft);
+        notebook.name = "Renamed".into();
+        notebook.create_map("Second");
+        journal.record(&mut notebook).unwrap();
+        assert_eq!(journal.len(), 8);
+
+        // The process dies here; the file still holds the first save
+        drop(journal);
+        let saved = storage.load(&path).unwrap();
+        assert_eq!(saved.name, "Journaled");
+        let replayed = Journal::replay(&Journal::path_for(&path), saved).unwrap();
+        assert_eq!(replayed.entries, 8);
+        assert_eq!(saved_form(&replayed.notebook), saved_form(&notebook));
+        assert_eq!(replayed.notebook.get_backlinks(&budget), vec![plan]);
+        assert_eq!(replayed.notebook.search("milestones").len(), 1);
+    }
+
+    #[test]
+    fn test_saving_compacts_the_journal() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.nexia.json");
+        let journal_path = Journal::path_for(&path);
+        let storage = JsonStorage::new();
+
+        let mut notebook = Notebook::new("Journaled");
+        storage.save(&notebook, &path).unwrap();
+        let mut journal = Journal::create(&journal_path, &mut notebook).unwrap();
+        notebook.create_note("First");
+        journal.record(&mut notebook).unwrap();
+
+        storage.save(&notebook, &path).unwrap();
+        let mut journal = Journal::create(&journal_path, &mut notebook).unwrap();
+        assert!(journal.is_empty());
+        assert!(Journal::read(&journal_path).unwrap().is_empty());
+
+        // Resuming after a load keeps what was there
+        notebook.create_note("Second");
+        journal.record(&mut notebook).unwrap();
+        let replayed = Journal::replay(&journal_path, storage.load(&path).unwrap()).unwrap();
+        let mut loaded = replayed.notebook;
+        let mut journal = Journal::resume(&journal_path, &mut loaded).unwrap();
+        assert_eq!(journal.len(), 2);
+        loaded.create_note("Third");
+        journal.record(&mut loaded).unwrap();
+        let replayed = Journal::replay(&journal_path, storage.load(&path).unwrap()).unwrap();
+        assert_eq!(replayed.notebook.len(), 3);
+
+        // Replaying changes the file already has does nothing
+        storage.save(&replayed.notebook, &path).unwrap();
+        let again = Journal::replay(&journal_path, storage.load(&path).unwrap()).unwrap();
+        assert_eq!(saved_form(&again.notebook), saved_form(&replayed.notebook));
+    }
+
+    #[test]
+    fn test_resuming_drops_a_torn_last_line() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.journal");
+        let mut notebook = Notebook::new("Journaled");
+        let mut journal = Journal::create(&path, &mut notebook).unwrap();
+        notebook.create_note("Before");
+        journal.record(&mut notebook).unwrap();
+
+        // A crash cut the next line short
+        let kept = fs::read_to_string(&path).unwrap();
+        fs::write(&path, format!("{kept}{{\"op\": \"put_no")).unwrap();
+
+        let base = Notebook::new("Journaled");
+        let mut loaded = Journal::replay(&path, base.clone()).unwrap().notebook;
+        let mut journal = Journal::resume(&path, &mut loaded).unwrap();
+        loaded.create_note("After");
+        journal.record(&mut loaded).unwrap();
+        assert_eq!(Journal::replay(&path, base).unwrap().notebook.len(), 2);
+    }
+
+    #[test]
+    fn test_failed_writes_are_retried() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.journal");
+        let mut notebook = Notebook::new("Journaled");
+        let mut journal = Journal::create(&path, &mut notebook).unwrap();
+
+        // A handle that can't be written to makes the write fail
+        let writable = std::mem::replace(&mut journal.file, File::open(&path).unwrap());
+        notebook.create_note("Pending");
+        notebook.name = "Renamed".into();
+        assert!(journal.record(&mut notebook).is_err());
+
+        journal.file = writable;
+        assert!(journal.record(&mut notebook).unwrap() > 0);
+        let replayed = Journal::replay(&path, Notebook::new("Journaled")).unwrap();
+        assert_eq!(replayed.notebook.name, "Renamed");
+        assert_eq!(replayed.notebook.len(), 1);
+    }
+
+    #[test]
+    fn test_journals_are_named_after_the_whole_file() {
+        assert_eq!(
+            Journal::path_for(Path::new("dir/notes.json")),
+            Path::new("dir/notes.json.journal")
+        );
+        assert_ne!(
+            Journal::path_for(Path::new("notes.json")),
+            Journal::path_for(Path::new("notes.nxb"))
+        );
+        assert_eq!(
+            Journal::path_for(Path::new("dir/notes")),
+            Path::new("dir/notes.journal")
+        );
+    }
+
+    #[test]
+    fn test_damaged_lines_end_the_journal() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.journal");
+        let mut notebook = Notebook::new("Journaled");
+        let mut journal = Journal::create(&path, &mut notebook).unwrap();
+        notebook.create_note("Kept");
+        journal.record(&mut notebook).unwrap();
+        let kept = fs::read_to_string(&path).unwrap();
+
+        fs::write(&path, format!("{kept}{{\"op\": \"put_no")).unwrap();
+        assert_eq!(Journal::read(&path).unwrap().len(), journal.len());
+        fs::write(&path, format!("{kept}garbage\n{kept}")).unwrap();
+        assert_eq!(Journal::read(&path).unwrap().len(), journal.len());
+
+        fs::write(&path, "{\"op\": \"begin\", \"format_version\": 999}\n").unwrap();
+        assert!(matches!(
+            Journal::read(&path),
+            Err(StorageError::UnsupportedVersion { found: 999, .. })
+        ));
+        assert!(Journal::read(&dir.path().join("missing.journal"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_older_journals_are_upgraded() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.nexia.json");
+        let storage = JsonStorage::new();
+        storage.save(&Notebook::new("Journaled"), &path).unwrap();
+
+        // A journal left by a build writing format version 0
+        let note = serde_json::to_value(Note::new("Unsaved")).unwrap();
+        let journal_path = Journal::path_for(&path);
+        let old = [
+            json!({ "op": "begin", "format_version": 0 }),
+            json!({ "op": "put_note", "note": note }),
+            json!({ "op": "set", "key": "name", "value": "Renamed" }),
+        ];
+        let text: Vec<String> = old.iter().map(Value::to_string).collect();
+        fs::write(&journal_path, text.join("\n") + "\n").unwrap();
+
+        let saved = storage.load(&path).unwrap();
+        let mut replayed = Journal::replay(&journal_path, saved).unwrap();
+        assert_eq!(replayed.entries, 2);
+        assert_eq!(replayed.notebook.name, "Renamed");
+        assert_eq!(replayed.notebook.search("unsaved").len(), 1);
+
+        // Resuming rewrites the journal in the current version
+        Journal::resume(&journal_path, &mut replayed.notebook).unwrap();
+        let first = fs::read_to_string(&journal_path).unwrap();
+        let first: Value = serde_json::from_str(first.lines().next().unwrap()).unwrap();
+        assert_eq!(first["format_version"], json!(FORMAT_VERSION));
+        assert_eq!(Journal::read(&journal_path).unwrap().len(), 2);
+    }
+}
@@ -4,11 +4,39 @@
 //! This crate provides the core data structures and operations for Nexia,
 //! a cross-platform personal knowledge management tool.
 
+pub mod adornment;
+pub mod agent;
+pub mod analysis;
+pub mod arrange;
+#[cfg(feature = "binary")]
+pub mod binary;
+pub mod canvas;
+pub mod embedding;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+pub mod facet;
+pub mod format;
+pub mod fuzzy;
+pub mod graph_io;
+pub mod journal;
+pub mod markdown;
 pub mod note;
 pub mod notebook;
+pub mod placement;
+pub mod query;
+pub mod replace;
+pub mod routing;
+pub mod search;
+pub mod spatial;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 pub mod storage;
+pub mod svg;
+#[cfg(test)]
+mod testing;
+pub mod text_index;
 
-pub use note::{Note, NoteId, Point2D};
+pub use note::{Note, NoteId, Point2D, Rect};
 pub use notebook::Notebook;
 pub use storage::Storage;
 
@@ -0,0 +1,742 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Markdown storage - a notebook as a folder of Markdown files
+//!
+//! Each note is saved as a `.md` file named after its title, with its id,
+//! canvas position, links, prototype, attributes and timestamps in YAML
+//! front matter and its content as the body. Everything else about the
+//! notebook (name, maps, adornments, agents, text analysis settings and
+//! the format version) goes in a small `notebook.yaml` manifest.
+//!
+//! The folder is meant to be edited by hand and by other tools, so loading
+//! is forgiving about what it finds:
+//!
+//! - A file without front matter is a note titled after the file name.
+//! - A missing id is derived from the file name, so it stays the same
+//!   from one load to the next; a duplicated id (a copied file) is
+//!   treated the same way.
+//! - Missing timestamps are taken from the file's own times.
+//! - Links and prototypes may name a note by id, title or file name, with
+//!   or without `[[...]]`; ones naming no note are dropped.
+//! - Front-matter keys other than the ones above are read as attributes.
+//! - A folder without a manifest is a notebook named after the folder.
+//!
+//! Fields that are present but unreadable are errors rather than being
+//! dropped. Only `.md` files directly in the folder are notes; hidden
+//! files and subfolders are left alone. Saving rewrites only files whose
+//! contents changed and removes the files of deleted or renamed notes; it
+//! only removes files the same storage loaded or saved, so files added to
+//! the folder in the meantime are kept. Each file is written atomically,
+//! but a save is not atomic as a whole.
+
+use crate::format::{self, Versioned, FORMAT_VERSION, VERSION_FIELD};
+use crate::note::{Note, NoteId, Point2D};
+use crate::notebook::Notebook;
+use crate::storage::{write_atomic, Storage, StorageError};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use serde_yaml::Mapping;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use uuid::Uuid;
+
+/// Name of the notebook manifest inside the folder
+pub const MANIFEST_FILE: &str = "notebook.yaml";
+
+/// Extension of note files
+const NOTE_EXTENSION: &str = "md";
+
+/// Longest file name, in characters, made from a title
+const MAX_STEM_CHARS: usize = 80;
+
+/// Front-matter keys with a meaning of their own; any other key is an
+/// attribute
+const KNOWN_KEYS: [&str; 9] = [
+    "id",
+    "title",
+    "position",
+    "size",
+    "created_at",
+    "modified_at",
+    "links",
+    "prototype",
+    "attributes",
+];
+
+/// Front matter as written for a note
+#[derive(Serialize)]
+struct FrontMatter<'a> {
+    id: NoteId,
+    title: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<Point2D>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<(f64, f64)>,
+    created_at: DateTime<Utc>,
+    modified_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    links: &'a Vec<NoteId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prototype: Option<NoteId>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    attributes: BTreeMap<&'a str, &'a Value>,
+}
+
+/// Storage as a folder of Markdown files with YAML front matter
+#[derive(Debug, Default)]
+pub struct MarkdownStorage {
+    /// Names of the note files last loaded from or saved to each folder
+    known: Mutex<HashMap<PathBuf, HashSet<String>>>,
+}
+
+impl MarkdownStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn remember(&self, dir: &Path, names: HashSet<String>) {
+        let key = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        let mut known = self.known.lock().unwrap_or_else(PoisonError::into_inner);
+        known.insert(key, names);
+    }
+
+    fn known(&self, dir: &Path) -> HashSet<String> {
+        let key = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        let known = self.known.lock().unwrap_or_else(PoisonError::into_inner);
+        known.get(&key).cloned().unwrap_or_default()
+    }
+}
+
+impl Storage for MarkdownStorage {
+    fn save(&self, notebook: &Notebook, path: &Path) -> Result<(), StorageError> {
+        fs::create_dir_all(path)?;
+
+        let mut manifest = serde_json::to_value(Versioned::new(notebook))?;
+        if let Some(object) = manifest.as_object_mut() {
+            object.remove("notes");
+            object.remove("backlinks");
+        }
+        write_if_changed(
+            &path.join(MANIFEST_FILE),
+            serde_yaml::to_string(&manifest)?.as_bytes(),
+        )?;
+
+        // Oldest notes claim their plain title first, so names stay put as
+        // notes are added
+        let mut notes: Vec<&Note> = notebook.all_notes().collect();
+        notes.sort_by_key(|note| (note.created_at, note.id));
+        let mut taken = HashSet::new();
+        let mut written = HashSet::new();
+        for note in notes {
+            let name = file_name(note, &mut taken);
+            write_if_changed(&path.join(&name), render(note)?.as_bytes())?;
+            written.insert(name);
+        }
+
+        // Remove files of notes that were deleted or renamed, and hand-made
+        // files whose notes have been written under their own name; files
+        // added since the notebook was loaded, such as copies or files
+        // brought in by a sync, are kept
+        let known = self.known(path);
+        for file in note_files(path)? {
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            if known.contains(name.as_ref()) && !written.contains(name.as_ref()) {
+                fs::remove_file(&file)?;
+            }
+        }
+        self.remember(path, written);
+        Ok(())
+    }
+
+    fn load(&self, path: &Path) -> Result<Notebook, StorageError> {
+        if !path.exists() {
+            return Err(StorageError::NotFound(path.display().to_string()));
+        }
+        if !path.is_dir() {
+            return Err(invalid(path, "not a folder"));
+        }
+
+        let mut document = read_manifest(path)?;
+        let names = note_files(path)?
+            .iter()
+            .map(|file| {
+                file.file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        let notes = read_notes(path)?;
+
+        let mut backlinks: HashMap<NoteId, HashSet<NoteId>> = HashMap::new();
+        for note in notes.values() {
+            for target in &note.links {
+                backlinks.entry(*target).or_default().insert(note.id);
+            }
+        }
+        document.insert("notes".into(), serde_json::to_value(&notes)?);
+        document.insert("backlinks".into(), serde_json::to_value(&backlinks)?);
+        let notebook = format::from_document(Value::Object(document))?;
+        self.remember(path, names);
+        Ok(notebook)
+    }
+}
+
+/// Read the manifest, filling in what a missing or hand-written one lacks
+fn read_manifest(dir: &Path) -> Result<Map<String, Value>, StorageError> {
+    let path = dir.join(MANIFEST_FILE);
+    let mut manifest = if path.exists() {
+        match serde_yaml::from_str(&fs::read_to_string(&path)?)? {
+            Value::Object(object) => object,
+            Value::Null => Map::new(),
+            _ => return Err(invalid(&path, "expected a mapping")),
+        }
+    } else {
+        // A plain folder of Markdown is read as the current version
+        let mut manifest = Map::new();
+        manifest.insert(VERSION_FIELD.into(), Value::from(FORMAT_VERSION));
+        manifest
+    };
+
+    if !manifest.contains_key("name") {
+        let name = dir
+            .canonicalize()
+            .ok()
+            .and_then(|dir| Some(dir.file_name()?.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "Untitled Notebook".into());
+        manifest.insert("name".into(), Value::from(name));
+    }
+    let now = serde_json::to_value(Utc::now())?;
+    for key in ["created_at", "modified_at"] {
+        manifest.entry(key).or_insert_with(|| now.clone());
+    }
+    Ok(manifest)
+}
+
+/// A note file as read, before its links are resolved
+struct NoteFile {
+    path: PathBuf,
+    front: Mapping,
+    body: String,
+}
+
+impl NoteFile {
+    fn read(path: &Path) -> Result<Self, StorageError> {
+        let text = fs::read_to_string(path)?;
+        let text = text.strip_prefix('\u{feff}').unwrap_or(&text);
+        let (front, body) = match split_front_matter(text) {
+            Some((yaml, body)) => {
+                let front = match serde_yaml::from_str(yaml)
+                    .map_err(|e| invalid(path, format!("front matter: {e}")))?
+                {
+                    serde_yaml::Value::Mapping(front) => front,
+                    serde_yaml::Value::Null => Mapping::new(),
+                    _ => return Err(invalid(path, "front matter is not a mapping")),
+                };
+                (front, body)
+            }
+            None => (Mapping::new(), text),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            front,
+            body: body.to_string(),
+        })
+    }
+
+    fn stem(&self) -> String {
+        self.path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn field<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, StorageError> {
+        match self.front.get(key) {
+            None | Some(serde_yaml::Value::Null) => Ok(None),
+            Some(value) => serde_yaml::from_value(value.clone())
+                .map(Some)
+                .map_err(|e| invalid(&self.path, format!("{key}: {e}"))),
+        }
+    }
+
+    fn explicit_id(&self) -> Result<Option<NoteId>, StorageError> {
+        self.field("id")
+    }
+
+    /// Id for a file without one of its own, stable across loads
+    fn derived_id(&self) -> NoteId {
+        let name = self.path.file_name().unwrap_or_default();
+        Uuid::new_v5(&Uuid::NAMESPACE_URL, name.to_string_lossy().as_bytes())
+    }
+
+    fn text(&self, key: &str) -> Result<Option<String>, StorageError> {
+        match self.front.get(key) {
+            None | Some(serde_yaml::Value::Null) => Ok(None),
+            Some(value) => scalar_text(value)
+                .map(Some)
+                .ok_or_else(|| invalid(&self.path, format!("{key}: expected text"))),
+        }
+    }
+
+    fn time(&self, key: &str) -> Result<Option<DateTime<Utc>>, StorageError> {
+        self.text(key)?
+            .map(|text| {
+                parse_time(&text)
+                    .ok_or_else(|| invalid(&self.path, format!("{key}: invalid time {text:?}")))
+            })
+            .transpose()
+    }
+
+    /// References in a field holding one reference or a list of them
+    fn references(&self, key: &str) -> Result<Vec<String>, StorageError> {
+        let values = match self.front.get(key) {
+            None | Some(serde_yaml::Value::Null) => return Ok(Vec::new()),
+            Some(serde_yaml::Value::Sequence(values)) => values.iter().collect(),
+            Some(value) => vec![value],
+        };
+        values
+            .into_iter()
+            .map(|value| {
+                scalar_text(value)
+                    .ok_or_else(|| invalid(&self.path, format!("{key}: expected note names")))
+            })
+            .collect()
+    }
+
+    /// The `attributes` mapping, plus any keys the format does not know
+    fn attributes(&self) -> Result<HashMap<String, Value>, StorageError> {
+        let mut attributes = HashMap::new();
+        let nested = match self.front.get("attributes") {
+            None | Some(serde_yaml::Value::Null) => None,
+            Some(serde_yaml::Value::Mapping(nested)) => Some(nested),
+            Some(_) => return Err(invalid(&self.path, "attributes: expected a mapping")),
+        };
+        let extra = self
+            .front
+            .iter()
+            .filter(|(key, _)| key.as_str().is_none_or(|key| !KNOWN_KEYS.contains(&key)));
+        // Entries under `attributes` win over loose keys of the same name
+        for (key, value) in extra.chain(nested.into_iter().flatten()) {
+            let key = scalar_text(key)
+                .ok_or_else(|| invalid(&self.path, "attribute names must be text"))?;
+            let value = serde_json::to_value(value)
+                .map_err(|e| invalid(&self.path, format!("attribute {key}: {e}")))?;
+            attributes.insert(key, value);
+        }
+        Ok(attributes)
+    }
+}
+
+/// Split a file into its front matter and body, if it has front matter
+fn split_front_matter(text: &str) -> Option<(&str, &str)> {
+    let rest = text
+        .strip_prefix("---\n")
+        .or_else(|| text.strip_prefix("---\r\n"))?;
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        let marker = line.trim_end_matches(['\n', '\r']);
+        if marker == "---" || marker == "..." {
+            return Some((&rest[..offset], &rest[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// Text of a YAML string, number or boolean
+fn scalar_text(value: &serde_yaml::Value) -> Option<String> {
+    match value {
+        serde_yaml::Value::String(text) => Some(text.clone()),
+        serde_yaml::Value::Number(number) => Some(number.to_string()),
+        serde_yaml::Value::Bool(flag) => Some(flag.to_string()),
+        _ => None,
+    }
+}
+
+/// Parse an RFC 3339 time, or a date or time without a zone taken as UTC
+fn parse_time(text: &str) -> Option<DateTime<Utc>> {
+    let text = text.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time.with_timezone(&Utc));
+    }
+    [
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%d %H:%M",
+    ]
+    .iter()
+    .find_map(|pattern| NaiveDateTime::parse_from_str(text, pattern).ok())
+    .or_else(|| {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d")
+            .ok()?
+            .and_hms_opt(0, 0, 0)
+    })
+    .map(|time| time.and_utc())
+}
+
+/// Markdown files directly inside a folder, in name order
+fn note_files(dir: &Path) -> Result<Vec<PathBuf>, StorageError> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && !path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+                && path
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case(NOTE_EXTENSION))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Read every note in a folder and resolve the links between them
+fn read_notes(dir: &Path) -> Result<HashMap<NoteId, Note>, StorageError> {
+    let mut files = Vec::new();
+    let mut ids = HashSet::new();
+    for path in note_files(dir)? {
+        let file = NoteFile::read(&path)?;
+        let id = [file.explicit_id()?, Some(file.derived_id())]
+            .into_iter()
+            .flatten()
+            .find(|id| !ids.contains(id))
+            .unwrap_or_else(Uuid::new_v4);
+        ids.insert(id);
+        files.push((id, file));
+    }
+
+    // Hand-written links may name a note instead of giving its id
+    let mut names: HashMap<String, NoteId> = HashMap::new();
+    for (id, file) in &files {
+        if let Some(title) = file.text("title")? {
+            names.entry(title.to_lowercase()).or_insert(*id);
+        }
+    }
+    for (id, file) in &files {
+        names.entry(file.stem().to_lowercase()).or_insert(*id);
+    }
+    let resolve = |reference: &str| -> Option<NoteId> {
+        let reference = reference.trim();
+        let reference = reference
+            .strip_prefix("[[")
+            .and_then(|inner| inner.strip_suffix("]]"))
+            .unwrap_or(reference);
+        match Uuid::parse_str(reference) {
+            Ok(id) => ids.contains(&id).then_some(id),
+            Err(_) => names.get(&reference.to_lowercase()).copied(),
+        }
+    };
+
+    let mut notes = HashMap::new();
+    for (id, file) in files {
+        let metadata = fs::metadata(&file.path)?;
+        let modified = metadata.modified().ok().map(DateTime::<Utc>::from);
+        let created = metadata.created().ok().map(DateTime::<Utc>::from);
+        let modified_at = file
+            .time("modified_at")?
+            .or(modified)
+            .unwrap_or_else(Utc::now);
+        let created_at = file.time("created_at")?.or(created).unwrap_or(modified_at);
+
+        let mut links = Vec::new();
+        for target in file.references("links")?.iter().filter_map(|r| resolve(r)) {
+            if !links.contains(&target) {
+                links.push(target);
+            }
+        }
+        let prototype = file
+            .references("prototype")?
+            .first()
+            .and_then(|reference| resolve(reference))
+            .filter(|prototype| *prototype != id);
+
+        let note = Note {
+            id,
+            title: file.text("title")?.unwrap_or_else(|| file.stem()),
+            content: file.body.clone(),
+            position: file.field("position")?,
+            size: file.field("size")?,
+            created_at,
+            modified_at,
+            links,
+            prototype,
+            attributes: file.attributes()?,
+        };
+        notes.insert(id, note);
+    }
+    Ok(notes)
+}
+
+/// Write a note as front matter followed by its content
+fn render(note: &Note) -> Result<String, StorageError> {
+    let front = FrontMatter {
+        id: note.id,
+        title: &note.title,
+        position: note.position,
+        size: note.size,
+        created_at: note.created_at,
+        modified_at: note.modified_at,
+        links: &note.links,
+        prototype: note.prototype,
+        attributes: note
+            .attributes
+            .iter()
+            .map(|(key, value)| (key.as_str(), value))
+            .collect(),
+    };
+    Ok(format!(
+        "---\n{}---\n{}",
+        serde_yaml::to_string(&front)?,
+        note.content
+    ))
+}
+
+/// File name for a note: its title where that is free, with part or all of
+/// its id added where not
+fn file_name(note: &Note, taken: &mut HashSet<String>) -> String {
+    let stem: String = note
+        .title
+        .chars()
+        .map(|c| {
+            if c.is_control() || r#"/\:*?"<>|"#.contains(c) {
+                '-'
+            } else {
+                c
+            }
+        })
+        .take(MAX_STEM_CHARS)
+        .collect();
+    let stem = stem.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    let stem = if stem.is_empty() { "Untitled" } else { stem };
+
+    let id = note.id.simple().to_string();
+    // Compared case-blind, as many file systems do
+    [
+        stem.to_string(),
+        format!("{stem}-{}", &id[..8]),
+        format!("{stem}-{id}"),
+    ]
+    .into_iter()
+    .map(|stem| format!("{stem}.{NOTE_EXTENSION}"))
+    .find(|name| taken.insert(name.to_lowercase()))
+    .unwrap_or_else(|| format!("{id}.{NOTE_EXTENSION}"))
+}
+
+/// Write a file unless it already holds exactly these contents
+fn write_if_changed(path: &Path, contents: &[u8]) -> Result<(), StorageError> {
+    if fs::read(path).is_ok_and(|current| current == contents) {
+        return Ok(());
+    }
+    write_atomic(path, contents)
+}
+
+fn invalid(path: &Path, reason: impl Display) -> StorageError {
+    StorageError::InvalidFormat(format!("{}: {reason}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{sample, saved_form};
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_round_trip_is_identical() {
+        let dir = tempdir().unwrap();
+        let (notebook, plan, budget, copy) = sample();
+        let storage = MarkdownStorage::new();
+        storage.save(&notebook, dir.path()).unwrap();
+
+        assert!(dir.path().join(MANIFEST_FILE).exists());
+        assert!(dir.path().join("Project plan.md").exists());
+        assert!(dir.path().join("Budget- 2024-25.md").exists());
+        assert_eq!(note_files(dir.path()).unwrap().len(), 3);
+
+        let loaded = storage.load(dir.path()).unwrap();
+        assert_eq!(saved_form(&loaded), saved_form(&notebook));
+        let mut backlinks = loaded.get_backlinks(&budget);
+        backlinks.sort();
+        let mut expected = vec![plan, copy];
+        expected.sort();
+        assert_eq!(backlinks, expected);
+        assert_eq!(loaded.search("milestones").len(), 1);
+
+        // Saving again leaves every file as it was
+        let before = fs::read_to_string(dir.path().join("Project plan.md")).unwrap();
+        storage.save(&loaded, dir.path()).unwrap();
+        let after = fs::read_to_string(dir.path().join("Project plan.md")).unwrap();
+        assert_eq!(before, after);
+        assert!(after.starts_with(&format!("---\nid: {plan}\ntitle: Project plan\n")));
+        assert!(after.ends_with("---\nMilestones\n---\nnot front matter"));
+    }
+
+    #[test]
+    fn test_deleted_and_renamed_notes_lose_their_files() {
+        let dir = tempdir().unwrap();
+        let (mut notebook, plan, budget, _) = sample();
+        let storage = MarkdownStorage::new();
+        storage.save(&notebook, dir.path()).unwrap();
+
+        notebook.remove_note(&budget);
+        notebook.get_note_mut(&plan).unwrap().title = "Roadmap".into();
+        storage.save(&notebook, dir.path()).unwrap();
+
+        let mut names: Vec<String> = note_files(dir.path())
+            .unwrap()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["Project plan.md", "Roadmap.md"]);
+        assert_eq!(
+            saved_form(&storage.load(dir.path()).unwrap()),
+            saved_form(&notebook)
+        );
+    }
+
+    #[test]
+    fn test_files_added_after_loading_are_kept() {
+        let dir = tempdir().unwrap();
+        let (notebook, _, budget, _) = sample();
+        MarkdownStorage::new().save(&notebook, dir.path()).unwrap();
+
+        let storage = MarkdownStorage::new();
+        let mut loaded = storage.load(dir.path()).unwrap();
+        // A note synced in from elsewhere, and a copy made by hand
+        let mut synced = Note::new("From laptop");
+        synced.content = "Synced".into();
+        fs::write(dir.path().join("From laptop.md"), render(&synced).unwrap()).unwrap();
+        fs::copy(
+            dir.path().join("Project plan.md"),
+            dir.path().join("Project plan (copy).md"),
+        )
+        .unwrap();
+
+        loaded.remove_note(&budget);
+        storage.save(&loaded, dir.path()).unwrap();
+        assert!(!dir.path().join("Budget- 2024-25.md").exists());
+        assert!(dir.path().join("From laptop.md").exists());
+        assert!(dir.path().join("Project plan (copy).md").exists());
+
+        // A storage that never saw the folder removes nothing
+        let mut fresh = storage.load(dir.path()).unwrap();
+        fresh.remove_note(&synced.id);
+        MarkdownStorage::new().save(&fresh, dir.path()).unwrap();
+        assert!(dir.path().join("From laptop.md").exists());
+    }
+
+    #[test]
+    fn test_hand_written_files_are_tolerated() {
+        let dir = tempdir().unwrap();
+        let (notebook, plan, _, _) = sample();
+        let storage = MarkdownStorage::new();
+        storage.save(&notebook, dir.path()).unwrap();
+
+        fs::write(dir.path().join("Scratch.md"), "Just some thoughts\n").unwrap();
+        fs::write(
+            dir.path().join("Meeting.md"),
+            "---\ntitle: 1984\ncreated_at: 2024-03-01\nlinks: [\"[[Scratch]]\", project plan, \
+             Nowhere]\ntags: [team]\nattributes:\n  tags: [minutes]\n---\nAgenda\n",
+        )
+        .unwrap();
+        // A copy of a saved note keeps the original's id in its front matter
+        fs::copy(
+            dir.path().join("Project plan.md"),
+            dir.path().join("Project plan copy.md"),
+        )
+        .unwrap();
+        fs::write(dir.path().join(".hidden.md"), "ignored").unwrap();
+
+        let loaded = storage.load(dir.path()).unwrap();
+        assert_eq!(loaded.len(), 6);
+        let find = |title: &str| {
+            loaded
+                .all_notes()
+                .find(|note| note.title == title)
+                .unwrap()
+                .clone()
+        };
+        let scratch = find("Scratch");
+        assert_eq!(scratch.content, "Just some thoughts\n");
+
+        let meeting = find("1984");
+        assert_eq!(meeting.content, "Agenda\n");
+        assert_eq!(
+            meeting.created_at,
+            parse_time("2024-03-01T00:00:00Z").unwrap()
+        );
+        assert_eq!(meeting.links, vec![scratch.id, plan]);
+        assert_eq!(meeting.attributes["tags"], json!(["minutes"]));
+        assert_eq!(loaded.get_backlinks(&scratch.id), vec![meeting.id]);
+
+        // The copy sorts first and keeps the id; the original gets its own
+        assert_eq!(loaded.get_note(&plan).unwrap().title, "Project plan");
+        assert_eq!(
+            loaded
+                .all_notes()
+                .filter(|note| note.content.starts_with("Milestones"))
+                .count(),
+            2
+        );
+
+        // Ids of hand-made files stay the same until they are saved
+        let again = storage.load(dir.path()).unwrap();
+        assert_eq!(saved_form(&again), saved_form(&loaded));
+
+        // Once saved, hand-made files are replaced by the notes' own
+        storage.save(&loaded, dir.path()).unwrap();
+        assert!(!dir.path().join("Meeting.md").exists());
+        assert!(dir.path().join("1984.md").exists());
+        assert!(dir.path().join(".hidden.md").exists());
+        assert_eq!(note_files(dir.path()).unwrap().len(), 6);
+        assert_eq!(
+            saved_form(&storage.load(dir.path()).unwrap()),
+            saved_form(&loaded)
+        );
+    }
+
+    #[test]
+    fn test_folder_without_manifest() {
+        let dir = tempdir().unwrap();
+        let folder = dir.path().join("Journal");
+        fs::create_dir(&folder).unwrap();
+        fs::write(folder.join("Monday.md"), "---\n---\nQuiet day").unwrap();
+
+        let loaded = MarkdownStorage::new().load(&folder).unwrap();
+        assert_eq!(loaded.name, "Journal");
+        assert_eq!(loaded.search("quiet").len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_fields_are_errors() {
+        let dir = tempdir().unwrap();
+        let storage = MarkdownStorage::new();
+        for front in [
+            "id: 12",
+            "position: here",
+            "created_at: someday",
+            "- a list",
+        ] {
+            fs::write(dir.path().join("Bad.md"), format!("---\n{front}\n---\n")).unwrap();
+            let result = storage.load(dir.path());
+            assert!(
+                matches!(&result, Err(StorageError::InvalidFormat(e)) if e.contains("Bad.md")),
+                "{front}: {result:?}"
+            );
+        }
+        assert!(matches!(
+            storage.load(&dir.path().join("missing")),
+            Err(StorageError::NotFound(_))
+        ));
+    }
+}
@@ -32,6 +32,67 @@ impl Default for Point2D {
     }
 }
 
+/// Axis-aligned rectangle on the spatial canvas
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Rect {
+    pub fn new(x: f64, y: f64, width: f64, height: f64) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Smallest rectangle containing both corner points
+    pub fn from_corners(a: Point2D, b: Point2D) -> Self {
+        Self {
+            x: a.x.min(b.x),
+            y: a.y.min(b.y),
+            width: (a.x - b.x).abs(),
+            height: (a.y - b.y).abs(),
+        }
+    }
+
+    pub fn min(&self) -> Point2D {
+        Point2D::new(self.x, self.y)
+    }
+
+    pub fn max(&self) -> Point2D {
+        Point2D::new(self.x + self.width, self.y + self.height)
+    }
+
+    pub fn center(&self) -> Point2D {
+        Point2D::new(self.x + self.width / 2.0, self.y + self.height / 2.0)
+    }
+
+    /// Check if a point lies inside or on the edge of the rectangle
+    pub fn contains(&self, point: Point2D) -> bool {
+        point.x >= self.x
+            && point.x <= self.x + self.width
+            && point.y >= self.y
+            && point.y <= self.y + self.height
+    }
+
+    /// Check if two rectangles overlap or touch
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.x <= other.x + other.width
+            && other.x <= self.x + self.width
+            && self.y <= other.y + other.height
+            && other.y <= self.y + self.height
+    }
+}
+
+/// Canvas size used for placed notes that have no explicit size
+pub const DEFAULT_NOTE_SIZE: (f64, f64) = (200.0, 100.0);
+
 /// A single note in the knowledge graph
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Note {
@@ -95,6 +156,15 @@ impl Note {
         self
     }
 
+    /// Bounding box on the canvas (None if not placed)
+    ///
+    /// Notes without an explicit size use [`DEFAULT_NOTE_SIZE`].
+    pub fn bounds(&self) -> Option<Rect> {
+        let (width, height) = self.size.unwrap_or(DEFAULT_NOTE_SIZE);
+        self.position
+            .map(|pos| Rect::new(pos.x, pos.y, width, height))
+    }
+
     /// Update the modified timestamp
     pub fn touch(&mut self) {
         self.modified_at = Utc::now();
@@ -155,6 +225,22 @@ mod tests {
         assert_eq!(note.position, Some(Point2D::new(100.0, 200.0)));
     }
 
+    #[test]
+    fn test_bounds() {
+        let mut note = Note::new("Boxed");
+        assert!(note.bounds().is_none());
+
+        note.position = Some(Point2D::new(10.0, 20.0));
+        let (w, h) = DEFAULT_NOTE_SIZE;
+        assert_eq!(note.bounds(), Some(Rect::new(10.0, 20.0, w, h)));
+
+        note.size = Some((50.0, 40.0));
+        let bounds = note.bounds().unwrap();
+        assert!(bounds.contains(Point2D::new(60.0, 60.0)));
+        assert!(!bounds.contains(Point2D::new(61.0, 60.0)));
+        assert!(bounds.intersects(&Rect::new(55.0, 55.0, 10.0, 10.0)));
+    }
+
     #[test]
     fn test_add_link() {
         let mut note = Note::new("Source");
@@ -1,9 +1,31 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 //! Notebook - collection of notes with relationship tracking
 
-use crate::note::{Note, NoteId};
+use crate::adornment::{Adornment, AdornmentId, AdornmentRule};
+use crate::agent::{
+    Agent, AgentAction, AgentChange, AgentId, AgentRun, NoteChange, Schedule, MAX_AGENT_HISTORY,
+    MAX_AGENT_PASSES,
+};
+use crate::analysis::{AnalysisConfig, Analyzer};
+use crate::arrange::{self, ArrangeOp};
+use crate::canvas::{CanvasMap, MapId, Placement, Viewport};
+use crate::embedding::{
+    self, Embedder, EmbeddingError, HashedTfIdf, SimilarNote, VectorIndex, DEFAULT_DIMENSIONS,
+};
+use crate::facet::{self, Facet, FacetOptions, FacetSelection, NoteGroup};
+use crate::fuzzy::{self, FuzzyMatch, FuzzyOptions};
+use crate::note::{Note, NoteId, Point2D, Rect, DEFAULT_NOTE_SIZE};
+use crate::placement::{self, Move, PlacementOptions};
+use crate::query::Query;
+use crate::replace::{self, FindError, FindOptions, RegexMatch, ReplacePlan};
+use crate::search::{RankedQuery, SearchHit, SearchOptions};
+use crate::spatial::{self, SpatialIndex};
+use crate::text_index::{self, SearchField, TextIndex};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use thiserror::Error;
 
 /// Errors that can occur during notebook operations
@@ -14,10 +36,26 @@ pub enum NotebookError {
 
     #[error("Cannot create circular link")]
     CircularLink,
+
+    #[error("Map not found: {0}")]
+    MapNotFound(MapId),
+
+    #[error("The default map cannot be removed")]
+    DefaultMapRemoval,
+
+    #[error("Agent not found: {0}")]
+    AgentNotFound(AgentId),
+
+    #[error("Embedding failed: {0}")]
+    Embedding(#[from] EmbeddingError),
+
+    #[error("Note changed since the replacement was previewed: {0}")]
+    ReplaceConflict(NoteId),
 }
 
 /// A notebook containing a collection of interconnected notes
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "NotebookData")]
 pub struct Notebook {
     /// All notes indexed by ID
     notes: HashMap<NoteId, Note>,
@@ -34,18 +72,171 @@ pub struct Notebook {
 
     /// When the notebook was last modified
     pub modified_at: chrono::DateTime<chrono::Utc>,
+
+    /// Canvas adornments indexed by ID
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    adornments: HashMap<AdornmentId, Adornment>,
+
+    /// Named canvas maps, in display order
+    maps: Vec<CanvasMap>,
+
+    /// The map backed by the notes' own positions
+    default_map: MapId,
+
+    /// Saved agents, in display order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    agents: Vec<Agent>,
+
+    /// Text analysis settings used by all searches
+    #[serde(default, skip_serializing_if = "AnalysisConfig::is_default")]
+    analysis: AnalysisConfig,
+
+    /// Spatial index over note bounding boxes (rebuilt on load)
+    #[serde(skip)]
+    spatial: SpatialIndex,
+
+    /// Full-text index over note titles and content (rebuilt on load)
+    #[serde(skip)]
+    text: TextIndex,
+
+    /// Notes handed out by `get_note_mut` that may have changed since they
+    /// were last indexed; reindexed on the next mutating call
+    #[serde(skip)]
+    pending: HashSet<NoteId>,
+
+    /// Embedding model for similarity search
+    #[serde(skip)]
+    embedder: Arc<dyn Embedder>,
+
+    /// Whether `embedder` was supplied instead of fitted to the notes
+    #[serde(skip)]
+    custom_embedder: bool,
+
+    /// Note vectors for similarity search (stored next to the notebook)
+    #[serde(skip)]
+    vectors: VectorIndex,
+
+    /// Notes whose vectors may be out of date
+    #[serde(skip)]
+    stale_vectors: HashSet<NoteId>,
+
+    /// Notes added, changed or removed since `take_changed_notes` was last
+    /// called
+    #[serde(skip)]
+    changed: HashSet<NoteId>,
+
+    /// Stamp of each note's latest change (see `note_generation`)
+    #[serde(skip)]
+    generations: HashMap<NoteId, u64>,
+}
+
+/// Source of note change stamps, shared by every notebook so a stamp
+/// never means two different versions of a note
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+fn next_generation() -> u64 {
+    NEXT_GENERATION.fetch_add(1, Ordering::Relaxed)
+}
+
+fn default_embedder() -> Arc<dyn Embedder> {
+    Arc::new(HashedTfIdf::default())
+}
+
+/// Serialized form of a notebook, without derived indexes
+#[derive(Deserialize)]
+struct NotebookData {
+    notes: HashMap<NoteId, Note>,
+    #[serde(default)]
+    backlinks: HashMap<NoteId, HashSet<NoteId>>,
+    name: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+    modified_at: chrono::DateTime<chrono::Utc>,
+    #[serde(default)]
+    adornments: HashMap<AdornmentId, Adornment>,
+    #[serde(default)]
+    maps: Vec<CanvasMap>,
+    #[serde(default)]
+    default_map: Option<MapId>,
+    #[serde(default)]
+    agents: Vec<Agent>,
+    #[serde(default)]
+    analysis: AnalysisConfig,
 }
 
+impl From<NotebookData> for Notebook {
+    fn from(data: NotebookData) -> Self {
+        // Files from before maps existed get a default map for their
+        // note positions
+        let mut maps = data.maps;
+        let default_map = match data.default_map {
+            Some(id) if maps.iter().any(|map| map.id == id) => id,
+            _ => {
+                let map = CanvasMap::new(DEFAULT_MAP_NAME);
+                let id = map.id;
+                maps.insert(0, map);
+                id
+            }
+        };
+
+        let mut notebook = Self {
+            notes: data.notes,
+            backlinks: data.backlinks,
+            name: data.name,
+            created_at: data.created_at,
+            modified_at: data.modified_at,
+            adornments: data.adornments,
+            maps,
+            default_map,
+            agents: data.agents,
+            analysis: data.analysis,
+            spatial: SpatialIndex::new(),
+            text: TextIndex::new(),
+            pending: HashSet::new(),
+            embedder: default_embedder(),
+            custom_embedder: false,
+            vectors: VectorIndex::default(),
+            stale_vectors: HashSet::new(),
+            changed: HashSet::new(),
+            generations: HashMap::new(),
+        };
+        notebook.rebuild_indexes();
+        notebook.generations = notebook
+            .notes
+            .keys()
+            .map(|id| (*id, next_generation()))
+            .collect();
+        notebook
+    }
+}
+
+/// Name given to the default map of a new notebook
+pub const DEFAULT_MAP_NAME: &str = "Main";
+
 impl Notebook {
     /// Create a new empty notebook
     pub fn new(name: impl Into<String>) -> Self {
         let now = chrono::Utc::now();
+        let default_map = CanvasMap::new(DEFAULT_MAP_NAME);
         Self {
             notes: HashMap::new(),
             backlinks: HashMap::new(),
             name: name.into(),
             created_at: now,
             modified_at: now,
+            adornments: HashMap::new(),
+            default_map: default_map.id,
+            maps: vec![default_map],
+            agents: Vec::new(),
+            analysis: AnalysisConfig::default(),
+            spatial: SpatialIndex::new(),
+            text: TextIndex::new(),
+            pending: HashSet::new(),
+            embedder: default_embedder(),
+            custom_embedder: false,
+            vectors: VectorIndex::default(),
+            stale_vectors: HashSet::new(),
+            changed: HashSet::new(),
+            generations: HashMap::new(),
         }
     }
 
@@ -61,6 +252,7 @@ impl Notebook {
 
     /// Add a note to the notebook
     pub fn add_note(&mut self, note: Note) -> NoteId {
+        self.flush_pending();
         let id = note.id;
 
         // Update backlinks for any links this note has
@@ -72,6 +264,7 @@ impl Notebook {
         }
 
         self.notes.insert(id, note);
+        self.reindex_note(&id);
         self.touch();
         id
     }
@@ -88,14 +281,31 @@ impl Notebook {
     }
 
     /// Get a mutable reference to a note
+    ///
+    /// The note is reindexed on the next mutating notebook call; queries in
+    /// the meantime check it directly, so results stay correct.
     pub fn get_note_mut(&mut self, id: &NoteId) -> Option<&mut Note> {
+        self.flush_pending();
         self.touch();
+        if self.notes.contains_key(id) {
+            self.pending.insert(*id);
+        }
         self.notes.get_mut(id)
     }
 
     /// Remove a note and all links to/from it
     pub fn remove_note(&mut self, id: &NoteId) -> Option<Note> {
+        self.flush_pending();
         if let Some(note) = self.notes.remove(id) {
+            self.spatial.remove(id);
+            self.text.remove(id);
+            self.vectors.remove(id);
+            self.stale_vectors.remove(id);
+            self.note_changed(id);
+            for map in &mut self.maps {
+                map.set_placement(*id, None);
+            }
+
             // Remove this note from backlinks of notes it linked to
             for target_id in &note.links {
                 if let Some(backlink_set) = self.backlinks.get_mut(target_id) {
@@ -109,6 +319,7 @@ impl Notebook {
                     if let Some(source_note) = self.notes.get_mut(&source_id) {
                         source_note.remove_link(id);
                     }
+                    self.note_changed(&source_id);
                 }
             }
 
@@ -136,6 +347,7 @@ impl Notebook {
 
         // Update backlinks
         self.backlinks.entry(to).or_default().insert(from);
+        self.note_changed(&from);
         self.touch();
 
         Ok(())
@@ -153,6 +365,7 @@ impl Notebook {
             backlink_set.remove(&from);
         }
 
+        self.note_changed(&from);
         self.touch();
         Ok(())
     }
@@ -175,40 +388,1127 @@ impl Notebook {
         self.notes.keys()
     }
 
-    /// Search notes by title (case-insensitive substring match)
-    pub fn search_by_title(&self, query: &str) -> Vec<&Note> {
-        let query_lower = query.to_lowercase();
-        self.notes
-            .values()
-            .filter(|note| note.title.to_lowercase().contains(&query_lower))
+    /// Notes added, changed or removed since `take_changed_notes` was last
+    /// called, without clearing them
+    pub fn changed_notes(&mut self) -> Vec<NoteId> {
+        self.flush_pending();
+        self.changed.iter().copied().collect()
+    }
+
+    /// Notes added, changed or removed since the last call, including
+    /// edits made through `get_note_mut`
+    pub fn take_changed_notes(&mut self) -> HashSet<NoteId> {
+        self.flush_pending();
+        std::mem::take(&mut self.changed)
+    }
+
+    /// Stamp of a note's latest change, or None if it may have changed
+    /// through `get_note_mut` since
+    ///
+    /// Stamps are unique across all notebooks, so a note with the same
+    /// stamp as when it was last saved is unchanged, even in a clone.
+    pub fn note_generation(&self, id: &NoteId) -> Option<u64> {
+        if self.pending.contains(id) {
+            return None;
+        }
+        self.generations.get(id).copied()
+    }
+
+    /// The saved fields other than notes and backlinks, as they are saved
+    pub(crate) fn manifest(&self) -> serde_json::Result<Map<String, Value>> {
+        let mut manifest = Map::new();
+        manifest.insert("name".into(), Value::from(self.name.as_str()));
+        manifest.insert("created_at".into(), serde_json::to_value(self.created_at)?);
+        manifest.insert("modified_at".into(), serde_json::to_value(self.modified_at)?);
+        if !self.adornments.is_empty() {
+            manifest.insert("adornments".into(), serde_json::to_value(&self.adornments)?);
+        }
+        manifest.insert("maps".into(), serde_json::to_value(&self.maps)?);
+        manifest.insert("default_map".into(), serde_json::to_value(self.default_map)?);
+        if !self.agents.is_empty() {
+            manifest.insert("agents".into(), serde_json::to_value(&self.agents)?);
+        }
+        if !self.analysis.is_default() {
+            manifest.insert("analysis".into(), serde_json::to_value(&self.analysis)?);
+        }
+        Ok(manifest)
+    }
+
+    /// Move a note on the canvas (None removes it from the canvas)
+    pub fn set_note_position(
+        &mut self,
+        id: &NoteId,
+        position: Option<Point2D>,
+    ) -> Result<(), NotebookError> {
+        self.flush_pending();
+        let note = self.notes.get_mut(id).ok_or(NotebookError::NoteNotFound(*id))?;
+        note.position = position;
+        note.touch();
+        self.reindex_note(id);
+        self.touch();
+        Ok(())
+    }
+
+    /// Resize a note on the canvas (None restores the default size)
+    pub fn set_note_size(
+        &mut self,
+        id: &NoteId,
+        size: Option<(f64, f64)>,
+    ) -> Result<(), NotebookError> {
+        self.flush_pending();
+        let note = self.notes.get_mut(id).ok_or(NotebookError::NoteNotFound(*id))?;
+        note.size = size;
+        note.touch();
+        self.reindex_note(id);
+        self.touch();
+        Ok(())
+    }
+
+    /// Move several notes at once
+    ///
+    /// All IDs are checked before anything moves, so an unknown ID leaves
+    /// the notebook unchanged.
+    pub fn move_notes(&mut self, moves: &[Move]) -> Result<(), NotebookError> {
+        if let Some((id, _)) = moves.iter().find(|(id, _)| !self.notes.contains_key(id)) {
+            return Err(NotebookError::NoteNotFound(*id));
+        }
+        for (id, position) in moves {
+            self.set_note_position(id, Some(*position))?;
+        }
+        Ok(())
+    }
+
+    /// Set the position and size of several notes at once
+    ///
+    /// A note's size is only written when it differs from its current
+    /// (possibly default) size. All IDs are checked before anything changes.
+    pub fn set_note_bounds(&mut self, updates: &[(NoteId, Rect)]) -> Result<(), NotebookError> {
+        if let Some((id, _)) = updates.iter().find(|(id, _)| !self.notes.contains_key(id)) {
+            return Err(NotebookError::NoteNotFound(*id));
+        }
+        self.flush_pending();
+        for (id, rect) in updates {
+            if let Some(note) = self.notes.get_mut(id) {
+                note.position = Some(rect.min());
+                if note.size.unwrap_or(DEFAULT_NOTE_SIZE) != (rect.width, rect.height) {
+                    note.size = Some((rect.width, rect.height));
+                }
+                note.touch();
+            }
+            self.reindex_note(id);
+        }
+        self.touch();
+        Ok(())
+    }
+
+    /// Align, distribute, size-match or snap a selection as one batch,
+    /// returning the IDs of notes that changed
+    pub fn arrange_notes(
+        &mut self,
+        ids: &[NoteId],
+        op: &ArrangeOp,
+    ) -> Result<Vec<NoteId>, NotebookError> {
+        if let Some(id) = ids.iter().find(|id| !self.notes.contains_key(id)) {
+            return Err(NotebookError::NoteNotFound(*id));
+        }
+        let updates = arrange::plan(self, ids, op);
+        self.set_note_bounds(&updates)?;
+        Ok(updates.into_iter().map(|(id, _)| id).collect())
+    }
+
+    /// Place a note at the free spot nearest to `near`, returning where it
+    /// ended up
+    pub fn place_note(
+        &mut self,
+        id: &NoteId,
+        near: Point2D,
+        options: &PlacementOptions,
+    ) -> Result<Point2D, NotebookError> {
+        let size = self
+            .get_note(id)
+            .ok_or(NotebookError::NoteNotFound(*id))?
+            .size
+            .unwrap_or(DEFAULT_NOTE_SIZE);
+        let ignore = HashSet::from([*id]);
+        let spot = placement::find_free_spot(self, size, near, &ignore, options);
+        self.set_note_position(id, Some(spot))?;
+        Ok(spot)
+    }
+
+    /// Get all notes whose bounding box intersects the rectangle
+    pub fn notes_in_rect(&self, rect: Rect) -> Vec<&Note> {
+        self.spatial
+            .intersecting(&rect)
+            .filter(|id| !self.pending.contains(id))
+            .chain(self.pending_matching(|bounds| bounds.intersects(&rect)))
+            .filter_map(|id| self.notes.get(&id))
             .collect()
     }
 
-    /// Search notes by content (case-insensitive substring match)
-    pub fn search_by_content(&self, query: &str) -> Vec<&Note> {
-        let query_lower = query.to_lowercase();
-        self.notes
-            .values()
-            .filter(|note| note.content.to_lowercase().contains(&query_lower))
+    /// Get all notes whose bounding box contains the point
+    pub fn notes_at_point(&self, point: Point2D) -> Vec<&Note> {
+        self.spatial
+            .at_point(point)
+            .filter(|id| !self.pending.contains(id))
+            .chain(self.pending_matching(|bounds| bounds.contains(point)))
+            .filter_map(|id| self.notes.get(&id))
             .collect()
     }
 
+    /// Hit-test the canvas: the note under the point, preferring the
+    /// smallest when several overlap
+    pub fn note_at_point(&self, point: Point2D) -> Option<&Note> {
+        self.notes_at_point(point).into_iter().min_by(|a, b| {
+            let area = |note: &Note| note.bounds().map_or(0.0, |r| r.width * r.height);
+            area(a).total_cmp(&area(b)).then(a.id.cmp(&b.id))
+        })
+    }
+
+    /// Get the placed note nearest to the point (distance to its bounding box)
+    pub fn nearest_note(&self, point: Point2D) -> Option<&Note> {
+        let indexed = self
+            .spatial
+            .nearest(point)
+            .find(|(id, _)| !self.pending.contains(id));
+        let pending = self.pending.iter().filter_map(|id| {
+            let bounds = self.notes.get(id)?.bounds()?;
+            Some((*id, spatial::distance_2(&bounds, point)))
+        });
+        indexed
+            .into_iter()
+            .chain(pending)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .and_then(|(id, _)| self.notes.get(&id))
+    }
+
+    /// Add an adornment to the canvas
+    ///
+    /// Rules do not run for notes already inside it; they only run when a
+    /// note later moves in or out.
+    pub fn add_adornment(&mut self, adornment: Adornment) -> AdornmentId {
+        let id = adornment.id;
+        self.adornments.insert(id, adornment);
+        self.touch();
+        id
+    }
+
+    /// Get an adornment by ID
+    pub fn get_adornment(&self, id: &AdornmentId) -> Option<&Adornment> {
+        self.adornments.get(id)
+    }
+
+    /// Get a mutable reference to an adornment
+    ///
+    /// Moving or resizing an adornment does not run its rules.
+    pub fn get_adornment_mut(&mut self, id: &AdornmentId) -> Option<&mut Adornment> {
+        self.touch();
+        self.adornments.get_mut(id)
+    }
+
+    /// Remove an adornment (notes inside it are left untouched)
+    pub fn remove_adornment(&mut self, id: &AdornmentId) -> Option<Adornment> {
+        let removed = self.adornments.remove(id);
+        if removed.is_some() {
+            self.touch();
+        }
+        removed
+    }
+
+    /// Get all adornments
+    pub fn all_adornments(&self) -> impl Iterator<Item = &Adornment> {
+        self.adornments.values()
+    }
+
+    /// Get all notes whose centre lies inside the adornment
+    pub fn notes_in_adornment(&self, id: &AdornmentId) -> Vec<&Note> {
+        let Some(adornment) = self.adornments.get(id) else {
+            return Vec::new();
+        };
+        self.notes_in_rect(adornment.bounds)
+            .into_iter()
+            .filter(|note| note.bounds().is_some_and(|b| adornment.contains_note(&b)))
+            .collect()
+    }
+
+    /// ID of the default map, backed by the notes' own positions
+    pub fn default_map_id(&self) -> MapId {
+        self.default_map
+    }
+
+    /// Get all maps in display order
+    pub fn all_maps(&self) -> impl Iterator<Item = &CanvasMap> {
+        self.maps.iter()
+    }
+
+    /// Get a map by ID
+    pub fn get_map(&self, id: &MapId) -> Option<&CanvasMap> {
+        self.maps.iter().find(|map| map.id == *id)
+    }
+
+    /// Create a new empty map
+    pub fn create_map(&mut self, name: impl Into<String>) -> MapId {
+        let map = CanvasMap::new(name);
+        let id = map.id;
+        self.maps.push(map);
+        self.touch();
+        id
+    }
+
+    /// Rename a map
+    pub fn rename_map(&mut self, id: &MapId, name: impl Into<String>) -> Result<(), NotebookError> {
+        self.map_mut(id)?.name = name.into();
+        self.touch();
+        Ok(())
+    }
+
+    /// Remember a map's viewport
+    pub fn set_map_viewport(
+        &mut self,
+        id: &MapId,
+        viewport: Viewport,
+    ) -> Result<(), NotebookError> {
+        self.map_mut(id)?.viewport = viewport;
+        self.touch();
+        Ok(())
+    }
+
+    /// Remove a map (the default map cannot be removed)
+    pub fn remove_map(&mut self, id: &MapId) -> Result<CanvasMap, NotebookError> {
+        if *id == self.default_map {
+            return Err(NotebookError::DefaultMapRemoval);
+        }
+        let index = self
+            .maps
+            .iter()
+            .position(|map| map.id == *id)
+            .ok_or(NotebookError::MapNotFound(*id))?;
+        self.touch();
+        Ok(self.maps.remove(index))
+    }
+
+    /// Get a note's placement on a map
+    pub fn map_placement(&self, map: &MapId, note: &NoteId) -> Option<Placement> {
+        if *map == self.default_map {
+            let note = self.notes.get(note)?;
+            return note.position.map(|position| Placement {
+                position,
+                size: note.size,
+            });
+        }
+        self.get_map(map)?.placement(note)
+    }
+
+    /// Place, move or (with None) remove a note on a map
+    ///
+    /// On the default map this sets the note's own `position` and `size`.
+    pub fn set_map_placement(
+        &mut self,
+        map: &MapId,
+        note: &NoteId,
+        placement: Option<Placement>,
+    ) -> Result<(), NotebookError> {
+        if !self.notes.contains_key(note) {
+            return Err(NotebookError::NoteNotFound(*note));
+        }
+        if *map == self.default_map {
+            self.flush_pending();
+            if let Some(n) = self.notes.get_mut(note) {
+                n.position = placement.map(|p| p.position);
+                n.size = placement.and_then(|p| p.size);
+                n.touch();
+            }
+            self.reindex_note(note);
+        } else {
+            self.map_mut(map)?.set_placement(*note, placement);
+        }
+        self.touch();
+        Ok(())
+    }
+
+    /// Get all notes on a map with their placements
+    pub fn notes_on_map(&self, map: &MapId) -> Vec<(&Note, Placement)> {
+        if *map == self.default_map {
+            return self
+                .notes
+                .values()
+                .filter_map(|note| Some((note, self.map_placement(map, &note.id)?)))
+                .collect();
+        }
+        let Some(canvas) = self.get_map(map) else {
+            return Vec::new();
+        };
+        canvas
+            .placements()
+            .filter_map(|(id, placement)| Some((self.notes.get(id)?, *placement)))
+            .collect()
+    }
+
+    /// Get all notes on a map whose bounding box intersects the rectangle
+    pub fn notes_in_rect_on_map(&self, map: &MapId, rect: Rect) -> Vec<&Note> {
+        if *map == self.default_map {
+            return self.notes_in_rect(rect);
+        }
+        let Some(canvas) = self.get_map(map) else {
+            return Vec::new();
+        };
+        canvas
+            .notes_in_rect(&rect)
+            .filter_map(|id| self.notes.get(&id))
+            .collect()
+    }
+
+    fn map_mut(&mut self, id: &MapId) -> Result<&mut CanvasMap, NotebookError> {
+        self.maps
+            .iter_mut()
+            .find(|map| map.id == *id)
+            .ok_or(NotebookError::MapNotFound(*id))
+    }
+
+    /// Search notes by title (substring match on normalized text)
+    pub fn search_by_title(&self, query: &str) -> Vec<&Note> {
+        self.text_search(query, SearchField::Title)
+    }
+
+    /// Search notes by content (substring match on normalized text)
+    pub fn search_by_content(&self, query: &str) -> Vec<&Note> {
+        self.text_search(query, SearchField::Content)
+    }
+
     /// Search notes by title or content
     pub fn search(&self, query: &str) -> Vec<&Note> {
-        let query_lower = query.to_lowercase();
-        self.notes
-            .values()
-            .filter(|note| {
-                note.title.to_lowercase().contains(&query_lower)
-                    || note.content.to_lowercase().contains(&query_lower)
+        self.text_search(query, SearchField::Any)
+    }
+
+    /// Search notes by words in the title or content, best matches first
+    ///
+    /// Each hit carries a content snippet and the ranges of matched words
+    /// for highlighting.
+    pub fn search_ranked(&self, query: &str, options: &SearchOptions) -> Vec<SearchHit> {
+        let query = RankedQuery::new(query, &self.text, options);
+        let indexed = query
+            .candidates(&self.text)
+            .into_iter()
+            .filter(|id| !self.pending.contains(id))
+            .filter_map(|id| self.notes.get(&id))
+            .map(|note| (query.score_indexed(&self.text, &note.id, options), note));
+        let pending = self
+            .pending
+            .iter()
+            .filter_map(|id| self.notes.get(id))
+            .map(|note| (query.score_note(note, options), note));
+        query.collect_hits(indexed.chain(pending).collect(), options)
+    }
+
+    /// Text analysis settings used by all searches
+    pub fn analysis_config(&self) -> &AnalysisConfig {
+        &self.analysis
+    }
+
+    /// Change the text analysis settings, reindexing all notes
+    pub fn set_analysis_config(&mut self, config: AnalysisConfig) {
+        if config == self.analysis {
+            return;
+        }
+        self.flush_pending();
+        self.analysis = config;
+        self.rebuild_indexes();
+        if !self.custom_embedder {
+            self.embedder = default_embedder();
+        }
+        // Text terms in agent queries may now match differently
+        for agent in &mut self.agents {
+            agent.cache.valid = false;
+        }
+        self.touch();
+    }
+
+    /// The analyzer applied to note text and queries
+    pub(crate) fn analyzer(&self) -> &Analyzer {
+        self.text.analyzer()
+    }
+
+    /// Fuzzy-match note titles for a quick switcher, best matches first
+    pub fn quick_switch(&self, pattern: &str, options: &FuzzyOptions) -> Vec<FuzzyMatch> {
+        let fold = self.analysis.fold_diacritics;
+        fuzzy::quick_switch(self.notes.values(), pattern, options, fold)
+    }
+
+    /// Notes matching a structured query, ordered by title
+    pub fn query(&self, query: &Query) -> Vec<&Note> {
+        query.run(self)
+    }
+
+    /// Regex matches in note titles, content and string attributes
+    pub fn find_regex(
+        &self,
+        pattern: &str,
+        options: &FindOptions,
+    ) -> Result<Vec<RegexMatch>, FindError> {
+        let regex = replace::compile(pattern, options)?;
+        Ok(replace::find(self.notes.values(), &regex, options))
+    }
+
+    /// Work out the changes a find-and-replace would make, without making
+    /// them
+    pub fn preview_replace(
+        &self,
+        pattern: &str,
+        replacement: &str,
+        options: &FindOptions,
+    ) -> Result<ReplacePlan, FindError> {
+        let regex = replace::compile(pattern, options)?;
+        Ok(replace::plan(self.notes.values(), &regex, replacement, options))
+    }
+
+    /// Apply a previewed replacement as one operation, returning the plan
+    /// that undoes it
+    ///
+    /// Nothing is changed if any note no longer has the text the plan
+    /// expects. Only notes that change are touched.
+    pub fn apply_replace(&mut self, plan: &ReplacePlan) -> Result<ReplacePlan, NotebookError> {
+        self.flush_pending();
+        for changes in &plan.notes {
+            let note = self
+                .notes
+                .get(&changes.note)
+                .ok_or(NotebookError::NoteNotFound(changes.note))?;
+            let current = changes.fields.iter().all(|field| {
+                replace::field_text(note, &field.location) == Some(field.before.as_str())
+            });
+            if !current {
+                return Err(NotebookError::ReplaceConflict(changes.note));
+            }
+        }
+
+        for changes in &plan.notes {
+            if let Some(note) = self.notes.get_mut(&changes.note) {
+                for field in &changes.fields {
+                    replace::set_field_text(note, &field.location, field.after.clone());
+                }
+                note.touch();
+            }
+            self.reindex_note(&changes.note);
+        }
+        if !plan.is_empty() {
+            self.touch();
+        }
+        Ok(plan.inverse())
+    }
+
+    /// Summarize attributes across the notes satisfying every selection
+    pub fn facets(&self, selections: &[FacetSelection], options: &FacetOptions) -> Vec<Facet> {
+        facet::compute_facets(self.filter_by_facets(selections), options)
+    }
+
+    /// Notes satisfying every facet selection
+    pub fn filter_by_facets(&self, selections: &[FacetSelection]) -> Vec<&Note> {
+        facet::filter_notes(self.notes.values(), selections)
+    }
+
+    /// Group all notes by an attribute's value, for list and kanban views
+    pub fn group_by_attribute(&self, key: &str) -> Vec<NoteGroup> {
+        facet::group_notes(self.notes.values(), key)
+    }
+
+    /// Use an embedding model for similarity search, or the built-in hashed
+    /// TF-IDF with None; existing vectors are recomputed on the next search
+    pub fn set_embedder(&mut self, embedder: Option<Arc<dyn Embedder>>) {
+        self.custom_embedder = embedder.is_some();
+        self.embedder = embedder.unwrap_or_else(default_embedder);
+        self.vectors = VectorIndex::default();
+        self.stale_vectors = self.notes.keys().copied().collect();
+    }
+
+    /// The embedding model used for similarity search
+    pub fn embedder(&self) -> &dyn Embedder {
+        self.embedder.as_ref()
+    }
+
+    /// Note vectors, for saving next to the notebook
+    pub fn vector_index(&self) -> &VectorIndex {
+        &self.vectors
+    }
+
+    /// Reuse saved note vectors, if they come from the current model
+    ///
+    /// Vectors of notes edited since are recomputed on the next search.
+    /// Returns false if the vectors were discarded.
+    pub fn load_vector_index(&mut self, mut index: VectorIndex) -> bool {
+        if !self.custom_embedder {
+            // The vectors only match the weights they were computed with
+            let saved = index
+                .weights()
+                .map(|weights| HashedTfIdf::with_weights(self.analyzer().clone(), weights.to_vec()))
+                .filter(|embedder| index.is_compatible(embedder));
+            match saved {
+                Some(embedder) => self.embedder = Arc::new(embedder),
+                None => {
+                    self.fit_builtin_embedder();
+                }
+            }
+        }
+        if !index.is_compatible(self.embedder.as_ref()) {
+            return false;
+        }
+        index.retain(|id| self.notes.contains_key(id));
+        self.vectors = index;
+        self.stale_vectors = self.notes.keys().copied().collect();
+        true
+    }
+
+    /// Bring note vectors up to date, returning how many were computed
+    ///
+    /// The built-in embedder is fitted to the notes whenever all vectors
+    /// are computed from scratch.
+    pub fn refresh_vectors(&mut self) -> Result<usize, EmbeddingError> {
+        self.flush_pending();
+        if !self.vectors.is_compatible(self.embedder.as_ref()) || self.vectors.is_empty() {
+            self.vectors = if self.custom_embedder {
+                VectorIndex::new(self.embedder.model(), self.embedder.dimensions())
+            } else {
+                let weights = self.fit_builtin_embedder();
+                VectorIndex::new(self.embedder.model(), self.embedder.dimensions())
+                    .with_weights(weights)
+            };
+            self.stale_vectors = self.notes.keys().copied().collect();
+        }
+
+        let stale: Vec<NoteId> = std::mem::take(&mut self.stale_vectors).into_iter().collect();
+        let mut computed = 0;
+        for (i, id) in stale.iter().enumerate() {
+            let Some(note) = self.notes.get(id) else {
+                continue;
+            };
+            let text = embedding::note_text(note);
+            let fingerprint = embedding::fingerprint(&text);
+            if self.vectors.is_current(id, fingerprint) {
+                continue;
+            }
+            let result = self
+                .embedder
+                .embed(&text)
+                .and_then(|vector| self.vectors.insert(*id, fingerprint, vector));
+            if let Err(e) = result {
+                // Leave the rest for the next attempt
+                self.stale_vectors.extend(&stale[i..]);
+                return Err(e);
+            }
+            computed += 1;
+        }
+        Ok(computed)
+    }
+
+    /// Notes most similar in meaning to a note, best first
+    pub fn similar_notes(
+        &mut self,
+        id: &NoteId,
+        limit: usize,
+    ) -> Result<Vec<SimilarNote>, NotebookError> {
+        if !self.notes.contains_key(id) {
+            return Err(NotebookError::NoteNotFound(*id));
+        }
+        self.refresh_vectors()?;
+        let vector = self.vectors.get(id).unwrap_or_default().to_vec();
+        Ok(self.similar_to(&vector, limit, Some(id)))
+    }
+
+    /// Notes most similar in meaning to a piece of text, best first
+    pub fn semantic_search(
+        &mut self,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<SimilarNote>, NotebookError> {
+        self.refresh_vectors()?;
+        let vector = self.embedder.embed(query)?;
+        Ok(self.similar_to(&vector, limit, None))
+    }
+
+    /// Add an agent; its matches are computed on its first run
+    pub fn add_agent(&mut self, agent: Agent) -> AgentId {
+        let id = agent.id;
+        self.agents.push(agent);
+        self.touch();
+        id
+    }
+
+    /// Get an agent by ID
+    pub fn get_agent(&self, id: &AgentId) -> Option<&Agent> {
+        self.agents.iter().find(|agent| agent.id == *id)
+    }
+
+    /// Iterate over all agents in display order
+    pub fn all_agents(&self) -> impl Iterator<Item = &Agent> {
+        self.agents.iter()
+    }
+
+    /// Remove an agent
+    pub fn remove_agent(&mut self, id: &AgentId) -> Option<Agent> {
+        let index = self.agents.iter().position(|agent| agent.id == *id)?;
+        self.touch();
+        Some(self.agents.remove(index))
+    }
+
+    /// Run an agent now, whatever its schedule, applying its action to its
+    /// matches
+    pub fn run_agent(&mut self, id: &AgentId) -> Result<AgentRun, NotebookError> {
+        let index = self.agent_index(id)?;
+        self.flush_pending();
+        Ok(self.execute_agent(index, chrono::Utc::now(), false, &mut HashSet::new()))
+    }
+
+    /// Work out what running an agent would change, without changing
+    /// anything but its cached matches
+    pub fn preview_agent(&mut self, id: &AgentId) -> Result<AgentRun, NotebookError> {
+        let index = self.agent_index(id)?;
+        self.flush_pending();
+        Ok(self.execute_agent(index, chrono::Utc::now(), true, &mut HashSet::new()))
+    }
+
+    /// Run every agent whose schedule is due, returning what each run did
+    ///
+    /// Continuous agents are always due, so calling this after changing
+    /// notes keeps their results current. When actions change notes,
+    /// continuous agents run again on the changes, up to
+    /// [`MAX_AGENT_PASSES`] times, and no agent changes the same note twice.
+    pub fn refresh_agents(&mut self, now: chrono::DateTime<chrono::Utc>) -> Vec<AgentRun> {
+        self.flush_pending();
+        let mut applied = HashSet::new();
+        let mut runs: Vec<AgentRun> = Vec::new();
+        let mut due: Vec<usize> = (0..self.agents.len())
+            .filter(|&index| self.agents[index].is_due(now))
+            .collect();
+
+        for _ in 0..MAX_AGENT_PASSES {
+            let mut changed = false;
+            for &index in &due {
+                let run = self.execute_agent(index, now, false, &mut applied);
+                changed |= !run.changes.is_empty();
+                match runs.iter_mut().find(|r| r.agent == run.agent) {
+                    Some(existing) => {
+                        existing.changes.extend(run.changes);
+                        existing.skipped.extend(run.skipped);
+                    }
+                    None => runs.push(run),
+                }
+            }
+            if !changed {
+                break;
+            }
+            due = (0..self.agents.len())
+                .filter(|&index| {
+                    let agent = &self.agents[index];
+                    agent.schedule == Schedule::Continuous
+                        && !agent.cache.changed.is_empty()
+                })
+                .collect();
+        }
+        runs
+    }
+
+    /// Cached matches of an agent as of its last run, ordered by title
+    pub fn agent_results(&self, id: &AgentId) -> Option<Vec<&Note>> {
+        let index = self.agents.iter().position(|agent| agent.id == *id)?;
+        Some(
+            self.sorted_agent_results(index)
+                .iter()
+                .filter_map(|id| self.notes.get(id))
+                .collect(),
+        )
+    }
+
+    fn sorted_agent_results(&self, index: usize) -> Vec<NoteId> {
+        let mut notes: Vec<&Note> = self.agents[index]
+            .cache
+            .results
+            .iter()
+            .filter_map(|id| self.notes.get(id))
+            .collect();
+        notes.sort_by(|a, b| a.title.cmp(&b.title).then(a.id.cmp(&b.id)));
+        notes.into_iter().map(|note| note.id).collect()
+    }
+
+    /// Bring an agent's cached matches up to date
+    fn update_agent(&mut self, index: usize, now: chrono::DateTime<chrono::Utc>) {
+        let today = now.date_naive();
+        let agent = &self.agents[index];
+        let cache = &agent.cache;
+        let full = !cache.valid
+            || (!agent.query.is_local()
+                && (!cache.changed.is_empty() || cache.evaluated_on != Some(today)));
+
+        if full {
+            let compiled = agent.query.compile(self);
+            let results = self
+                .notes
+                .values()
+                .filter(|note| compiled.matches(note, self))
+                .map(|note| note.id)
+                .collect();
+            self.agents[index].cache.results = results;
+        } else {
+            let compiled = agent.query.compile_direct(self);
+            let updates: Vec<(NoteId, bool)> = cache
+                .changed
+                .iter()
+                .map(|id| {
+                    let matches = self
+                        .notes
+                        .get(id)
+                        .is_some_and(|note| compiled.matches(note, self));
+                    (*id, matches)
+                })
+                .collect();
+            let results = &mut self.agents[index].cache.results;
+            for (id, matches) in updates {
+                if matches {
+                    results.insert(id);
+                } else {
+                    results.remove(&id);
+                }
+            }
+        }
+
+        let agent = &mut self.agents[index];
+        agent.cache.valid = true;
+        agent.cache.changed.clear();
+        agent.cache.evaluated_on = Some(today);
+    }
+
+    fn agent_index(&self, id: &AgentId) -> Result<usize, NotebookError> {
+        self.agents
+            .iter()
+            .position(|agent| agent.id == *id)
+            .ok_or(NotebookError::AgentNotFound(*id))
+    }
+
+    /// Update an agent's matches and apply (or, in a dry run, plan) its
+    /// action on them, skipping notes it already changed in `applied`
+    fn execute_agent(
+        &mut self,
+        index: usize,
+        now: chrono::DateTime<chrono::Utc>,
+        dry_run: bool,
+        applied: &mut HashSet<(AgentId, NoteId)>,
+    ) -> AgentRun {
+        self.update_agent(index, now);
+        let agent_id = self.agents[index].id;
+        let action = self.agents[index].action.clone();
+        let mut run = AgentRun {
+            agent: agent_id,
+            ran_at: now,
+            dry_run,
+            changes: Vec::new(),
+            skipped: Vec::new(),
+        };
+
+        for note in self.sorted_agent_results(index) {
+            let Some(change) = self.plan_agent_action(&note, &action) else {
+                continue;
+            };
+            if !applied.insert((agent_id, note)) {
+                run.skipped.push(note);
+                continue;
+            }
+            if !dry_run {
+                self.apply_note_change(&note, &change);
+            }
+            run.changes.push(AgentChange { note, change });
+        }
+
+        if !dry_run {
+            let agent = &mut self.agents[index];
+            agent.last_run = Some(now);
+            if !run.changes.is_empty() {
+                agent.history.push(run.clone());
+                let excess = agent.history.len().saturating_sub(MAX_AGENT_HISTORY);
+                agent.history.drain(..excess);
+            }
+        }
+        run
+    }
+
+    /// The change an action would make to a note, or None if the note
+    /// already satisfies it or the action cannot apply
+    fn plan_agent_action(&self, id: &NoteId, action: &AgentAction) -> Option<NoteChange> {
+        let note = self.notes.get(id)?;
+        match action {
+            AgentAction::Collect => None,
+            AgentAction::SetAttribute { key, value } => {
+                let old = note.get_attribute(key);
+                (old != Some(value)).then(|| NoteChange::AttributeSet {
+                    key: key.clone(),
+                    old: old.cloned(),
+                    new: value.clone(),
+                })
+            }
+            AgentAction::RemoveAttribute { key } => {
+                note.get_attribute(key)
+                    .map(|old| NoteChange::AttributeRemoved {
+                        key: key.clone(),
+                        old: old.clone(),
+                    })
+            }
+            AgentAction::AddLink { target } => (*target != *id
+                && self.notes.contains_key(target)
+                && !note.links_to(target))
+            .then_some(NoteChange::LinkAdded { target: *target }),
+            AgentAction::RemoveLink { target } => note
+                .links_to(target)
+                .then_some(NoteChange::LinkRemoved { target: *target }),
+            AgentAction::ApplyPrototype { prototype } => (note.prototype != Some(*prototype)
+                && self.notes.contains_key(prototype)
+                && !self.prototype_chain_contains(prototype, id))
+            .then_some(NoteChange::PrototypeSet {
+                old: note.prototype,
+                new: *prototype,
+            }),
+            AgentAction::RemovePrototype => note
+                .prototype
+                .map(|old| NoteChange::PrototypeRemoved { old }),
+        }
+    }
+
+    /// Check if a note, or any note up its prototype chain, is `target`
+    pub(crate) fn prototype_chain_contains(&self, start: &NoteId, target: &NoteId) -> bool {
+        let mut seen = HashSet::new();
+        let mut current = Some(*start);
+        while let Some(id) = current {
+            if id == *target {
+                return true;
+            }
+            if !seen.insert(id) {
+                return false;
+            }
+            current = self.notes.get(&id).and_then(|note| note.prototype);
+        }
+        false
+    }
+
+    /// Make a planned change to a note
+    fn apply_note_change(&mut self, id: &NoteId, change: &NoteChange) {
+        match change {
+            NoteChange::LinkAdded { target } => {
+                let _ = self.link_notes(*id, *target);
+            }
+            NoteChange::LinkRemoved { target } => {
+                let _ = self.unlink_notes(*id, *target);
+            }
+            change => {
+                let Some(note) = self.notes.get_mut(id) else {
+                    return;
+                };
+                match change {
+                    NoteChange::AttributeSet { key, new, .. } => {
+                        note.attributes.insert(key.clone(), new.clone());
+                    }
+                    NoteChange::AttributeRemoved { key, .. } => {
+                        note.attributes.remove(key);
+                    }
+                    NoteChange::PrototypeSet { new, .. } => note.prototype = Some(*new),
+                    NoteChange::PrototypeRemoved { .. } => note.prototype = None,
+                    NoteChange::LinkAdded { .. } | NoteChange::LinkRemoved { .. } => {}
+                }
+                note.touch();
+                self.note_changed(id);
+                self.touch();
+            }
+        }
+    }
+
+    /// Record a changed note for agents to re-check on their next run and
+    /// for `take_changed_notes`, and stamp it
+    fn note_changed(&mut self, id: &NoteId) {
+        self.changed.insert(*id);
+        if self.notes.contains_key(id) {
+            self.generations.insert(*id, next_generation());
+        } else {
+            self.generations.remove(id);
+        }
+        for agent in &mut self.agents {
+            if agent.cache.valid {
+                agent.cache.changed.insert(*id);
+            }
+        }
+    }
+
+    /// Notes whose vectors are nearest to a vector
+    fn similar_to(
+        &self,
+        vector: &[f32],
+        limit: usize,
+        exclude: Option<&NoteId>,
+    ) -> Vec<SimilarNote> {
+        self.vectors
+            .nearest(vector, limit, exclude)
+            .into_iter()
+            .filter_map(|(id, score)| {
+                let note = self.notes.get(&id)?;
+                Some(SimilarNote {
+                    id,
+                    title: note.title.clone(),
+                    score,
+                })
             })
             .collect()
     }
 
+    /// Replace the built-in embedder with one fitted to the current notes,
+    /// returning its weights
+    fn fit_builtin_embedder(&mut self) -> Vec<f32> {
+        let texts: Vec<String> = self.notes.values().map(embedding::note_text).collect();
+        let embedder = HashedTfIdf::fit(
+            self.analyzer().clone(),
+            DEFAULT_DIMENSIONS,
+            texts.iter().map(String::as_str),
+        );
+        let weights = embedder.weights().to_vec();
+        self.embedder = Arc::new(embedder);
+        weights
+    }
+
+    /// Look up a query in the text index, checking pending notes directly
+    fn text_search(&self, query: &str, field: SearchField) -> Vec<&Note> {
+        let pending = self
+            .pending
+            .iter()
+            .filter_map(|id| self.notes.get(id))
+            .filter(|note| text_index::note_matches(self.analyzer(), note, query, field));
+        self.text
+            .search(query, field)
+            .into_iter()
+            .filter(|id| !self.pending.contains(id))
+            .filter_map(|id| self.notes.get(&id))
+            .chain(pending)
+            .collect()
+    }
+
     /// Update the modified timestamp
     fn touch(&mut self) {
         self.modified_at = chrono::Utc::now();
     }
+
+    /// Pending notes whose current bounding box satisfies the predicate
+    fn pending_matching<'a>(
+        &'a self,
+        predicate: impl Fn(&Rect) -> bool + 'a,
+    ) -> impl Iterator<Item = NoteId> + 'a {
+        self.pending.iter().copied().filter(move |id| {
+            self.notes
+                .get(id)
+                .and_then(Note::bounds)
+                .is_some_and(|bounds| predicate(&bounds))
+        })
+    }
+
+    /// Bring a single note's index entries up to date, running adornment
+    /// rules if the move took it into or out of an adornment
+    fn reindex_note(&mut self, id: &NoteId) {
+        if let Some(note) = self.notes.get(id) {
+            self.text.insert(note);
+            self.stale_vectors.insert(*id);
+        }
+        self.note_changed(id);
+        let before = self.spatial.get(id);
+        let after = self.notes.get(id).and_then(Note::bounds);
+        match after {
+            Some(bounds) => self.spatial.insert(*id, bounds),
+            None => {
+                self.spatial.remove(id);
+            }
+        }
+        if before != after && !self.adornments.is_empty() {
+            self.run_adornment_rules(id, before, after);
+        }
+    }
+
+    /// Apply exit rules for adornments the note left, then enter rules for
+    /// adornments it entered
+    fn run_adornment_rules(&mut self, id: &NoteId, before: Option<Rect>, after: Option<Rect>) {
+        let inside =
+            |a: &Adornment, bounds: Option<Rect>| bounds.is_some_and(|b| a.contains_note(&b));
+        let mut adornments: Vec<&Adornment> = self.adornments.values().collect();
+        adornments.sort_by(|a, b| a.name.cmp(&b.name).then(a.id.cmp(&b.id)));
+
+        let mut rules: Vec<AdornmentRule> = Vec::new();
+        for a in &adornments {
+            if inside(a, before) && !inside(a, after) {
+                rules.extend(a.on_exit.iter().cloned());
+            }
+        }
+        for a in &adornments {
+            if !inside(a, before) && inside(a, after) {
+                rules.extend(a.on_enter.iter().cloned());
+            }
+        }
+        for rule in rules {
+            self.apply_adornment_rule(id, rule);
+        }
+    }
+
+    /// Apply one adornment rule; rules naming notes that no longer exist
+    /// are skipped
+    fn apply_adornment_rule(&mut self, id: &NoteId, rule: AdornmentRule) {
+        match rule {
+            AdornmentRule::SetAttribute { key, value } => {
+                if let Some(note) = self.notes.get_mut(id) {
+                    note.set_attribute(key, value);
+                }
+            }
+            AdornmentRule::RemoveAttribute { key } => {
+                if let Some(note) = self.notes.get_mut(id) {
+                    if note.attributes.remove(&key).is_some() {
+                        note.touch();
+                    }
+                }
+            }
+            AdornmentRule::ApplyPrototype { prototype } => {
+                if self.notes.contains_key(&prototype)
+                    && !self.prototype_chain_contains(&prototype, id)
+                {
+                    if let Some(note) = self.notes.get_mut(id) {
+                        note.prototype = Some(prototype);
+                        note.touch();
+                    }
+                }
+            }
+            AdornmentRule::RemovePrototype => {
+                if let Some(note) = self.notes.get_mut(id) {
+                    if note.prototype.take().is_some() {
+                        note.touch();
+                    }
+                }
+            }
+            AdornmentRule::AddLink { target } => {
+                let _ = self.link_notes(*id, target);
+            }
+            AdornmentRule::RemoveLink { target } => {
+                let _ = self.unlink_notes(*id, target);
+            }
+        }
+    }
+
+    /// Reindex notes that may have been edited through `get_note_mut`
+    fn flush_pending(&mut self) {
+        for id in std::mem::take(&mut self.pending) {
+            self.reindex_note(&id);
+        }
+    }
+
+    /// Rebuild all derived indexes from the notes
+    fn rebuild_indexes(&mut self) {
+        self.pending.clear();
+        for map in &mut self.maps {
+            map.rebuild_index();
+        }
+        self.spatial = SpatialIndex::bulk_load(
+            self.notes
+                .values()
+                .filter_map(|note| Some((note.id, note.bounds()?))),
+        );
+        let analyzer = Analyzer::new(self.analysis.clone());
+        self.text = TextIndex::build(analyzer, self.notes.values());
+        self.stale_vectors = self.notes.keys().copied().collect();
+    }
 }
 
 impl Default for Notebook {
@@ -303,4 +1603,101 @@ mod tests {
         let results = notebook.search("project");
         assert_eq!(results.len(), 2); // Both match
     }
+
+    #[test]
+    fn test_search_index_tracks_edits() {
+        let mut notebook = Notebook::new("Test");
+        let id = notebook.create_note("Draft");
+        notebook.get_note_mut(&id).unwrap().content = "Quarterly report".into();
+
+        // Visible before the edit has been reindexed
+        assert_eq!(notebook.search_by_content("quarterly").len(), 1);
+
+        notebook.create_note("Flush");
+        notebook.get_note_mut(&id).unwrap().title = "Final".into();
+        assert!(notebook.search_by_title("draft").is_empty());
+        assert_eq!(notebook.search_by_title("final").len(), 1);
+
+        let json = serde_json::to_string(&notebook).unwrap();
+        let mut loaded: Notebook = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.search("report")[0].id, id);
+
+        loaded.remove_note(&id);
+        assert!(loaded.search("report").is_empty());
+    }
+
+    #[test]
+    fn test_spatial_queries() {
+        let mut notebook = Notebook::new("Test");
+        let a = notebook.add_note(Note::new("A").with_position(0.0, 0.0));
+        let b = notebook.add_note(Note::new("B").with_position(500.0, 0.0));
+        notebook.create_note("Unplaced");
+
+        let hits = notebook.notes_in_rect(Rect::new(-10.0, -10.0, 50.0, 50.0));
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].id, a);
+
+        assert_eq!(notebook.note_at_point(Point2D::new(550.0, 50.0)).unwrap().id, b);
+        assert!(notebook.note_at_point(Point2D::new(300.0, 50.0)).is_none());
+        assert_eq!(notebook.nearest_note(Point2D::new(450.0, 50.0)).unwrap().id, b);
+
+        notebook
+            .set_note_position(&a, Some(Point2D::new(1000.0, 1000.0)))
+            .unwrap();
+        assert!(notebook
+            .notes_in_rect(Rect::new(-10.0, -10.0, 50.0, 50.0))
+            .is_empty());
+        assert_eq!(notebook.notes_at_point(Point2D::new(1100.0, 1050.0)).len(), 1);
+    }
+
+    #[test]
+    fn test_spatial_index_sees_direct_edits() {
+        let mut notebook = Notebook::new("Test");
+        let id = notebook.add_note(Note::new("Moved").with_position(0.0, 0.0));
+
+        // Edited through get_note_mut, then queried before any other mutation
+        notebook.get_note_mut(&id).unwrap().position = Some(Point2D::new(300.0, 300.0));
+        assert!(notebook.note_at_point(Point2D::new(10.0, 10.0)).is_none());
+        assert_eq!(notebook.note_at_point(Point2D::new(310.0, 310.0)).unwrap().id, id);
+
+        notebook.create_note("Flush");
+        assert_eq!(notebook.note_at_point(Point2D::new(310.0, 310.0)).unwrap().id, id);
+    }
+
+    #[test]
+    fn test_spatial_index_rebuilt_on_load() {
+        let mut notebook = Notebook::new("Test");
+        let id = notebook.add_note(Note::new("Placed").with_position(40.0, 40.0));
+
+        let json = serde_json::to_string(&notebook).unwrap();
+        let loaded: Notebook = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.note_at_point(Point2D::new(50.0, 50.0)).unwrap().id, id);
+    }
+
+    #[test]
+    fn test_changed_notes_are_tracked() {
+        let mut notebook = Notebook::new("Test");
+        let a = notebook.create_note("A");
+        let b = notebook.create_note("B");
+        let c = notebook.create_note("C");
+        notebook.link_notes(c, a).unwrap();
+        assert_eq!(notebook.take_changed_notes(), HashSet::from([a, b, c]));
+        assert!(notebook.take_changed_notes().is_empty());
+
+        notebook.get_note_mut(&b).unwrap().content = "Edited".into();
+        notebook.remove_note(&a);
+        assert_eq!(notebook.take_changed_notes(), HashSet::from([a, b, c]));
+    }
+
+    #[test]
+    fn test_manifest_is_saved_form_without_notes() {
+        let mut notebook = Notebook::new("Test");
+        notebook.create_note("A");
+        notebook.create_map("Second");
+        let mut saved = serde_json::to_value(&notebook).unwrap();
+        let saved = saved.as_object_mut().unwrap();
+        saved.remove("notes");
+        saved.remove("backlinks");
+        assert_eq!(&notebook.manifest().unwrap(), saved);
+    }
 }
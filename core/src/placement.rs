@@ -0,0 +1,455 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Placement - free-space search, overlap resolution and tidying on the canvas
+//!
+//! Functions here only plan moves; apply them with
+//! [`Notebook::move_notes`](crate::notebook::Notebook::move_notes).
+
+use crate::note::{Note, NoteId, Point2D, Rect};
+use crate::notebook::Notebook;
+use std::collections::{HashMap, HashSet};
+
+/// Tuning for placement operations
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlacementOptions {
+    /// Minimum gap kept between notes
+    pub margin: f64,
+
+    /// Upper bound on relaxation passes when resolving overlaps
+    pub max_iterations: usize,
+}
+
+impl Default for PlacementOptions {
+    fn default() -> Self {
+        Self {
+            margin: 20.0,
+            max_iterations: 100,
+        }
+    }
+}
+
+/// A planned move of a note to a new canvas position
+pub type Move = (NoteId, Point2D);
+
+/// Times the free-space search radius doubles before the search gives up
+/// and places the note below all others
+const MAX_SEARCH_STEPS: usize = 40;
+
+/// Treat negative and non-finite lengths as zero
+fn length(value: f64) -> f64 {
+    if value.is_finite() && value > 0.0 {
+        value
+    } else {
+        0.0
+    }
+}
+
+/// Grow a rectangle by `margin` on every side
+pub(crate) fn inflate(rect: &Rect, margin: f64) -> Rect {
+    Rect::new(
+        rect.x - margin,
+        rect.y - margin,
+        rect.width + 2.0 * margin,
+        rect.height + 2.0 * margin,
+    )
+}
+
+/// Strict overlap test (touching edges do not count)
+fn overlaps(a: &Rect, b: &Rect) -> bool {
+    a.x < b.x + b.width && b.x < a.x + a.width && a.y < b.y + b.height && b.y < a.y + a.height
+}
+
+/// Check if a rectangle of `size` at `at` is clear of all notes not in `ignore`
+fn is_free(
+    notebook: &Notebook,
+    at: Point2D,
+    size: (f64, f64),
+    margin: f64,
+    ignore: &HashSet<NoteId>,
+) -> bool {
+    let probe = Rect::new(at.x, at.y, size.0, size.1);
+    let padded = inflate(&probe, margin);
+    notebook
+        .notes_in_rect(padded)
+        .into_iter()
+        .filter(|note| !ignore.contains(&note.id))
+        .filter_map(Note::bounds)
+        .all(|bounds| !overlaps(&bounds, &padded))
+}
+
+/// Find the free position nearest to `near` for a note of the given size
+///
+/// Notes in `ignore` are treated as absent, so a note can be re-placed
+/// without colliding with itself. The returned point is the top-left corner.
+/// Negative and non-finite sizes and margins count as zero.
+pub fn find_free_spot(
+    notebook: &Notebook,
+    size: (f64, f64),
+    near: Point2D,
+    ignore: &HashSet<NoteId>,
+    options: &PlacementOptions,
+) -> Point2D {
+    let (w, h) = (length(size.0), length(size.1));
+    let size = (w, h);
+    let margin = length(options.margin);
+    if is_free(notebook, near, size, margin, ignore) {
+        return near;
+    }
+
+    // The nearest free spot either keeps a coordinate of `near` or sits flush
+    // against an obstacle edge, so it suffices to try every combination of
+    // those coordinates for obstacles within a growing search radius.
+    // The radius must start above zero for doubling to grow it
+    let mut radius = (w.max(h).max(margin) * 2.0).max(1.0);
+    for _ in 0..MAX_SEARCH_STEPS {
+        let region = Rect::new(
+            near.x - radius - w,
+            near.y - radius - h,
+            2.0 * (radius + w),
+            2.0 * (radius + h),
+        );
+        let obstacles: Vec<Rect> = notebook
+            .notes_in_rect(region)
+            .into_iter()
+            .filter(|note| !ignore.contains(&note.id))
+            .filter_map(Note::bounds)
+            .collect();
+
+        let mut xs = vec![near.x];
+        let mut ys = vec![near.y];
+        for ob in &obstacles {
+            xs.push(ob.x - w - margin);
+            xs.push(ob.x + ob.width + margin);
+            ys.push(ob.y - h - margin);
+            ys.push(ob.y + ob.height + margin);
+        }
+
+        let mut candidates: Vec<(f64, Point2D)> = xs
+            .iter()
+            .flat_map(|&x| ys.iter().map(move |&y| Point2D::new(x, y)))
+            .map(|p| ((p.x - near.x).hypot(p.y - near.y), p))
+            .filter(|(dist, _)| *dist <= radius)
+            .collect();
+        // Ties go below, then to the right, as new notes usually follow on
+        candidates.sort_by(|a, b| {
+            a.0.total_cmp(&b.0)
+                .then(b.1.y.total_cmp(&a.1.y))
+                .then(b.1.x.total_cmp(&a.1.x))
+        });
+
+        if let Some((_, spot)) = candidates
+            .into_iter()
+            .find(|(_, p)| is_free(notebook, *p, size, margin, ignore))
+        {
+            return spot;
+        }
+        radius *= 2.0;
+    }
+
+    // Only reached for points far outside the canvas; below every note is
+    // always free
+    let bottom = notebook
+        .all_notes()
+        .filter(|note| !ignore.contains(&note.id))
+        .filter_map(Note::bounds)
+        .map(|bounds| bounds.y + bounds.height)
+        .fold(near.y, f64::max);
+    Point2D::new(near.x, bottom + margin)
+}
+
+/// Plan moves that separate overlapping notes in a selection
+///
+/// Each pass pushes every overlapping pair apart along the axis of least
+/// penetration, splitting the distance between two selected notes and
+/// moving only the selected note when the other is outside the selection.
+/// Notes that are not placed on the canvas are ignored. Only notes that
+/// actually move are returned.
+pub fn resolve_overlaps(
+    notebook: &Notebook,
+    ids: &[NoteId],
+    options: &PlacementOptions,
+) -> Vec<Move> {
+    let margin = options.margin;
+    let mut selected: Vec<NoteId> = ids
+        .iter()
+        .copied()
+        .filter(|id| notebook.get_note(id).and_then(Note::bounds).is_some())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    selected.sort();
+    let members: HashSet<NoteId> = selected.iter().copied().collect();
+
+    let original: HashMap<NoteId, Rect> = selected
+        .iter()
+        .filter_map(|id| Some((*id, notebook.get_note(id)?.bounds()?)))
+        .collect();
+    let mut rects = original.clone();
+
+    for _ in 0..options.max_iterations {
+        let mut moved = false;
+
+        for (i, a) in selected.iter().enumerate() {
+            // Other selected notes: share the push
+            for b in &selected[i + 1..] {
+                let (ra, rb) = (rects[a], rects[b]);
+                if let Some((dx, dy)) = separation(&ra, &rb, margin) {
+                    shift(rects.get_mut(a), -dx / 2.0, -dy / 2.0);
+                    shift(rects.get_mut(b), dx / 2.0, dy / 2.0);
+                    moved = true;
+                }
+            }
+
+            // Fixed notes outside the selection: move only the selected one
+            let ra = rects[a];
+            for other in notebook.notes_in_rect(inflate(&ra, margin)) {
+                if members.contains(&other.id) {
+                    continue;
+                }
+                let Some(rb) = other.bounds() else { continue };
+                let ra = rects[a];
+                if let Some((dx, dy)) = separation(&ra, &rb, margin) {
+                    shift(rects.get_mut(a), -dx, -dy);
+                    moved = true;
+                }
+            }
+        }
+
+        if !moved {
+            break;
+        }
+    }
+
+    selected
+        .into_iter()
+        .filter(|id| rects[id] != original[id])
+        .map(|id| (id, rects[&id].min()))
+        .collect()
+}
+
+/// Minimal translation of `b` (or opposite translation of `a`) that leaves
+/// `margin` between them, or None if they are already apart
+fn separation(a: &Rect, b: &Rect, margin: f64) -> Option<(f64, f64)> {
+    let padded = inflate(a, margin);
+    if !overlaps(&padded, b) {
+        return None;
+    }
+    let (ca, cb) = (a.center(), b.center());
+
+    let push_right = padded.x + padded.width - b.x;
+    let push_left = b.x + b.width - padded.x;
+    let push_down = padded.y + padded.height - b.y;
+    let push_up = b.y + b.height - padded.y;
+
+    let dx = if cb.x >= ca.x { push_right } else { -push_left };
+    let dy = if cb.y >= ca.y { push_down } else { -push_up };
+
+    if dx.abs() <= dy.abs() {
+        Some((dx, 0.0))
+    } else {
+        Some((0.0, dy))
+    }
+}
+
+fn shift(rect: Option<&mut Rect>, dx: f64, dy: f64) {
+    if let Some(rect) = rect {
+        rect.x += dx;
+        rect.y += dy;
+    }
+}
+
+/// Plan a tidy arrangement of every note intersecting a region
+///
+/// Notes keep their reading order (top-to-bottom, then left-to-right) and
+/// are laid out in rows from the region's top-left corner, wrapping when a
+/// row would exceed the region's width. Only notes that actually move are
+/// returned.
+pub fn tidy_region(notebook: &Notebook, region: Rect, options: &PlacementOptions) -> Vec<Move> {
+    let margin = options.margin;
+    let mut notes: Vec<(NoteId, Rect)> = notebook
+        .notes_in_rect(region)
+        .into_iter()
+        .filter_map(|note| Some((note.id, note.bounds()?)))
+        .collect();
+    if notes.is_empty() {
+        return Vec::new();
+    }
+
+    // Group into rows: a note starts a new row when its top is below the
+    // middle of the current row's first note
+    notes.sort_by(|a, b| a.1.y.total_cmp(&b.1.y).then(a.1.x.total_cmp(&b.1.x)));
+    let mut rows: Vec<Vec<(NoteId, Rect)>> = Vec::new();
+    for entry in notes {
+        match rows.last_mut() {
+            Some(row) if entry.1.y < row[0].1.center().y => row.push(entry),
+            _ => rows.push(vec![entry]),
+        }
+    }
+    for row in &mut rows {
+        row.sort_by(|a, b| a.1.x.total_cmp(&b.1.x).then(a.0.cmp(&b.0)));
+    }
+
+    let mut moves = Vec::new();
+    let (mut x, mut y) = (region.x, region.y);
+    let mut row_height: f64 = 0.0;
+    for row in rows {
+        if x > region.x {
+            x = region.x;
+            y += row_height + margin;
+            row_height = 0.0;
+        }
+        for (id, rect) in row {
+            if x > region.x && x + rect.width > region.x + region.width {
+                x = region.x;
+                y += row_height + margin;
+                row_height = 0.0;
+            }
+            let target = Point2D::new(x, y);
+            if rect.min() != target {
+                moves.push((id, target));
+            }
+            x += rect.width + margin;
+            row_height = row_height.max(rect.height);
+        }
+    }
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn placed(notebook: &mut Notebook, x: f64, y: f64) -> NoteId {
+        let mut note = Note::new("Placed").with_position(x, y);
+        note.size = Some((100.0, 50.0));
+        notebook.add_note(note)
+    }
+
+    #[test]
+    fn test_free_spot_when_empty() {
+        let notebook = Notebook::new("Test");
+        let spot = find_free_spot(
+            &notebook,
+            (100.0, 50.0),
+            Point2D::origin(),
+            &HashSet::new(),
+            &PlacementOptions::default(),
+        );
+        assert_eq!(spot, Point2D::origin());
+    }
+
+    #[test]
+    fn test_free_spot_beside_obstacle() {
+        let mut notebook = Notebook::new("Test");
+        placed(&mut notebook, 0.0, 0.0);
+
+        let options = PlacementOptions::default();
+        let spot = find_free_spot(
+            &notebook,
+            (100.0, 50.0),
+            Point2D::new(10.0, 0.0),
+            &HashSet::new(),
+            &options,
+        );
+        // Nearest is directly below the obstacle (70 away) rather than to
+        // its right (110 away)
+        assert_eq!(spot, Point2D::new(10.0, 70.0));
+    }
+
+    #[test]
+    fn test_free_spot_for_degenerate_sizes() {
+        let mut notebook = Notebook::new("Test");
+        placed(&mut notebook, 0.0, 0.0);
+        let options = PlacementOptions {
+            margin: 0.0,
+            ..PlacementOptions::default()
+        };
+
+        for size in [(0.0, 0.0), (f64::NAN, f64::NAN), (-5.0, f64::INFINITY)] {
+            let spot = find_free_spot(
+                &notebook,
+                size,
+                Point2D::new(10.0, 10.0),
+                &HashSet::new(),
+                &options,
+            );
+            assert!(is_free(&notebook, spot, (0.0, 0.0), 0.0, &HashSet::new()));
+        }
+    }
+
+    #[test]
+    fn test_place_note_stacks_pasted_notes() {
+        let mut notebook = Notebook::new("Test");
+        let options = PlacementOptions::default();
+        let mut spots = Vec::new();
+        for _ in 0..5 {
+            let id = placed(&mut notebook, 0.0, 0.0);
+            spots.push(
+                notebook
+                    .place_note(&id, Point2D::origin(), &options)
+                    .unwrap(),
+            );
+        }
+
+        let rects: Vec<Rect> = spots
+            .iter()
+            .map(|p| Rect::new(p.x, p.y, 100.0, 50.0))
+            .collect();
+        for (i, a) in rects.iter().enumerate() {
+            for b in &rects[i + 1..] {
+                assert!(!overlaps(a, b), "{a:?} overlaps {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_resolve_overlaps() {
+        let mut notebook = Notebook::new("Test");
+        let a = placed(&mut notebook, 0.0, 0.0);
+        let b = placed(&mut notebook, 10.0, 5.0);
+        let fixed = placed(&mut notebook, 500.0, 500.0);
+
+        let options = PlacementOptions::default();
+        let moves = resolve_overlaps(&notebook, &[a, b], &options);
+        assert_eq!(moves.len(), 2);
+        notebook.move_notes(&moves).unwrap();
+
+        let ra = notebook.get_note(&a).unwrap().bounds().unwrap();
+        let rb = notebook.get_note(&b).unwrap().bounds().unwrap();
+        assert!(!overlaps(&inflate(&ra, options.margin - 1e-9), &rb));
+        // Vertical penetration is smaller, so the pair splits vertically
+        assert_eq!(ra.x, 0.0);
+        assert_eq!(rb.x, 10.0);
+        assert_eq!(
+            notebook.get_note(&fixed).unwrap().position,
+            Some(Point2D::new(500.0, 500.0))
+        );
+    }
+
+    #[test]
+    fn test_resolve_overlaps_pushes_off_fixed_notes() {
+        let mut notebook = Notebook::new("Test");
+        let fixed = placed(&mut notebook, 0.0, 0.0);
+        let moving = placed(&mut notebook, 0.0, 40.0);
+
+        let moves = resolve_overlaps(&notebook, &[moving], &PlacementOptions::default());
+        assert_eq!(moves, vec![(moving, Point2D::new(0.0, 70.0))]);
+        assert!(!moves.iter().any(|(id, _)| *id == fixed));
+    }
+
+    #[test]
+    fn test_tidy_region() {
+        let mut notebook = Notebook::new("Test");
+        let first = placed(&mut notebook, 12.0, 3.0);
+        let second = placed(&mut notebook, 150.0, 18.0);
+        let third = placed(&mut notebook, 40.0, 90.0);
+
+        let region = Rect::new(0.0, 0.0, 250.0, 400.0);
+        let moves = tidy_region(&notebook, region, &PlacementOptions::default());
+        notebook.move_notes(&moves).unwrap();
+
+        let pos = |id: &NoteId| notebook.get_note(id).unwrap().position.unwrap();
+        assert_eq!(pos(&first), Point2D::new(0.0, 0.0));
+        assert_eq!(pos(&second), Point2D::new(120.0, 0.0));
+        assert_eq!(pos(&third), Point2D::new(0.0, 70.0));
+    }
+}
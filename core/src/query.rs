@@ -0,0 +1,1238 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Query language - structured note queries for searches and agents
+//!
+//! A query is a sequence of terms, combined with `AND` (the default when
+//! terms are just written one after another), `OR` and `NOT` (or a leading
+//! `-`), with parentheses for grouping. `NOT` binds tightest and `OR`
+//! loosest. Operators must be written in capitals; lowercase `and`, `or`
+//! and `not` are searched for as words.
+//!
+//! Terms:
+//!
+//! - `budget`, `"quarterly budget"` - text in the title or content
+//!   (normalized substring, as in [`Notebook::search`])
+//! - `title:plan`, `content:"next steps"` - text in one field
+//! - `status=done`, `priority>2`, `due<=2024-06-30`, `owner!=me` -
+//!   attribute comparisons; values are numbers, `true`/`false` or text
+//! - `has:status` - the attribute is set
+//! - `created:2024-01-01..2024-03-31`, `modified:>=-7d`,
+//!   `created_at<2024-01-01` - date ranges; dates are `YYYY-MM-DD`, RFC
+//!   3339 times, `today`, `yesterday` or `-N` days/weeks ago (`-7d`, `-2w`)
+//! - `links-to:"Project Plan"`, `linked-from:Index` - links to or from a
+//!   note given by title or ID
+//! - `prototype:Task` - the note's prototype chain includes the given note
+
+use crate::note::{Note, NoteId};
+use crate::notebook::Notebook;
+use crate::text_index::{self, SearchField};
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+use uuid::Uuid;
+
+/// Errors that can occur when parsing a query
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum QueryError {
+    #[error("Unexpected end of query: expected {expected}")]
+    UnexpectedEnd { expected: &'static str },
+
+    #[error("Unexpected '{found}' at position {position}: expected {expected}")]
+    Unexpected {
+        found: String,
+        position: usize,
+        expected: &'static str,
+    },
+
+    #[error("Unterminated quote starting at position {position}")]
+    UnterminatedQuote { position: usize },
+
+    #[error("Missing ')' for the '(' at position {position}")]
+    UnclosedGroup { position: usize },
+
+    #[error("Unknown field '{field}' at position {position}{}", did_you_mean(.suggestion))]
+    UnknownField {
+        field: String,
+        position: usize,
+        suggestion: Option<&'static str>,
+    },
+
+    #[error("Missing value after '{field}' at position {position}")]
+    MissingValue { field: String, position: usize },
+
+    #[error(
+        "Invalid date '{value}' at position {position}: expected YYYY-MM-DD, an RFC 3339 \
+         time, today, yesterday or a relative date like -7d"
+    )]
+    InvalidDate { value: String, position: usize },
+
+    #[error("Operator '{operator}' at position {position} cannot be used with dates")]
+    InvalidDateOperator { operator: String, position: usize },
+}
+
+fn did_you_mean(suggestion: &Option<&'static str>) -> String {
+    match suggestion {
+        Some(field) => format!(" (did you mean '{field}'?)"),
+        None => String::new(),
+    }
+}
+
+/// Fields that can be written as `field:value`
+const FIELDS: &[&str] = &[
+    "title",
+    "content",
+    "has",
+    "created",
+    "created_at",
+    "modified",
+    "modified_at",
+    "links-to",
+    "linked-from",
+    "prototype",
+];
+
+/// Note field searched by a text term
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextField {
+    Title,
+    Content,
+    Any,
+}
+
+/// Note timestamp used by a date term
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateField {
+    Created,
+    Modified,
+}
+
+/// Attribute comparison operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompareOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            CompareOp::Eq => "=",
+            CompareOp::Ne => "!=",
+            CompareOp::Lt => "<",
+            CompareOp::Le => "<=",
+            CompareOp::Gt => ">",
+            CompareOp::Ge => ">=",
+        }
+    }
+}
+
+/// A date as written in a query
+///
+/// Relative dates are kept as written and resolved each time the query
+/// runs, so stored queries such as `modified:>=-7d` stay current.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateValue {
+    /// A whole calendar day (UTC)
+    Day(NaiveDate),
+    /// A moment, covering one second
+    Time(DateTime<Utc>),
+    /// The whole day this many days before today
+    DaysAgo(i64),
+}
+
+impl DateValue {
+    /// The half-open span of time the date covers
+    ///
+    /// Spans beyond the representable range are clamped to it.
+    pub fn span(&self, now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+        let after = |start: DateTime<Utc>, length: Duration| {
+            start
+                .checked_add_signed(length)
+                .unwrap_or(DateTime::<Utc>::MAX_UTC)
+        };
+        let day = |date: NaiveDate| {
+            let start = Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap_or_default());
+            (start, after(start, Duration::days(1)))
+        };
+        match self {
+            DateValue::Day(date) => day(*date),
+            DateValue::Time(time) => (*time, after(*time, Duration::seconds(1))),
+            DateValue::DaysAgo(days) => day(days_before(now.date_naive(), *days).unwrap_or(
+                if *days > 0 {
+                    NaiveDate::MIN
+                } else {
+                    NaiveDate::MAX
+                },
+            )),
+        }
+    }
+}
+
+/// The date a number of days before another, if representable
+fn days_before(date: NaiveDate, days: i64) -> Option<NaiveDate> {
+    Duration::try_days(days).and_then(|ago| date.checked_sub_signed(ago))
+}
+
+/// Which end of a date's span a range bound uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanEdge {
+    Start,
+    End,
+}
+
+/// Date range as written; None means unbounded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateRange {
+    pub start: Option<(DateValue, SpanEdge)>,
+    pub end: Option<(DateValue, SpanEdge)>,
+}
+
+impl DateRange {
+    /// Half-open time range at the given moment
+    pub fn resolve(&self, now: DateTime<Utc>) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+        let edge = |(value, edge): (DateValue, SpanEdge)| {
+            let (start, end) = value.span(now);
+            match edge {
+                SpanEdge::Start => start,
+                SpanEdge::End => end,
+            }
+        };
+        (self.start.map(edge), self.end.map(edge))
+    }
+}
+
+/// Parsed query expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// Matches every note (the empty query)
+    All,
+    Text {
+        field: TextField,
+        text: String,
+    },
+    Compare {
+        key: String,
+        op: CompareOp,
+        value: serde_json::Value,
+    },
+    Has(String),
+    Date {
+        field: DateField,
+        range: DateRange,
+    },
+    /// Notes linking to the referenced notes
+    LinksTo(String),
+    /// Notes linked from the referenced notes
+    LinkedFrom(String),
+    /// Notes whose prototype chain includes the referenced notes
+    Prototype(String),
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+}
+
+/// A parsed query, kept with its source text
+///
+/// Serializes as the source text, so stored queries stay readable and are
+/// re-parsed on load.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Query {
+    source: String,
+    expr: Expr,
+}
+
+impl Query {
+    /// Parse a query
+    pub fn parse(source: &str) -> Result<Self, QueryError> {
+        let expr = Parser::new(source)?.parse()?;
+        Ok(Self {
+            source: source.to_string(),
+            expr,
+        })
+    }
+
+    /// The text the query was parsed from
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The parsed expression
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+
+    /// Resolve note references and text terms against a notebook
+    pub(crate) fn compile(&self, notebook: &Notebook) -> CompiledQuery {
+        CompiledQuery(compile(&self.expr, notebook, true))
+    }
+
+    /// Like [`Query::compile`], but checking text terms on each note
+    /// directly; cheaper when only a few notes will be matched
+    pub(crate) fn compile_direct(&self, notebook: &Notebook) -> CompiledQuery {
+        CompiledQuery(compile(&self.expr, notebook, false))
+    }
+
+    /// Check if matching a note depends only on that note, so that
+    /// results can be updated by re-checking just the notes that changed
+    ///
+    /// Link and prototype terms depend on other notes, and relative dates
+    /// on the current day.
+    pub(crate) fn is_local(&self) -> bool {
+        fn local(expr: &Expr) -> bool {
+            match expr {
+                Expr::LinksTo(_) | Expr::LinkedFrom(_) | Expr::Prototype(_) => false,
+                Expr::Date { range, .. } => [range.start, range.end]
+                    .iter()
+                    .flatten()
+                    .all(|(value, _)| !matches!(value, DateValue::DaysAgo(_))),
+                Expr::Not(inner) => local(inner),
+                Expr::And(terms) | Expr::Or(terms) => terms.iter().all(local),
+                _ => true,
+            }
+        }
+        local(&self.expr)
+    }
+
+    /// Notes in the notebook matching the query, ordered by title
+    pub fn run<'a>(&self, notebook: &'a Notebook) -> Vec<&'a Note> {
+        let compiled = self.compile(notebook);
+        let mut notes: Vec<&Note> = notebook
+            .all_notes()
+            .filter(|note| compiled.matches(note, notebook))
+            .collect();
+        notes.sort_by(|a, b| a.title.cmp(&b.title).then(a.id.cmp(&b.id)));
+        notes
+    }
+}
+
+impl FromStr for Query {
+    type Err = QueryError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        Self::parse(source)
+    }
+}
+
+impl TryFrom<String> for Query {
+    type Error = QueryError;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        Self::parse(&source)
+    }
+}
+
+impl From<Query> for String {
+    fn from(query: Query) -> Self {
+        query.source
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Lexer
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Open,
+    Close,
+    /// A run of text; `quote_at` is the length of the unquoted prefix if
+    /// part of the word was quoted
+    Word {
+        text: String,
+        quote_at: Option<usize>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    kind: TokenKind,
+    position: usize,
+}
+
+fn lex(source: &str) -> Result<Vec<Token>, QueryError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c == '(' || c == ')' {
+            tokens.push(Token {
+                kind: if c == '(' {
+                    TokenKind::Open
+                } else {
+                    TokenKind::Close
+                },
+                position: i,
+            });
+            i += 1;
+            continue;
+        }
+
+        let position = i;
+        let mut text = String::new();
+        let mut quote_at = None;
+        while i < chars.len() {
+            let c = chars[i];
+            if c == '"' {
+                let start = i;
+                quote_at.get_or_insert(text.chars().count());
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    text.push(chars[i]);
+                    i += 1;
+                }
+                if i == chars.len() {
+                    return Err(QueryError::UnterminatedQuote { position: start });
+                }
+                i += 1;
+            } else if c.is_whitespace() || c == '(' || c == ')' {
+                break;
+            } else {
+                text.push(c);
+                i += 1;
+            }
+        }
+        tokens.push(Token {
+            kind: TokenKind::Word { text, quote_at },
+            position,
+        });
+    }
+    Ok(tokens)
+}
+
+// ---------------------------------------------------------------------------
+// Parser
+
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Parser {
+    fn new(source: &str) -> Result<Self, QueryError> {
+        Ok(Self {
+            tokens: lex(source)?,
+            next: 0,
+        })
+    }
+
+    fn parse(mut self) -> Result<Expr, QueryError> {
+        if self.tokens.is_empty() {
+            return Ok(Expr::All);
+        }
+        let expr = self.parse_or()?;
+        match self.tokens.get(self.next) {
+            None => Ok(expr),
+            Some(token) => Err(QueryError::Unexpected {
+                found: describe(token),
+                position: token.position,
+                expected: "a term or operator",
+            }),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    fn peek_operator(&self, operator: &str) -> bool {
+        matches!(
+            self.peek(),
+            Some(Token { kind: TokenKind::Word { text, quote_at: None }, .. }) if text == operator
+        )
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, QueryError> {
+        let mut terms = vec![self.parse_and()?];
+        while self.peek_operator("OR") {
+            self.next += 1;
+            terms.push(self.parse_and()?);
+        }
+        Ok(if terms.len() == 1 {
+            terms.remove(0)
+        } else {
+            Expr::Or(terms)
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, QueryError> {
+        let mut terms = vec![self.parse_unary()?];
+        loop {
+            if self.peek_operator("AND") {
+                self.next += 1;
+            } else if self.peek_operator("OR")
+                || matches!(
+                    self.peek(),
+                    None | Some(Token {
+                        kind: TokenKind::Close,
+                        ..
+                    })
+                )
+            {
+                break;
+            }
+            terms.push(self.parse_unary()?);
+        }
+        Ok(if terms.len() == 1 {
+            terms.remove(0)
+        } else {
+            Expr::And(terms)
+        })
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, QueryError> {
+        if self.peek_operator("NOT") {
+            self.next += 1;
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, QueryError> {
+        let Some(token) = self.tokens.get(self.next).cloned() else {
+            return Err(QueryError::UnexpectedEnd { expected: "a term" });
+        };
+        self.next += 1;
+        match token.kind {
+            TokenKind::Open => {
+                let expr = self.parse_or()?;
+                match self.peek() {
+                    Some(Token {
+                        kind: TokenKind::Close,
+                        ..
+                    }) => {
+                        self.next += 1;
+                        Ok(expr)
+                    }
+                    _ => Err(QueryError::UnclosedGroup {
+                        position: token.position,
+                    }),
+                }
+            }
+            TokenKind::Close => Err(QueryError::Unexpected {
+                found: ")".into(),
+                position: token.position,
+                expected: "a term",
+            }),
+            TokenKind::Word { text, quote_at } => {
+                if matches!(text.as_str(), "AND" | "OR") && quote_at.is_none() {
+                    return Err(QueryError::Unexpected {
+                        found: text,
+                        position: token.position,
+                        expected: "a term",
+                    });
+                }
+                parse_term(&text, quote_at, token.position)
+            }
+        }
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match &token.kind {
+        TokenKind::Open => "(".into(),
+        TokenKind::Close => ")".into(),
+        TokenKind::Word { text, .. } => text.clone(),
+    }
+}
+
+/// Parse a single word into a term
+fn parse_term(text: &str, quote_at: Option<usize>, position: usize) -> Result<Expr, QueryError> {
+    let chars: Vec<char> = text.chars().collect();
+    // Only the part before any quote can hold syntax
+    let syntax_len = quote_at.unwrap_or(chars.len());
+
+    // A quote straight after the '-' still negates: -"two words"
+    if chars.len() > 1 && chars[0] == '-' && syntax_len >= 1 {
+        let rest: String = chars[1..].iter().collect();
+        let inner = parse_term(&rest, quote_at.map(|q| q - 1), position + 1)?;
+        return Ok(Expr::Not(Box::new(inner)));
+    }
+
+    // Find the first ':' or comparison operator in the unquoted prefix
+    let mut split = None;
+    for (i, c) in chars[..syntax_len].iter().enumerate() {
+        match c {
+            ':' => {
+                split = Some((i, 1, None));
+                break;
+            }
+            '=' => {
+                split = Some((i, 1, Some(CompareOp::Eq)));
+                break;
+            }
+            '!' | '<' | '>' => {
+                let two = chars.get(i + 1) == Some(&'=') && i + 1 < syntax_len;
+                let op = match (c, two) {
+                    ('!', true) => CompareOp::Ne,
+                    ('!', false) => continue,
+                    ('<', true) => CompareOp::Le,
+                    ('<', false) => CompareOp::Lt,
+                    ('>', true) => CompareOp::Ge,
+                    _ => CompareOp::Gt,
+                };
+                split = Some((i, if two { 2 } else { 1 }, Some(op)));
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    let Some((at, len, op)) = split.filter(|(at, _, _)| *at > 0) else {
+        return Ok(Expr::Text {
+            field: TextField::Any,
+            text: text.to_string(),
+        });
+    };
+
+    let key: String = chars[..at].iter().collect();
+    let value: String = chars[at + len..].iter().collect();
+    let value_position = position + at + len;
+    let quoted = quote_at.is_some();
+    if value.is_empty() && !quoted {
+        return Err(QueryError::MissingValue {
+            field: key,
+            position,
+        });
+    }
+
+    let date_field = match key.as_str() {
+        "created" | "created_at" => Some(DateField::Created),
+        "modified" | "modified_at" => Some(DateField::Modified),
+        _ => None,
+    };
+
+    match op {
+        // Comparison: attribute or date
+        Some(op) => match date_field {
+            Some(field) => {
+                let range = compare_range(op, &value, value_position)?;
+                Ok(Expr::Date { field, range })
+            }
+            None => Ok(Expr::Compare {
+                key,
+                op,
+                value: parse_value(&value, quoted),
+            }),
+        },
+        // Field
+        None => {
+            if let Some(field) = date_field {
+                return Ok(Expr::Date {
+                    field,
+                    range: parse_date_range(&value, value_position)?,
+                });
+            }
+            match key.as_str() {
+                "title" => Ok(Expr::Text {
+                    field: TextField::Title,
+                    text: value,
+                }),
+                "content" => Ok(Expr::Text {
+                    field: TextField::Content,
+                    text: value,
+                }),
+                "has" => Ok(Expr::Has(value)),
+                "links-to" => Ok(Expr::LinksTo(value)),
+                "linked-from" => Ok(Expr::LinkedFrom(value)),
+                "prototype" => Ok(Expr::Prototype(value)),
+                _ => Err(QueryError::UnknownField {
+                    suggestion: closest_field(&key),
+                    field: key,
+                    position,
+                }),
+            }
+        }
+    }
+}
+
+/// Interpret an attribute value: numbers and booleans unless quoted
+fn parse_value(value: &str, quoted: bool) -> serde_json::Value {
+    if !quoted {
+        if let Ok(number) = value.parse::<f64>() {
+            if let Some(number) = serde_json::Number::from_f64(number) {
+                return serde_json::Value::Number(number);
+            }
+        }
+        match value {
+            "true" => return serde_json::Value::Bool(true),
+            "false" => return serde_json::Value::Bool(false),
+            _ => {}
+        }
+    }
+    serde_json::Value::String(value.to_string())
+}
+
+/// Known field closest to a misspelled one, if any is close enough
+fn closest_field(field: &str) -> Option<&'static str> {
+    FIELDS
+        .iter()
+        .map(|candidate| (edit_distance(field, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = (previous + usize::from(ca != *cb))
+                .min(row[j] + 1)
+                .min(current + 1);
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+// ---------------------------------------------------------------------------
+// Dates
+
+/// Parse a date value
+fn parse_date(value: &str, position: usize) -> Result<DateValue, QueryError> {
+    let invalid = || QueryError::InvalidDate {
+        value: value.to_string(),
+        position,
+    };
+    match value {
+        "today" => return Ok(DateValue::DaysAgo(0)),
+        "yesterday" => return Ok(DateValue::DaysAgo(1)),
+        _ => {}
+    }
+    if let Some(relative) = value.strip_prefix('-') {
+        let (amount, unit_days) = if let Some(days) = relative.strip_suffix('d') {
+            (days, 1)
+        } else if let Some(weeks) = relative.strip_suffix('w') {
+            (weeks, 7)
+        } else {
+            return Err(invalid());
+        };
+        // Refuse amounts reaching outside the calendar
+        return amount
+            .parse::<i64>()
+            .ok()
+            .and_then(|amount| amount.checked_mul(unit_days))
+            .filter(|days| days_before(Utc::now().date_naive(), *days).is_some())
+            .map(DateValue::DaysAgo)
+            .ok_or_else(invalid);
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(DateValue::Day(date));
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(DateValue::Time(time.with_timezone(&Utc)));
+    }
+    Err(invalid())
+}
+
+/// Range for `field:value`: a date, `a..b`, `a..`, `..b` or a comparison
+fn parse_date_range(value: &str, position: usize) -> Result<DateRange, QueryError> {
+    for (prefix, op) in [
+        (">=", CompareOp::Ge),
+        ("<=", CompareOp::Le),
+        (">", CompareOp::Gt),
+        ("<", CompareOp::Lt),
+    ] {
+        if let Some(rest) = value.strip_prefix(prefix) {
+            return compare_range(op, rest, position + prefix.len());
+        }
+    }
+
+    if let Some((from, to)) = value.split_once("..") {
+        let start = if from.is_empty() {
+            None
+        } else {
+            Some((parse_date(from, position)?, SpanEdge::Start))
+        };
+        let end = if to.is_empty() {
+            None
+        } else {
+            let position = position + from.chars().count() + 2;
+            Some((parse_date(to, position)?, SpanEdge::End))
+        };
+        return Ok(DateRange { start, end });
+    }
+
+    compare_range(CompareOp::Eq, value, position)
+}
+
+/// Range for a date comparison such as `>=2024-01-01`
+fn compare_range(op: CompareOp, value: &str, position: usize) -> Result<DateRange, QueryError> {
+    let date = parse_date(value, position)?;
+    let (start, end) = match op {
+        CompareOp::Eq => (Some((date, SpanEdge::Start)), Some((date, SpanEdge::End))),
+        CompareOp::Gt => (Some((date, SpanEdge::End)), None),
+        CompareOp::Ge => (Some((date, SpanEdge::Start)), None),
+        CompareOp::Lt => (None, Some((date, SpanEdge::Start))),
+        CompareOp::Le => (None, Some((date, SpanEdge::End))),
+        CompareOp::Ne => {
+            return Err(QueryError::InvalidDateOperator {
+                operator: op.symbol().into(),
+                position,
+            })
+        }
+    };
+    Ok(DateRange { start, end })
+}
+
+// ---------------------------------------------------------------------------
+// Evaluation
+
+/// A query with note references and text terms resolved against a notebook
+#[derive(Debug, Clone)]
+pub(crate) struct CompiledQuery(Compiled);
+
+#[derive(Debug, Clone)]
+enum Compiled {
+    All,
+    Ids(HashSet<NoteId>),
+    Text {
+        field: SearchField,
+        text: String,
+    },
+    Compare {
+        key: String,
+        op: CompareOp,
+        value: serde_json::Value,
+    },
+    Has(String),
+    Date {
+        field: DateField,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+    },
+    LinksTo(HashSet<NoteId>),
+    LinkedFrom(HashSet<NoteId>),
+    Prototype(HashSet<NoteId>),
+    Not(Box<Compiled>),
+    And(Vec<Compiled>),
+    Or(Vec<Compiled>),
+}
+
+impl CompiledQuery {
+    /// Check a single note
+    pub(crate) fn matches(&self, note: &Note, notebook: &Notebook) -> bool {
+        self.0.matches(note, notebook)
+    }
+}
+
+/// Notes referred to by title (case-insensitive) or ID
+fn resolve(reference: &str, notebook: &Notebook) -> HashSet<NoteId> {
+    if let Ok(id) = Uuid::parse_str(reference) {
+        if notebook.get_note(&id).is_some() {
+            return HashSet::from([id]);
+        }
+    }
+    let lower = reference.to_lowercase();
+    notebook
+        .search_by_title(reference)
+        .into_iter()
+        .filter(|note| note.title.to_lowercase() == lower)
+        .map(|note| note.id)
+        .collect()
+}
+
+fn compile(expr: &Expr, notebook: &Notebook, bulk: bool) -> Compiled {
+    match expr {
+        Expr::All => Compiled::All,
+        Expr::Text { field, text } if !bulk => Compiled::Text {
+            field: match field {
+                TextField::Title => SearchField::Title,
+                TextField::Content => SearchField::Content,
+                TextField::Any => SearchField::Any,
+            },
+            text: text.clone(),
+        },
+        Expr::Text { field, text } => {
+            let notes = match field {
+                TextField::Title => notebook.search_by_title(text),
+                TextField::Content => notebook.search_by_content(text),
+                TextField::Any => notebook.search(text),
+            };
+            Compiled::Ids(notes.into_iter().map(|note| note.id).collect())
+        }
+        Expr::Compare { key, op, value } => Compiled::Compare {
+            key: key.clone(),
+            op: *op,
+            value: value.clone(),
+        },
+        Expr::Has(key) => Compiled::Has(key.clone()),
+        Expr::Date { field, range } => {
+            let (start, end) = range.resolve(Utc::now());
+            Compiled::Date {
+                field: *field,
+                start,
+                end,
+            }
+        }
+        Expr::LinksTo(reference) => Compiled::LinksTo(resolve(reference, notebook)),
+        Expr::LinkedFrom(reference) => Compiled::LinkedFrom(resolve(reference, notebook)),
+        Expr::Prototype(reference) => Compiled::Prototype(resolve(reference, notebook)),
+        Expr::Not(inner) => Compiled::Not(Box::new(compile(inner, notebook, bulk))),
+        Expr::And(terms) => {
+            Compiled::And(terms.iter().map(|t| compile(t, notebook, bulk)).collect())
+        }
+        Expr::Or(terms) => Compiled::Or(terms.iter().map(|t| compile(t, notebook, bulk)).collect()),
+    }
+}
+
+impl Compiled {
+    fn matches(&self, note: &Note, notebook: &Notebook) -> bool {
+        match self {
+            Compiled::All => true,
+            Compiled::Ids(ids) => ids.contains(&note.id),
+            Compiled::Text { field, text } => {
+                text_index::note_matches(notebook.analyzer(), note, text, *field)
+            }
+            Compiled::Compare { key, op, value } => {
+                let found = note.get_attribute(key);
+                match op {
+                    CompareOp::Eq => {
+                        found.is_some_and(|v| any_element(v, |v| values_equal(v, value)))
+                    }
+                    CompareOp::Ne => {
+                        !found.is_some_and(|v| any_element(v, |v| values_equal(v, value)))
+                    }
+                    _ => found.is_some_and(|v| {
+                        any_element(v, |v| {
+                            compare_values(v, value).is_some_and(|ordering| match op {
+                                CompareOp::Lt => ordering == Ordering::Less,
+                                CompareOp::Le => ordering != Ordering::Greater,
+                                CompareOp::Gt => ordering == Ordering::Greater,
+                                _ => ordering != Ordering::Less,
+                            })
+                        })
+                    }),
+                }
+            }
+            Compiled::Has(key) => note.attributes.contains_key(key),
+            Compiled::Date { field, start, end } => {
+                let time = match field {
+                    DateField::Created => note.created_at,
+                    DateField::Modified => note.modified_at,
+                };
+                start.is_none_or(|start| time >= start) && end.is_none_or(|end| time < end)
+            }
+            Compiled::LinksTo(targets) => note.links.iter().any(|id| targets.contains(id)),
+            Compiled::LinkedFrom(sources) => sources.iter().any(|source| {
+                notebook
+                    .get_note(source)
+                    .is_some_and(|source| source.links_to(&note.id))
+            }),
+            Compiled::Prototype(prototypes) => {
+                // Follow the chain, guarding against cycles
+                let mut seen = HashSet::new();
+                let mut current = note.prototype;
+                while let Some(id) = current {
+                    if prototypes.contains(&id) {
+                        return true;
+                    }
+                    if !seen.insert(id) {
+                        break;
+                    }
+                    current = notebook.get_note(&id).and_then(|proto| proto.prototype);
+                }
+                false
+            }
+            Compiled::Not(inner) => !inner.matches(note, notebook),
+            Compiled::And(terms) => terms.iter().all(|t| t.matches(note, notebook)),
+            Compiled::Or(terms) => terms.iter().any(|t| t.matches(note, notebook)),
+        }
+    }
+}
+
+/// Apply a test to a value, or to each element of an array value
+fn any_element(value: &serde_json::Value, test: impl Fn(&serde_json::Value) -> bool) -> bool {
+    match value {
+        serde_json::Value::Array(items) => items.iter().any(test),
+        value => test(value),
+    }
+}
+
+/// Numeric value of a number or numeric string
+fn as_number(value: &serde_json::Value) -> Option<f64> {
+    match value {
+        serde_json::Value::Number(number) => number.as_f64(),
+        serde_json::Value::String(text) => text.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Equality with numbers compared numerically and text ignoring case
+fn values_equal(attribute: &serde_json::Value, wanted: &serde_json::Value) -> bool {
+    use serde_json::Value;
+    match (attribute, wanted) {
+        (Value::String(a), Value::String(b)) => a.to_lowercase() == b.to_lowercase(),
+        (_, Value::Number(_)) => as_number(attribute) == as_number(wanted),
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::String(a), Value::Bool(b)) => a.eq_ignore_ascii_case(&b.to_string()),
+        _ => false,
+    }
+}
+
+/// Ordering for `<`/`>`: numeric when both sides are numbers, otherwise
+/// text ignoring case (so ISO dates compare correctly)
+fn compare_values(attribute: &serde_json::Value, wanted: &serde_json::Value) -> Option<Ordering> {
+    if let (Some(a), Some(b)) = (as_number(attribute), as_number(wanted)) {
+        return a.partial_cmp(&b);
+    }
+    match (attribute, wanted) {
+        (serde_json::Value::String(a), serde_json::Value::String(b)) => {
+            Some(a.to_lowercase().cmp(&b.to_lowercase()))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn titles(notebook: &Notebook, query: &str) -> Vec<String> {
+        Query::parse(query)
+            .unwrap()
+            .run(notebook)
+            .iter()
+            .map(|note| note.title.clone())
+            .collect()
+    }
+
+    fn sample() -> Notebook {
+        let mut notebook = Notebook::new("Test");
+        let task = notebook.create_note("Task");
+        let bug = notebook.create_note("Bug");
+        notebook.get_note_mut(&bug).unwrap().prototype = Some(task);
+
+        let mut plan = Note::new("Project Plan");
+        plan.content = "Quarterly budget and milestones".into();
+        plan.set_attribute("status", json!("done"));
+        plan.set_attribute("priority", json!(3));
+        plan.set_attribute("tags", json!(["work", "planning"]));
+        plan.prototype = Some(task);
+        let plan = notebook.add_note(plan);
+
+        let mut crash = Note::new("Crash on save");
+        crash.content = "Happens with the quarterly report".into();
+        crash.set_attribute("status", json!("open"));
+        crash.set_attribute("priority", json!("1"));
+        crash.prototype = Some(bug);
+        crash.created_at = Utc.with_ymd_and_hms(2024, 2, 10, 12, 0, 0).unwrap();
+        let crash = notebook.add_note(crash);
+
+        notebook.link_notes(crash, plan).unwrap();
+        notebook
+    }
+
+    #[test]
+    fn test_text_and_phrases() {
+        let notebook = sample();
+        assert_eq!(
+            titles(&notebook, "quarterly"),
+            vec!["Crash on save", "Project Plan"]
+        );
+        assert_eq!(
+            titles(&notebook, "\"quarterly budget\""),
+            vec!["Project Plan"]
+        );
+        assert_eq!(titles(&notebook, "title:plan"), vec!["Project Plan"]);
+        assert_eq!(
+            titles(&notebook, "content:\"the quarterly\""),
+            vec!["Crash on save"]
+        );
+    }
+
+    #[test]
+    fn test_attribute_comparisons() {
+        let notebook = sample();
+        assert_eq!(titles(&notebook, "status=DONE"), vec!["Project Plan"]);
+        assert_eq!(titles(&notebook, "priority>2"), vec!["Project Plan"]);
+        // Numeric strings compare as numbers
+        assert_eq!(titles(&notebook, "priority<=1"), vec!["Crash on save"]);
+        assert_eq!(titles(&notebook, "tags=planning"), vec!["Project Plan"]);
+        assert_eq!(
+            titles(&notebook, "status!=done"),
+            vec!["Bug", "Crash on save", "Task"]
+        );
+        assert_eq!(titles(&notebook, "has:tags"), vec!["Project Plan"]);
+    }
+
+    #[test]
+    fn test_dates_links_and_prototypes() {
+        let notebook = sample();
+        assert_eq!(
+            titles(&notebook, "created:2024-02-01..2024-02-10"),
+            vec!["Crash on save"]
+        );
+        assert_eq!(
+            titles(&notebook, "created_at<2024-02-10"),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            titles(&notebook, "created:<=2024-02-10 status=open").len(),
+            1
+        );
+        assert_eq!(titles(&notebook, "modified:>=-1d").len(), 4);
+        assert_eq!(
+            Query::parse("modified:>=-2w").unwrap().expr(),
+            &Expr::Date {
+                field: DateField::Modified,
+                range: DateRange {
+                    start: Some((DateValue::DaysAgo(14), SpanEdge::Start)),
+                    end: None,
+                },
+            }
+        );
+        // Spans past the calendar's ends are clamped
+        let now = Utc::now();
+        for value in [
+            DateValue::DaysAgo(i64::MAX),
+            DateValue::DaysAgo(i64::MIN),
+            DateValue::Day(NaiveDate::MAX),
+            DateValue::Time(DateTime::<Utc>::MAX_UTC),
+        ] {
+            let (start, end) = value.span(now);
+            assert!(start <= end);
+        }
+
+        assert_eq!(
+            titles(&notebook, "links-to:\"project plan\""),
+            vec!["Crash on save"]
+        );
+        assert_eq!(
+            titles(&notebook, "linked-from:\"Crash on save\""),
+            vec!["Project Plan"]
+        );
+        // Inherited through Bug
+        assert_eq!(
+            titles(&notebook, "prototype:Task"),
+            vec!["Bug", "Crash on save", "Project Plan"]
+        );
+    }
+
+    #[test]
+    fn test_boolean_operators() {
+        let notebook = sample();
+        assert_eq!(
+            titles(&notebook, "prototype:Task AND NOT status=done"),
+            vec!["Bug", "Crash on save"]
+        );
+        assert_eq!(
+            titles(&notebook, "(status=done OR status=open) -crash"),
+            vec!["Project Plan"]
+        );
+        assert_eq!(titles(&notebook, "title:task OR title:bug").len(), 2);
+        assert_eq!(
+            Query::parse("-\"quarterly budget\"").unwrap().expr(),
+            &Expr::Not(Box::new(Expr::Text {
+                field: TextField::Any,
+                text: "quarterly budget".into()
+            }))
+        );
+        assert_eq!(titles(&notebook, "-\"crash on\"").len(), 3);
+        // NOT binds tighter than AND, AND tighter than OR
+        assert_eq!(
+            Query::parse("a OR b c").unwrap().expr(),
+            &Expr::Or(vec![
+                Expr::Text {
+                    field: TextField::Any,
+                    text: "a".into()
+                },
+                Expr::And(vec![
+                    Expr::Text {
+                        field: TextField::Any,
+                        text: "b".into()
+                    },
+                    Expr::Text {
+                        field: TextField::Any,
+                        text: "c".into()
+                    },
+                ]),
+            ])
+        );
+        assert_eq!(titles(&notebook, "").len(), 4);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Query::parse("titel:plan"),
+            Err(QueryError::UnknownField {
+                field: "titel".into(),
+                position: 0,
+                suggestion: Some("title"),
+            })
+        );
+        assert_eq!(
+            Query::parse("a \"open").unwrap_err(),
+            QueryError::UnterminatedQuote { position: 2 }
+        );
+        assert_eq!(
+            Query::parse("(a OR b").unwrap_err(),
+            QueryError::UnclosedGroup { position: 0 }
+        );
+        assert!(matches!(
+            Query::parse("a )"),
+            Err(QueryError::Unexpected { position: 2, .. })
+        ));
+        assert!(matches!(
+            Query::parse("a AND"),
+            Err(QueryError::UnexpectedEnd { .. })
+        ));
+        assert_eq!(
+            Query::parse("modified:>=last-week").unwrap_err(),
+            QueryError::InvalidDate {
+                value: "last-week".into(),
+                position: 11,
+            }
+        );
+        assert!(matches!(
+            Query::parse("status="),
+            Err(QueryError::MissingValue { .. })
+        ));
+        for value in ["-7é", "-99999999999d", "-9223372036854775807w", "-d"] {
+            assert!(
+                matches!(
+                    Query::parse(&format!("modified:>={value}")),
+                    Err(QueryError::InvalidDate { .. })
+                ),
+                "{value}"
+            );
+        }
+
+        let message = Query::parse("titel:plan").unwrap_err().to_string();
+        assert!(message.contains("did you mean 'title'"));
+    }
+
+    #[test]
+    fn test_query_serializes_as_source() {
+        let query = Query::parse("status=done OR priority>2").unwrap();
+        let json = serde_json::to_string(&query).unwrap();
+        assert_eq!(json, "\"status=done OR priority>2\"");
+        let loaded: Query = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, query);
+        assert!(serde_json::from_str::<Query>("\"(oops\"").is_err());
+    }
+}
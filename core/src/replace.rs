@@ -0,0 +1,514 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Find and replace - regex search and notebook-wide replacement
+//!
+//! Patterns use the `regex` crate syntax and match across titles, content
+//! and string attributes (including strings inside array attributes), with
+//! `^` and `$` matching at line breaks. Replacements may refer to capture
+//! groups as `$1` or `${name}`.
+//!
+//! Replacing is done in two steps: [`Notebook::preview_replace`] computes a
+//! [`ReplacePlan`] listing every changed field with its text before and
+//! after, and [`Notebook::apply_replace`] applies a plan all at once. Applying
+//! returns the inverse plan, which undoes the replacement when applied in
+//! turn.
+//!
+//! [`Notebook::preview_replace`]: crate::notebook::Notebook::preview_replace
+//! [`Notebook::apply_replace`]: crate::notebook::Notebook::apply_replace
+
+use crate::note::{Note, NoteId};
+use regex::{NoExpand, Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// Errors that can occur when preparing a search or replacement
+#[derive(Debug, Error)]
+pub enum FindError {
+    #[error("Invalid pattern: {0}")]
+    InvalidPattern(#[from] regex::Error),
+}
+
+/// Options for regex search and replacement
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FindOptions {
+    /// Match letters regardless of case
+    pub case_insensitive: bool,
+
+    /// Treat the pattern and replacement as plain text
+    pub literal: bool,
+
+    /// Search note titles
+    pub titles: bool,
+
+    /// Search note content
+    pub content: bool,
+
+    /// Search string attributes
+    pub attributes: bool,
+}
+
+impl Default for FindOptions {
+    fn default() -> Self {
+        Self {
+            case_insensitive: false,
+            literal: false,
+            titles: true,
+            content: true,
+            attributes: true,
+        }
+    }
+}
+
+/// Where in a note text was found
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "field", rename_all = "snake_case")]
+pub enum Location {
+    Title,
+    Content,
+
+    /// A string attribute, or the string at `index` in an array attribute
+    Attribute {
+        key: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        index: Option<usize>,
+    },
+}
+
+/// One regex match
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegexMatch {
+    pub note: NoteId,
+    pub location: Location,
+
+    /// Char offset of the match in the field's text
+    pub start: usize,
+
+    /// Char offset one past the end of the match
+    pub end: usize,
+
+    /// The matched text
+    pub text: String,
+
+    /// Numbered capture groups from 1, None where a group did not take part
+    pub captures: Vec<Option<String>>,
+
+    /// Named capture groups that took part in the match
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub named: HashMap<String, String>,
+}
+
+/// A field's text before and after a replacement
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub location: Location,
+    pub before: String,
+    pub after: String,
+
+    /// Number of replaced matches
+    pub replacements: usize,
+}
+
+/// Changes to one note
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NoteChanges {
+    pub note: NoteId,
+
+    /// The note's title when the plan was made
+    pub title: String,
+
+    pub fields: Vec<FieldChange>,
+}
+
+/// Changes a replacement makes, note by note
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReplacePlan {
+    pub notes: Vec<NoteChanges>,
+}
+
+impl ReplacePlan {
+    /// Check if the plan changes nothing
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    /// Total number of replaced matches
+    pub fn replacements(&self) -> usize {
+        self.notes
+            .iter()
+            .flat_map(|note| &note.fields)
+            .map(|field| field.replacements)
+            .sum()
+    }
+
+    /// The plan that reverts this one
+    pub fn inverse(&self) -> ReplacePlan {
+        ReplacePlan {
+            notes: self
+                .notes
+                .iter()
+                .map(|note| NoteChanges {
+                    note: note.note,
+                    title: note
+                        .fields
+                        .iter()
+                        .find(|field| field.location == Location::Title)
+                        .map_or_else(|| note.title.clone(), |field| field.after.clone()),
+                    fields: note
+                        .fields
+                        .iter()
+                        .map(|field| FieldChange {
+                            location: field.location.clone(),
+                            before: field.after.clone(),
+                            after: field.before.clone(),
+                            replacements: field.replacements,
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Compile a pattern with the options' flags
+pub fn compile(pattern: &str, options: &FindOptions) -> Result<Regex, FindError> {
+    let pattern: Cow<str> = if options.literal {
+        Cow::Owned(regex::escape(pattern))
+    } else {
+        Cow::Borrowed(pattern)
+    };
+    Ok(RegexBuilder::new(&pattern)
+        .case_insensitive(options.case_insensitive)
+        .multi_line(true)
+        .build()?)
+}
+
+/// Searchable fields of a note, in order
+fn fields<'a>(note: &'a Note, options: &FindOptions) -> Vec<(Location, &'a str)> {
+    let mut fields = Vec::new();
+    if options.titles {
+        fields.push((Location::Title, note.title.as_str()));
+    }
+    if options.content {
+        fields.push((Location::Content, note.content.as_str()));
+    }
+    if options.attributes {
+        let mut keys: Vec<&String> = note.attributes.keys().collect();
+        keys.sort();
+        for key in keys {
+            match &note.attributes[key] {
+                Value::String(text) => fields.push((
+                    Location::Attribute {
+                        key: key.clone(),
+                        index: None,
+                    },
+                    text.as_str(),
+                )),
+                Value::Array(items) => {
+                    for (index, item) in items.iter().enumerate() {
+                        if let Value::String(text) = item {
+                            fields.push((
+                                Location::Attribute {
+                                    key: key.clone(),
+                                    index: Some(index),
+                                },
+                                text.as_str(),
+                            ));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    fields
+}
+
+/// Current text of a field, if the note has it as a string
+pub(crate) fn field_text<'a>(note: &'a Note, location: &Location) -> Option<&'a str> {
+    match location {
+        Location::Title => Some(&note.title),
+        Location::Content => Some(&note.content),
+        Location::Attribute { key, index } => {
+            let value = note.attributes.get(key)?;
+            match index {
+                None => value.as_str(),
+                Some(i) => value.as_array()?.get(*i)?.as_str(),
+            }
+        }
+    }
+}
+
+/// Set a field's text; the field must exist, as checked by [`field_text`]
+pub(crate) fn set_field_text(note: &mut Note, location: &Location, text: String) {
+    match location {
+        Location::Title => note.title = text,
+        Location::Content => note.content = text,
+        Location::Attribute { key, index } => {
+            let Some(value) = note.attributes.get_mut(key) else {
+                return;
+            };
+            let slot = match index {
+                None => Some(value),
+                Some(i) => value.as_array_mut().and_then(|items| items.get_mut(*i)),
+            };
+            if let Some(slot) = slot {
+                *slot = Value::String(text);
+            }
+        }
+    }
+}
+
+/// All matches in the notes, ordered by note title, then by field and
+/// position
+pub fn find<'a>(
+    notes: impl IntoIterator<Item = &'a Note>,
+    regex: &Regex,
+    options: &FindOptions,
+) -> Vec<RegexMatch> {
+    let mut notes: Vec<&Note> = notes.into_iter().collect();
+    notes.sort_by(|a, b| a.title.cmp(&b.title).then(a.id.cmp(&b.id)));
+    let names: Vec<(usize, &str)> = regex
+        .capture_names()
+        .enumerate()
+        .filter_map(|(i, name)| Some((i, name?)))
+        .collect();
+
+    let mut matches = Vec::new();
+    for note in notes {
+        for (location, text) in fields(note, options) {
+            // Convert byte offsets to char offsets incrementally
+            let (mut byte, mut chars) = (0, 0);
+            for captures in regex.captures_iter(text) {
+                let whole = captures.get(0).expect("group 0 always takes part");
+                chars += text[byte..whole.start()].chars().count();
+                let start = chars;
+                chars += whole.as_str().chars().count();
+                byte = whole.end();
+                matches.push(RegexMatch {
+                    note: note.id,
+                    location: location.clone(),
+                    start,
+                    end: chars,
+                    text: whole.as_str().to_string(),
+                    captures: captures
+                        .iter()
+                        .skip(1)
+                        .map(|group| group.map(|g| g.as_str().to_string()))
+                        .collect(),
+                    named: names
+                        .iter()
+                        .filter_map(|(i, name)| {
+                            Some((name.to_string(), captures.get(*i)?.as_str().to_string()))
+                        })
+                        .collect(),
+                });
+            }
+        }
+    }
+    matches
+}
+
+/// Work out the changes a replacement would make to the notes
+pub fn plan<'a>(
+    notes: impl IntoIterator<Item = &'a Note>,
+    regex: &Regex,
+    replacement: &str,
+    options: &FindOptions,
+) -> ReplacePlan {
+    let mut notes: Vec<&Note> = notes.into_iter().collect();
+    notes.sort_by(|a, b| a.title.cmp(&b.title).then(a.id.cmp(&b.id)));
+
+    let mut plan = ReplacePlan::default();
+    for note in notes {
+        let mut changes = Vec::new();
+        for (location, text) in fields(note, options) {
+            let replacements = regex.find_iter(text).count();
+            if replacements == 0 {
+                continue;
+            }
+            let after = if options.literal {
+                regex.replace_all(text, NoExpand(replacement))
+            } else {
+                regex.replace_all(text, replacement)
+            };
+            // Matches replaced by themselves change nothing
+            if after != text {
+                changes.push(FieldChange {
+                    location,
+                    before: text.to_string(),
+                    after: after.into_owned(),
+                    replacements,
+                });
+            }
+        }
+        if !changes.is_empty() {
+            plan.notes.push(NoteChanges {
+                note: note.id,
+                title: note.title.clone(),
+                fields: changes,
+            });
+        }
+    }
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notebook::{Notebook, NotebookError};
+    use serde_json::json;
+
+    fn sample() -> (Notebook, NoteId, NoteId, NoteId) {
+        let mut notebook = Notebook::new("Test");
+        let mut a = Note::new("Acme kickoff");
+        a.content = "Met with Acme Corp.\nAcme wants a demo on 2024-05-01.".into();
+        a.set_attribute("client", json!("Acme Corp"));
+        a.set_attribute("tags", json!(["acme", 3, "sales"]));
+        let a = notebook.add_note(a);
+        let mut b = Note::new("Budget");
+        b.content = "Nothing about the client here".into();
+        let b = notebook.add_note(b);
+        let mut c = Note::new("Follow-up");
+        c.content = "Send Acme the slides by 2024-05-03".into();
+        let c = notebook.add_note(c);
+        (notebook, a, b, c)
+    }
+
+    #[test]
+    fn test_find_with_captures() {
+        let (notebook, a, _, c) = sample();
+        let options = FindOptions::default();
+        let matches = notebook
+            .find_regex(r"(?P<year>\d{4})-(\d{2})-(\d{2})", &options)
+            .unwrap();
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].note, a);
+        assert_eq!(matches[0].location, Location::Content);
+        assert_eq!(matches[0].text, "2024-05-01");
+        assert_eq!(matches[0].captures[1], Some("05".to_string()));
+        assert_eq!(matches[0].named["year"], "2024");
+        assert_eq!(matches[1].note, c);
+
+        // Char offsets and line anchors
+        let matches = notebook.find_regex("^Acme", &options).unwrap();
+        let content: Vec<&RegexMatch> = matches
+            .iter()
+            .filter(|m| m.location == Location::Content)
+            .collect();
+        assert_eq!(content.len(), 1);
+        assert_eq!((content[0].start, content[0].end), (20, 24));
+
+        // String attributes, including inside arrays
+        let matches = notebook
+            .find_regex(
+                "acme",
+                &FindOptions {
+                    case_insensitive: true,
+                    titles: false,
+                    content: false,
+                    ..FindOptions::default()
+                },
+            )
+            .unwrap();
+        let locations: Vec<&Location> = matches.iter().map(|m| &m.location).collect();
+        assert_eq!(
+            locations,
+            vec![
+                &Location::Attribute {
+                    key: "client".into(),
+                    index: None
+                },
+                &Location::Attribute {
+                    key: "tags".into(),
+                    index: Some(0)
+                },
+            ]
+        );
+
+        assert!(matches!(
+            notebook.find_regex("(unclosed", &options),
+            Err(FindError::InvalidPattern(_))
+        ));
+    }
+
+    #[test]
+    fn test_preview_and_apply() {
+        let (mut notebook, a, b, c) = sample();
+        let untouched = notebook.get_note(&b).unwrap().modified_at;
+        let plan = notebook
+            .preview_replace(
+                r"(\d{4})-(\d{2})-(\d{2})",
+                "$3/$2/$1",
+                &FindOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(plan.notes.len(), 2);
+        assert_eq!(plan.replacements(), 2);
+        assert_eq!(
+            plan.notes[1].fields[0].after,
+            "Send Acme the slides by 03/05/2024"
+        );
+        // Previewing changes nothing
+        assert!(notebook
+            .get_note(&c)
+            .unwrap()
+            .content
+            .contains("2024-05-03"));
+
+        let undo = notebook.apply_replace(&plan).unwrap();
+        assert!(notebook
+            .get_note(&a)
+            .unwrap()
+            .content
+            .contains("01/05/2024"));
+        assert_eq!(notebook.get_note(&b).unwrap().modified_at, untouched);
+        assert_eq!(notebook.search("03/05/2024").len(), 1);
+
+        notebook.apply_replace(&undo).unwrap();
+        assert!(notebook
+            .get_note(&c)
+            .unwrap()
+            .content
+            .contains("2024-05-03"));
+    }
+
+    #[test]
+    fn test_literal_replacement_across_fields() {
+        let (mut notebook, a, _, _) = sample();
+        let options = FindOptions {
+            literal: true,
+            ..FindOptions::default()
+        };
+        let plan = notebook
+            .preview_replace("Acme", "Initech $1", &options)
+            .unwrap();
+        notebook.apply_replace(&plan).unwrap();
+        let note = notebook.get_note(&a).unwrap();
+        assert_eq!(note.title, "Initech $1 kickoff");
+        assert_eq!(
+            note.get_attribute("client"),
+            Some(&json!("Initech $1 Corp"))
+        );
+        // Other array elements are kept as they were
+        assert_eq!(
+            note.get_attribute("tags"),
+            Some(&json!(["acme", 3, "sales"]))
+        );
+    }
+
+    #[test]
+    fn test_apply_is_all_or_nothing() {
+        let (mut notebook, a, _, c) = sample();
+        let plan = notebook
+            .preview_replace("Acme", "Initech", &FindOptions::default())
+            .unwrap();
+        notebook.get_note_mut(&c).unwrap().content = "Edited meanwhile".into();
+
+        let result = notebook.apply_replace(&plan);
+        assert!(matches!(result, Err(NotebookError::ReplaceConflict(id)) if id == c));
+        assert_eq!(notebook.get_note(&a).unwrap().title, "Acme kickoff");
+    }
+}
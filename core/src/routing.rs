@@ -0,0 +1,629 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Edge routing - link paths between notes on the canvas
+//!
+//! Computes where each link leaves and enters its notes and a bezier or
+//! orthogonal path between them that steers around other notes. Links
+//! joining the same pair of notes (A→B and B→A) are bundled side by side
+//! instead of being drawn on top of each other.
+
+use crate::note::{Note, NoteId, Point2D, Rect};
+use crate::notebook::Notebook;
+use crate::placement::inflate;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+/// Shape of routed paths
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteStyle {
+    /// A single cubic bezier curve per link
+    Bezier,
+    /// Horizontal and vertical line segments
+    Orthogonal,
+}
+
+/// Tuning for edge routing
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RoutingOptions {
+    pub style: RouteStyle,
+
+    /// Minimum distance kept between a path and notes it passes
+    pub clearance: f64,
+
+    /// Distance between bundled parallel links
+    pub bundle_spacing: f64,
+}
+
+impl Default for RoutingOptions {
+    fn default() -> Self {
+        Self {
+            style: RouteStyle::Bezier,
+            clearance: 10.0,
+            bundle_spacing: 8.0,
+        }
+    }
+}
+
+/// One segment of a routed path, continuing from the previous end point
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PathSegment {
+    Line {
+        to: Point2D,
+    },
+    Cubic {
+        control1: Point2D,
+        control2: Point2D,
+        to: Point2D,
+    },
+}
+
+/// The routed path of a single link
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EdgeRoute {
+    pub source: NoteId,
+    pub target: NoteId,
+
+    /// Anchor on the source note's border
+    pub start: Point2D,
+
+    /// Anchor on the target note's border
+    pub end: Point2D,
+
+    /// Path from `start` to `end`
+    pub segments: Vec<PathSegment>,
+}
+
+impl EdgeRoute {
+    /// Render the path as SVG path data (`M … C …` or `M … L …`)
+    pub fn to_svg_path(&self) -> String {
+        let mut d = format!("M {} {}", fmt(self.start.x), fmt(self.start.y));
+        for segment in &self.segments {
+            match segment {
+                PathSegment::Line { to } => {
+                    let _ = write!(d, " L {} {}", fmt(to.x), fmt(to.y));
+                }
+                PathSegment::Cubic {
+                    control1,
+                    control2,
+                    to,
+                } => {
+                    let _ = write!(
+                        d,
+                        " C {} {} {} {} {} {}",
+                        fmt(control1.x),
+                        fmt(control1.y),
+                        fmt(control2.x),
+                        fmt(control2.y),
+                        fmt(to.x),
+                        fmt(to.y)
+                    );
+                }
+            }
+        }
+        d
+    }
+
+    /// Approximate the path as a polyline with `steps` points per curve
+    pub fn flatten(&self, steps: usize) -> Vec<Point2D> {
+        flatten(self.start, &self.segments, steps)
+    }
+}
+
+/// A path without its link endpoints
+type Path = (Point2D, Vec<PathSegment>);
+
+fn flatten(start: Point2D, segments: &[PathSegment], steps: usize) -> Vec<Point2D> {
+    let mut points = vec![start];
+    let mut from = start;
+    for segment in segments {
+        match *segment {
+            PathSegment::Line { to } => {
+                points.push(to);
+                from = to;
+            }
+            PathSegment::Cubic {
+                control1,
+                control2,
+                to,
+            } => {
+                let steps = steps.max(1);
+                for i in 1..=steps {
+                    let t = i as f64 / steps as f64;
+                    points.push(cubic_point(from, control1, control2, to, t));
+                }
+                from = to;
+            }
+        }
+    }
+    points
+}
+
+/// Format a coordinate compactly (at most two decimals)
+pub(crate) fn fmt(v: f64) -> String {
+    let rounded = (v * 100.0).round() / 100.0;
+    if rounded == 0.0 {
+        "0".to_string()
+    } else {
+        rounded.to_string()
+    }
+}
+
+fn cubic_point(p0: Point2D, p1: Point2D, p2: Point2D, p3: Point2D, t: f64) -> Point2D {
+    let u = 1.0 - t;
+    let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+    Point2D::new(
+        a * p0.x + b * p1.x + c * p2.x + d * p3.x,
+        a * p0.y + b * p1.y + c * p2.y + d * p3.y,
+    )
+}
+
+fn add(p: Point2D, dx: f64, dy: f64) -> Point2D {
+    Point2D::new(p.x + dx, p.y + dy)
+}
+
+/// Check if the segment a→b passes through the interior of the rectangle
+/// (Liang–Barsky clipping)
+fn segment_hits(a: Point2D, b: Point2D, rect: &Rect) -> bool {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let mut t0: f64 = 0.0;
+    let mut t1: f64 = 1.0;
+    let checks = [
+        (-dx, a.x - rect.x),
+        (dx, rect.x + rect.width - a.x),
+        (-dy, a.y - rect.y),
+        (dy, rect.y + rect.height - a.y),
+    ];
+    for (p, q) in checks {
+        if p == 0.0 {
+            if q <= 0.0 {
+                return false;
+            }
+        } else {
+            let r = q / p;
+            if p < 0.0 {
+                t0 = t0.max(r);
+            } else {
+                t1 = t1.min(r);
+            }
+            if t0 >= t1 {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+fn polyline_hits(points: &[Point2D], obstacles: &[Rect]) -> bool {
+    points
+        .windows(2)
+        .any(|w| obstacles.iter().any(|r| segment_hits(w[0], w[1], r)))
+}
+
+/// Point where a ray from `from` (inside the rectangle) in direction
+/// (dx, dy) leaves the rectangle
+fn exit_point(rect: &Rect, from: Point2D, dx: f64, dy: f64) -> Point2D {
+    let tx = if dx > 0.0 {
+        (rect.x + rect.width - from.x) / dx
+    } else if dx < 0.0 {
+        (rect.x - from.x) / dx
+    } else {
+        f64::INFINITY
+    };
+    let ty = if dy > 0.0 {
+        (rect.y + rect.height - from.y) / dy
+    } else if dy < 0.0 {
+        (rect.y - from.y) / dy
+    } else {
+        f64::INFINITY
+    };
+    let t = tx.min(ty);
+    if t.is_finite() {
+        add(from, dx * t, dy * t)
+    } else {
+        from
+    }
+}
+
+/// Clamp a point into a rectangle
+fn clamp(p: Point2D, rect: &Rect) -> Point2D {
+    Point2D::new(
+        p.x.clamp(rect.x, rect.x + rect.width),
+        p.y.clamp(rect.y, rect.y + rect.height),
+    )
+}
+
+/// Route every link whose source and target are both on the canvas
+pub fn route_all(notebook: &Notebook, options: &RoutingOptions) -> Vec<EdgeRoute> {
+    let mut links: Vec<(NoteId, NoteId)> = notebook
+        .all_notes()
+        .flat_map(|note| note.links.iter().map(move |target| (note.id, *target)))
+        .collect();
+    links.sort();
+    route_links(notebook, &links, options)
+}
+
+/// Route the given links; links with an endpoint that is missing or not on
+/// the canvas are skipped
+pub fn route_links(
+    notebook: &Notebook,
+    links: &[(NoteId, NoteId)],
+    options: &RoutingOptions,
+) -> Vec<EdgeRoute> {
+    let bounds = |id: &NoteId| notebook.get_note(id).and_then(Note::bounds);
+
+    // Bundle links by unordered note pair
+    let mut bundles: HashMap<(NoteId, NoteId), Vec<(NoteId, NoteId)>> = HashMap::new();
+    for &(from, to) in links {
+        if from == to || bounds(&from).is_none() || bounds(&to).is_none() {
+            continue;
+        }
+        let key = if from < to { (from, to) } else { (to, from) };
+        let bundle = bundles.entry(key).or_default();
+        if !bundle.contains(&(from, to)) {
+            bundle.push((from, to));
+        }
+    }
+
+    let mut routes = Vec::with_capacity(links.len());
+    let mut routed = HashSet::new();
+    for &(from, to) in links {
+        let key = if from < to { (from, to) } else { (to, from) };
+        let Some(bundle) = bundles.get(&key) else {
+            continue;
+        };
+        let Some(slot) = bundle.iter().position(|link| *link == (from, to)) else {
+            continue;
+        };
+        if !routed.insert((from, to)) {
+            continue;
+        }
+
+        // Offsets are measured along the normal of the canonical direction,
+        // so the two links of a pair land on opposite sides
+        let offset = (slot as f64 - (bundle.len() - 1) as f64 / 2.0) * options.bundle_spacing;
+
+        let (Some(source), Some(target)) = (bounds(&from), bounds(&to)) else {
+            continue;
+        };
+        let (canon_a, canon_b) = if from == key.0 {
+            (source.center(), target.center())
+        } else {
+            (target.center(), source.center())
+        };
+        let (nx, ny) = normal(canon_a, canon_b);
+
+        let obstacles = obstacles_between(notebook, &source, &target, from, to, options.clearance);
+        let (start, segments) = match options.style {
+            RouteStyle::Bezier => {
+                route_bezier(&source, &target, (nx * offset, ny * offset), &obstacles)
+            }
+            RouteStyle::Orthogonal => route_orthogonal(&source, &target, offset, &obstacles),
+        };
+        let end = match segments.last() {
+            Some(PathSegment::Line { to } | PathSegment::Cubic { to, .. }) => *to,
+            None => start,
+        };
+        routes.push(EdgeRoute {
+            source: from,
+            target: to,
+            start,
+            end,
+            segments,
+        });
+    }
+    routes
+}
+
+/// Unit normal (rotated 90° clockwise) of the direction a→b
+fn normal(a: Point2D, b: Point2D) -> (f64, f64) {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let len = dx.hypot(dy);
+    if len == 0.0 {
+        (0.0, 1.0)
+    } else {
+        (-dy / len, dx / len)
+    }
+}
+
+/// Inflated bounding boxes of notes near the straight line between two
+/// notes, excluding the notes themselves
+fn obstacles_between(
+    notebook: &Notebook,
+    source: &Rect,
+    target: &Rect,
+    from: NoteId,
+    to: NoteId,
+    clearance: f64,
+) -> Vec<Rect> {
+    let (a, b) = (source.center(), target.center());
+    // Widen the search so notes beside a detour are checked too
+    let pad = (a.x - b.x).abs().max((a.y - b.y).abs()) / 4.0;
+    let region = inflate(&Rect::from_corners(a, b), pad + clearance);
+    notebook
+        .notes_in_rect(region)
+        .into_iter()
+        .filter(|note| note.id != from && note.id != to)
+        .filter_map(Note::bounds)
+        .map(|r| inflate(&r, clearance))
+        .collect()
+}
+
+fn route_bezier(source: &Rect, target: &Rect, (ox, oy): (f64, f64), obstacles: &[Rect]) -> Path {
+    let (cs, ct) = (source.center(), target.center());
+    let (dx, dy) = (ct.x - cs.x, ct.y - cs.y);
+    let start = exit_point(source, clamp(add(cs, ox, oy), source), dx, dy);
+    let end = exit_point(target, clamp(add(ct, ox, oy), target), -dx, -dy);
+
+    let make = |bend: f64| {
+        let (nx, ny) = normal(start, end);
+        let (ex, ey) = (end.x - start.x, end.y - start.y);
+        let control1 = add(start, ex / 3.0 + nx * bend, ey / 3.0 + ny * bend);
+        let control2 = add(
+            start,
+            2.0 * ex / 3.0 + nx * bend,
+            2.0 * ey / 3.0 + ny * bend,
+        );
+        let segments = vec![PathSegment::Cubic {
+            control1,
+            control2,
+            to: end,
+        }];
+        (start, segments)
+    };
+    let hits =
+        |path: &Path, obstacles: &[Rect]| polyline_hits(&flatten(path.0, &path.1, 16), obstacles);
+
+    let straight = make(0.0);
+    let blocking: Vec<Rect> = obstacles
+        .iter()
+        .filter(|r| hits(&straight, std::slice::from_ref(r)))
+        .copied()
+        .collect();
+    if blocking.is_empty() {
+        return straight;
+    }
+
+    // With the controls at thirds of the chord, the curve's distance along
+    // the chord is linear in t and its sideways bulge at fraction u is
+    // 3u(1-u)h for a control offset h. Size h so the bulge clears every
+    // corner of the blocking notes, on whichever side needs less.
+    let (nx, ny) = normal(start, end);
+    let (ex, ey) = (end.x - start.x, end.y - start.y);
+    let chord2 = (ex * ex + ey * ey).max(f64::EPSILON);
+    let mut positive: f64 = 0.0;
+    let mut negative: f64 = 0.0;
+    for r in &blocking {
+        for corner in [
+            r.min(),
+            r.max(),
+            Point2D::new(r.x + r.width, r.y),
+            Point2D::new(r.x, r.y + r.height),
+        ] {
+            let (cx, cy) = (corner.x - start.x, corner.y - start.y);
+            let u = ((cx * ex + cy * ey) / chord2).clamp(0.05, 0.95);
+            let needed = (cx * nx + cy * ny) / (3.0 * u * (1.0 - u));
+            positive = positive.max(needed);
+            negative = negative.max(-needed);
+        }
+    }
+    let mut sides = [positive, -negative];
+    if negative < positive {
+        sides.swap(0, 1);
+    }
+    for bend in sides {
+        // Corners are only an estimate of the tightest point; widen a few
+        // times before giving up on a side
+        let mut bend = bend;
+        for _ in 0..4 {
+            let curve = make(bend);
+            if !hits(&curve, obstacles) {
+                return curve;
+            }
+            bend *= 1.25;
+        }
+    }
+    make(sides[0])
+}
+
+fn route_orthogonal(source: &Rect, target: &Rect, offset: f64, obstacles: &[Rect]) -> Path {
+    let gap_x = (target.x - (source.x + source.width)).max(source.x - (target.x + target.width));
+    let gap_y = (target.y - (source.y + source.height)).max(source.y - (target.y + target.height));
+    let horizontal = gap_x >= gap_y;
+
+    let try_route = |horizontal: bool| -> (Vec<Point2D>, bool) {
+        let (cs, ct) = (source.center(), target.center());
+        // Exit from the side facing the target
+        let (start, end) = if horizontal {
+            let (sx, tx) = if ct.x >= cs.x {
+                (source.x + source.width, target.x)
+            } else {
+                (source.x, target.x + target.width)
+            };
+            (
+                Point2D::new(sx, cs.y + offset),
+                Point2D::new(tx, ct.y + offset),
+            )
+        } else {
+            let (sy, ty) = if ct.y >= cs.y {
+                (source.y + source.height, target.y)
+            } else {
+                (source.y, target.y + target.height)
+            };
+            (
+                Point2D::new(cs.x + offset, sy),
+                Point2D::new(ct.x + offset, ty),
+            )
+        };
+        let path = |mid: f64| {
+            if horizontal {
+                vec![
+                    start,
+                    Point2D::new(mid, start.y),
+                    Point2D::new(mid, end.y),
+                    end,
+                ]
+            } else {
+                vec![
+                    start,
+                    Point2D::new(start.x, mid),
+                    Point2D::new(end.x, mid),
+                    end,
+                ]
+            }
+        };
+
+        let (lo, hi) = if horizontal {
+            (start.x.min(end.x), start.x.max(end.x))
+        } else {
+            (start.y.min(end.y), start.y.max(end.y))
+        };
+        let middle = (lo + hi) / 2.0 + offset;
+        let mut candidates = vec![middle];
+        for r in obstacles {
+            if horizontal {
+                candidates.push(r.x);
+                candidates.push(r.x + r.width);
+            } else {
+                candidates.push(r.y);
+                candidates.push(r.y + r.height);
+            }
+        }
+        candidates.retain(|m| *m >= lo && *m <= hi);
+        candidates.sort_by(|a, b| (a - middle).abs().total_cmp(&(b - middle).abs()));
+
+        for mid in candidates {
+            let points = path(mid);
+            if !polyline_hits(&points, obstacles) {
+                return (points, true);
+            }
+        }
+        (path(middle.clamp(lo, hi)), false)
+    };
+
+    let (mut points, clear) = try_route(horizontal);
+    if !clear {
+        let (other, other_clear) = try_route(!horizontal);
+        if other_clear {
+            points = other;
+        }
+    }
+    points.dedup();
+
+    let segments = points[1..]
+        .iter()
+        .map(|&to| PathSegment::Line { to })
+        .collect();
+    (points[0], segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boxed(notebook: &mut Notebook, x: f64, y: f64) -> NoteId {
+        let mut note = Note::new("Box").with_position(x, y);
+        note.size = Some((100.0, 50.0));
+        notebook.add_note(note)
+    }
+
+    fn on_border(rect: &Rect, p: Point2D) -> bool {
+        let eps = 1e-9;
+        rect.contains(p)
+            && ((p.x - rect.x).abs() < eps
+                || (p.x - rect.x - rect.width).abs() < eps
+                || (p.y - rect.y).abs() < eps
+                || (p.y - rect.y - rect.height).abs() < eps)
+    }
+
+    #[test]
+    fn test_anchors_on_borders() {
+        let mut notebook = Notebook::new("Test");
+        let a = boxed(&mut notebook, 0.0, 0.0);
+        let b = boxed(&mut notebook, 300.0, 200.0);
+        notebook.link_notes(a, b).unwrap();
+
+        let routes = route_all(&notebook, &RoutingOptions::default());
+        assert_eq!(routes.len(), 1);
+        let route = &routes[0];
+        assert!(on_border(
+            &notebook.get_note(&a).unwrap().bounds().unwrap(),
+            route.start
+        ));
+        assert!(on_border(
+            &notebook.get_note(&b).unwrap().bounds().unwrap(),
+            route.end
+        ));
+        assert!(route.to_svg_path().starts_with("M "));
+    }
+
+    #[test]
+    fn test_bezier_avoids_obstacle() {
+        let mut notebook = Notebook::new("Test");
+        let a = boxed(&mut notebook, 0.0, 0.0);
+        let b = boxed(&mut notebook, 600.0, 0.0);
+        let blocker = boxed(&mut notebook, 300.0, 0.0);
+        notebook.link_notes(a, b).unwrap();
+
+        let options = RoutingOptions::default();
+        let route = &route_all(&notebook, &options)[0];
+        let obstacle = inflate(
+            &notebook.get_note(&blocker).unwrap().bounds().unwrap(),
+            options.clearance,
+        );
+        assert!(!polyline_hits(&route.flatten(32), &[obstacle]));
+    }
+
+    #[test]
+    fn test_orthogonal_route() {
+        let mut notebook = Notebook::new("Test");
+        let a = boxed(&mut notebook, 0.0, 0.0);
+        let b = boxed(&mut notebook, 400.0, 200.0);
+        let blocker = boxed(&mut notebook, 220.0, 80.0);
+        notebook.link_notes(a, b).unwrap();
+
+        let options = RoutingOptions {
+            style: RouteStyle::Orthogonal,
+            ..RoutingOptions::default()
+        };
+        let route = &route_all(&notebook, &options)[0];
+        let points = route.flatten(1);
+        for w in points.windows(2) {
+            assert!(
+                w[0].x == w[1].x || w[0].y == w[1].y,
+                "not orthogonal: {w:?}"
+            );
+        }
+        let obstacle = inflate(
+            &notebook.get_note(&blocker).unwrap().bounds().unwrap(),
+            options.clearance,
+        );
+        assert!(!polyline_hits(&points, &[obstacle]));
+    }
+
+    #[test]
+    fn test_parallel_links_bundled() {
+        let mut notebook = Notebook::new("Test");
+        let a = boxed(&mut notebook, 0.0, 0.0);
+        let b = boxed(&mut notebook, 400.0, 0.0);
+        notebook.link_notes(a, b).unwrap();
+        notebook.link_notes(b, a).unwrap();
+
+        let options = RoutingOptions::default();
+        let routes = route_all(&notebook, &options);
+        assert_eq!(routes.len(), 2);
+        let there = routes.iter().find(|r| r.source == a).unwrap();
+        let back = routes.iter().find(|r| r.source == b).unwrap();
+        assert_eq!((there.start.y - back.end.y).abs(), options.bundle_spacing);
+    }
+
+    #[test]
+    fn test_unplaced_links_skipped() {
+        let mut notebook = Notebook::new("Test");
+        let a = boxed(&mut notebook, 0.0, 0.0);
+        let b = notebook.create_note("Unplaced");
+        notebook.link_notes(a, b).unwrap();
+        assert!(route_all(&notebook, &RoutingOptions::default()).is_empty());
+    }
+}
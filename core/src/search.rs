@@ -0,0 +1,490 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Ranked search - BM25 scoring with snippets and highlight ranges
+//!
+//! Queries are analyzed the same way note text is indexed. Each
+//! note containing any query word is scored with BM25 over its content and
+//! title, the title score weighted by a boost. While typing, the last query
+//! word also matches longer words it is a prefix of, at a reduced weight.
+
+use crate::analysis::{Analyzer, Token};
+use crate::note::{Note, NoteId};
+use crate::text_index::{self, TermFrequency, TextIndex};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// BM25 term frequency saturation
+const K1: f64 = 1.2;
+
+/// BM25 length normalisation
+const B: f64 = 0.75;
+
+/// Weight of words matched only through the trailing prefix
+const PREFIX_WEIGHT: f64 = 0.5;
+
+/// Most indexed words the trailing prefix expands to
+const MAX_PREFIX_TERMS: usize = 64;
+
+/// Options for ranked search
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchOptions {
+    /// Maximum number of hits returned
+    pub limit: usize,
+
+    /// Multiplier applied to title scores
+    pub title_boost: f64,
+
+    /// Approximate snippet length in characters
+    pub snippet_chars: usize,
+
+    /// Let the last query word match as a prefix, for search-as-you-type
+    pub prefix: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            limit: 20,
+            title_boost: 2.0,
+            snippet_chars: 160,
+            prefix: true,
+        }
+    }
+}
+
+/// A highlighted range, in char offsets into the string it belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Highlight {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// One ranked search result
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchHit {
+    /// The matching note
+    pub id: NoteId,
+
+    /// The note's title
+    pub title: String,
+
+    /// Relevance score; higher is better
+    pub score: f64,
+
+    /// Matched words in `title`
+    pub title_highlights: Vec<Highlight>,
+
+    /// Best-matching excerpt of the note's content
+    pub snippet: String,
+
+    /// Matched words in `snippet`
+    pub snippet_highlights: Vec<Highlight>,
+}
+
+/// A query word or prefix expansion with its weight and rarity
+#[derive(Debug)]
+struct QueryTerm {
+    term: String,
+    weight: f64,
+    idf: f64,
+}
+
+/// A query prepared against a text index
+#[derive(Debug)]
+pub(crate) struct RankedQuery<'a> {
+    analyzer: &'a Analyzer,
+    terms: Vec<QueryTerm>,
+    prefix: Option<String>,
+    averages: (f64, f64),
+}
+
+/// BM25 contribution of one term in one field
+fn bm25(tf: u32, len: u32, average: f64) -> f64 {
+    if tf == 0 {
+        return 0.0;
+    }
+    let tf = f64::from(tf);
+    let norm = if average > 0.0 {
+        f64::from(len) / average
+    } else {
+        1.0
+    };
+    tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * norm))
+}
+
+impl<'a> RankedQuery<'a> {
+    pub(crate) fn new(query: &str, index: &'a TextIndex, options: &SearchOptions) -> Self {
+        let analyzer = index.analyzer();
+        let n = index.len() as f64;
+        let idf = |term: &str| {
+            let df = index.document_frequency(term) as f64;
+            (1.0 + (n - df + 0.5) / (df + 0.5)).ln()
+        };
+
+        let tokens = analyzer.tokenize(query);
+        let mut seen = HashSet::new();
+        let mut terms = Vec::new();
+        for token in &tokens {
+            if seen.insert(token.term.clone()) {
+                terms.push(QueryTerm {
+                    idf: idf(&token.term),
+                    term: token.term.clone(),
+                    weight: 1.0,
+                });
+            }
+        }
+
+        // A query ending mid-word is still being typed; its last word is
+        // matched as written, before stop-word removal and stemming
+        let typing = query.chars().last().is_some_and(char::is_alphanumeric);
+        let prefix = analyzer
+            .words(query)
+            .pop()
+            .filter(|_| options.prefix && typing)
+            .map(|token| token.term);
+        if let Some(prefix) = &prefix {
+            for term in index.terms_with_prefix(prefix).take(MAX_PREFIX_TERMS) {
+                if seen.insert(term.to_string()) {
+                    terms.push(QueryTerm {
+                        idf: idf(term),
+                        term: term.to_string(),
+                        weight: PREFIX_WEIGHT,
+                    });
+                }
+            }
+        }
+
+        Self {
+            analyzer,
+            terms,
+            prefix,
+            averages: index.average_lengths(),
+        }
+    }
+
+    /// Indexed notes containing at least one query term
+    pub(crate) fn candidates(&self, index: &TextIndex) -> HashSet<NoteId> {
+        self.terms
+            .iter()
+            .filter_map(|term| index.postings(&term.term))
+            .flat_map(HashMap::keys)
+            .copied()
+            .collect()
+    }
+
+    /// Score a note from its index entry
+    pub(crate) fn score_indexed(
+        &self,
+        index: &TextIndex,
+        id: &NoteId,
+        options: &SearchOptions,
+    ) -> f64 {
+        let lengths = index.document_lengths(id).unwrap_or_default();
+        self.score(
+            |term| {
+                index
+                    .postings(term)
+                    .and_then(|postings| postings.get(id))
+                    .copied()
+                    .unwrap_or_default()
+            },
+            lengths,
+            options,
+        )
+    }
+
+    /// Score a note directly from its text, for notes not yet reindexed
+    pub(crate) fn score_note(&self, note: &Note, options: &SearchOptions) -> f64 {
+        let (words, lengths) = text_index::word_counts(self.analyzer, note);
+        self.score(
+            |term| words.get(term).copied().unwrap_or_default(),
+            lengths,
+            options,
+        )
+    }
+
+    fn score(
+        &self,
+        frequency: impl Fn(&str) -> TermFrequency,
+        lengths: (u32, u32),
+        options: &SearchOptions,
+    ) -> f64 {
+        self.terms
+            .iter()
+            .map(|term| {
+                let tf = frequency(&term.term);
+                let content = bm25(tf.content, lengths.1, self.averages.1);
+                let title = bm25(tf.title, lengths.0, self.averages.0);
+                term.weight * term.idf * (content + options.title_boost * title)
+            })
+            .sum()
+    }
+
+    /// Check if a word in the text counts as a match for highlighting
+    fn matches(&self, term: &str) -> bool {
+        self.terms.iter().any(|t| t.term == term)
+            || self
+                .prefix
+                .as_ref()
+                .is_some_and(|prefix| term.starts_with(prefix.as_str()))
+    }
+
+    /// Turn scored notes into the best hits, highest score first
+    pub(crate) fn collect_hits(
+        &self,
+        mut scored: Vec<(f64, &'a Note)>,
+        options: &SearchOptions,
+    ) -> Vec<SearchHit> {
+        scored.retain(|(score, _)| *score > 0.0);
+        scored.sort_by(|a, b| {
+            b.0.total_cmp(&a.0)
+                .then_with(|| a.1.title.cmp(&b.1.title))
+                .then_with(|| a.1.id.cmp(&b.1.id))
+        });
+        scored.truncate(options.limit);
+        scored
+            .into_iter()
+            .map(|(score, note)| self.hit(note, score, options))
+            .collect()
+    }
+
+    fn hit(&self, note: &Note, score: f64, options: &SearchOptions) -> SearchHit {
+        let title_highlights = self
+            .analyzer
+            .tokenize(&note.title)
+            .into_iter()
+            .filter(|token| self.matches(&token.term))
+            .map(|token| Highlight {
+                start: token.start,
+                end: token.end,
+            })
+            .collect();
+        let (snippet, snippet_highlights) = self.snippet(&note.content, options.snippet_chars);
+        SearchHit {
+            id: note.id,
+            title: note.title.clone(),
+            score,
+            title_highlights,
+            snippet,
+            snippet_highlights,
+        }
+    }
+
+    /// Excerpt of `content` around the window of `width` chars covering the
+    /// most distinct query words, with highlights relative to the excerpt
+    fn snippet(&self, content: &str, width: usize) -> (String, Vec<Highlight>) {
+        let chars: Vec<char> = content.chars().collect();
+        let tokens = self.analyzer.tokenize(content);
+        let matched: Vec<&Token> = tokens.iter().filter(|t| self.matches(&t.term)).collect();
+
+        let (start, end) = match best_window(&matched, width) {
+            None => (0, chars.len().min(width)),
+            Some(first) => {
+                // Lead in with some context, starting on a word
+                let lead = (width / 4).min(first.start);
+                let start = tokens
+                    .iter()
+                    .map(|t| t.start)
+                    .find(|s| *s >= first.start - lead)
+                    .unwrap_or(first.start);
+                let limit = (start + width).min(chars.len());
+                let end = if limit == chars.len() {
+                    limit
+                } else {
+                    tokens
+                        .iter()
+                        .map(|t| t.end)
+                        .rfind(|e| *e <= limit)
+                        .unwrap_or(limit)
+                        .max(first.end)
+                };
+                (start, end)
+            }
+        };
+
+        let mut snippet = String::new();
+        if start > 0 {
+            snippet.push('…');
+        }
+        let offset = usize::from(start > 0);
+        snippet.extend(
+            chars[start..end]
+                .iter()
+                .map(|c| if c.is_whitespace() { ' ' } else { *c }),
+        );
+        if end < chars.len() {
+            snippet.push('…');
+        }
+
+        let highlights = matched
+            .iter()
+            .filter(|t| t.start >= start && t.end <= end)
+            .map(|t| Highlight {
+                start: t.start - start + offset,
+                end: t.end - start + offset,
+            })
+            .collect();
+        (snippet, highlights)
+    }
+}
+
+/// First matched token of the window of `width` chars that covers the most
+/// distinct matched words, then the most matches
+fn best_window<'t>(matched: &[&'t Token], width: usize) -> Option<&'t Token> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    let mut best: Option<(usize, usize, usize)> = None;
+    let mut j = 0;
+    for i in 0..matched.len() {
+        while j < matched.len() && (j <= i || matched[j].end <= matched[i].start + width) {
+            *counts.entry(matched[j].term.as_str()).or_default() += 1;
+            j += 1;
+        }
+        let quality = (counts.len(), j - i);
+        if best.is_none_or(|(distinct, total, _)| quality > (distinct, total)) {
+            best = Some((quality.0, quality.1, i));
+        }
+        let term = matched[i].term.as_str();
+        if let Some(count) = counts.get_mut(term) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(term);
+            }
+        }
+    }
+    best.map(|(_, _, i)| matched[i])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{AnalysisConfig, Language};
+    use crate::notebook::Notebook;
+
+    fn add(notebook: &mut Notebook, title: &str, content: &str) -> NoteId {
+        let mut note = Note::new(title);
+        note.content = content.into();
+        notebook.add_note(note)
+    }
+
+    #[test]
+    fn test_ranking_boosts_titles() {
+        let mut notebook = Notebook::new("Test");
+        let body = add(&mut notebook, "Gardening", "Compost helps the garden grow");
+        let title = add(&mut notebook, "Compost", "How to start a heap");
+        add(&mut notebook, "Unrelated", "Nothing to see");
+
+        let hits = notebook.search_ranked("compost", &SearchOptions::default());
+        let ids: Vec<NoteId> = hits.iter().map(|hit| hit.id).collect();
+        assert_eq!(ids, vec![title, body]);
+        assert!(hits[0].score > hits[1].score);
+        assert_eq!(
+            hits[0].title_highlights,
+            vec![Highlight { start: 0, end: 7 }]
+        );
+    }
+
+    #[test]
+    fn test_rarer_words_score_higher() {
+        let mut notebook = Notebook::new("Test");
+        let common = add(&mut notebook, "A", "common word here");
+        let rare = add(&mut notebook, "B", "rare word here");
+        add(&mut notebook, "C", "common again");
+
+        let hits = notebook.search_ranked("common rare", &SearchOptions::default());
+        assert_eq!(hits[0].id, rare);
+        assert!(hits.iter().any(|hit| hit.id == common));
+    }
+
+    #[test]
+    fn test_snippet_and_highlights() {
+        let mut notebook = Notebook::new("Test");
+        let filler = "lorem ipsum ".repeat(30);
+        let content = format!("{filler}the quarterly budget review\nis due {filler}");
+        add(&mut notebook, "Memo", &content);
+
+        let options = SearchOptions {
+            snippet_chars: 60,
+            ..SearchOptions::default()
+        };
+        let hit = &notebook.search_ranked("budget review", &options)[0];
+        assert!(hit.snippet.starts_with('…') && hit.snippet.ends_with('…'));
+        assert!(!hit.snippet.contains('\n'));
+        let chars: Vec<char> = hit.snippet.chars().collect();
+        let words: Vec<String> = hit
+            .snippet_highlights
+            .iter()
+            .map(|h| chars[h.start..h.end].iter().collect())
+            .collect();
+        assert_eq!(words, vec!["budget", "review"]);
+    }
+
+    #[test]
+    fn test_prefix_while_typing() {
+        let mut notebook = Notebook::new("Test");
+        let id = add(&mut notebook, "Architecture", "Design notes");
+
+        let hits = notebook.search_ranked("arch", &SearchOptions::default());
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].id, id);
+        assert_eq!(
+            hits[0].title_highlights,
+            vec![Highlight { start: 0, end: 12 }]
+        );
+
+        // A finished word does not match longer words
+        assert!(notebook
+            .search_ranked("arch ", &SearchOptions::default())
+            .is_empty());
+    }
+
+    #[test]
+    fn test_pending_edits_are_ranked() {
+        let mut notebook = Notebook::new("Test");
+        let id = notebook.create_note("Draft");
+        notebook.get_note_mut(&id).unwrap().content = "fresh words".into();
+
+        let hits = notebook.search_ranked("fresh", &SearchOptions::default());
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].snippet, "fresh words");
+    }
+
+    #[test]
+    fn test_notebook_analysis_settings() {
+        let mut notebook = Notebook::new("Test");
+        let cafe = add(&mut notebook, "Café visits", "We were running late");
+        let kyoto = add(&mut notebook, "旅行", "京都に行きました");
+
+        assert_eq!(notebook.search("cafe").len(), 1);
+        let hits = notebook.search_ranked("京都", &SearchOptions::default());
+        assert_eq!(hits[0].id, kyoto);
+        assert_eq!(
+            hits[0].snippet_highlights,
+            vec![Highlight { start: 0, end: 2 }]
+        );
+        assert!(notebook
+            .search_ranked("runs ", &SearchOptions::default())
+            .is_empty());
+
+        notebook.set_analysis_config(AnalysisConfig {
+            language: Some(Language::English),
+            stop_words: true,
+            ..AnalysisConfig::default()
+        });
+        let hits = notebook.search_ranked("the runs ", &SearchOptions::default());
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].id, cafe);
+        assert_eq!(
+            hits[0].snippet_highlights,
+            vec![Highlight { start: 8, end: 15 }]
+        );
+
+        // Settings are saved with the notebook
+        let json = serde_json::to_string(&notebook).unwrap();
+        let loaded: Notebook = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.analysis_config().language, Some(Language::English));
+        assert_eq!(
+            loaded.search_ranked("run", &SearchOptions::default()).len(),
+            1
+        );
+    }
+}
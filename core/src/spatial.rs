@@ -0,0 +1,166 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Spatial index - R-tree over note bounding boxes on the canvas
+
+use crate::note::{NoteId, Point2D, Rect};
+use rstar::primitives::{GeomWithData, Rectangle};
+use rstar::{RTree, AABB};
+use std::collections::HashMap;
+
+type Entry = GeomWithData<Rectangle<[f64; 2]>, NoteId>;
+
+fn to_rectangle(rect: &Rect) -> Rectangle<[f64; 2]> {
+    Rectangle::from_corners(
+        [rect.x, rect.y],
+        [rect.x + rect.width, rect.y + rect.height],
+    )
+}
+
+fn to_aabb(rect: &Rect) -> AABB<[f64; 2]> {
+    AABB::from_corners(
+        [rect.x, rect.y],
+        [rect.x + rect.width, rect.y + rect.height],
+    )
+}
+
+/// Squared distance from a point to the nearest edge of a rectangle
+/// (zero if the point is inside)
+pub fn distance_2(rect: &Rect, point: Point2D) -> f64 {
+    let dx = (rect.x - point.x)
+        .max(point.x - (rect.x + rect.width))
+        .max(0.0);
+    let dy = (rect.y - point.y)
+        .max(point.y - (rect.y + rect.height))
+        .max(0.0);
+    dx * dx + dy * dy
+}
+
+/// R-tree of note bounding boxes, keyed by note ID
+#[derive(Debug, Clone, Default)]
+pub struct SpatialIndex {
+    tree: RTree<Entry>,
+    bounds: HashMap<NoteId, Rect>,
+}
+
+impl SpatialIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build an index in one pass (faster than repeated inserts)
+    pub fn bulk_load(entries: impl IntoIterator<Item = (NoteId, Rect)>) -> Self {
+        let bounds: HashMap<NoteId, Rect> = entries.into_iter().collect();
+        let tree = RTree::bulk_load(
+            bounds
+                .iter()
+                .map(|(id, rect)| GeomWithData::new(to_rectangle(rect), *id))
+                .collect(),
+        );
+        Self { tree, bounds }
+    }
+
+    /// Number of indexed notes
+    pub fn len(&self) -> usize {
+        self.bounds.len()
+    }
+
+    /// Check if the index is empty
+    pub fn is_empty(&self) -> bool {
+        self.bounds.is_empty()
+    }
+
+    /// Insert or move a note's bounding box
+    pub fn insert(&mut self, id: NoteId, rect: Rect) {
+        if self.bounds.get(&id) == Some(&rect) {
+            return;
+        }
+        self.remove(&id);
+        self.tree.insert(GeomWithData::new(to_rectangle(&rect), id));
+        self.bounds.insert(id, rect);
+    }
+
+    /// Remove a note from the index
+    pub fn remove(&mut self, id: &NoteId) -> Option<Rect> {
+        let rect = self.bounds.remove(id)?;
+        self.tree
+            .remove(&GeomWithData::new(to_rectangle(&rect), *id));
+        Some(rect)
+    }
+
+    /// Indexed bounding box of a note
+    pub fn get(&self, id: &NoteId) -> Option<Rect> {
+        self.bounds.get(id).copied()
+    }
+
+    /// IDs of notes whose bounding box intersects the rectangle
+    pub fn intersecting(&self, rect: &Rect) -> impl Iterator<Item = NoteId> + '_ {
+        self.tree
+            .locate_in_envelope_intersecting(&to_aabb(rect))
+            .map(|entry| entry.data)
+    }
+
+    /// IDs of notes whose bounding box contains the point
+    pub fn at_point(&self, point: Point2D) -> impl Iterator<Item = NoteId> + '_ {
+        self.tree
+            .locate_all_at_point(&[point.x, point.y])
+            .map(|entry| entry.data)
+    }
+
+    /// IDs of notes ordered by distance from the point, with the squared
+    /// distance to each note's bounding box
+    pub fn nearest(&self, point: Point2D) -> impl Iterator<Item = (NoteId, f64)> + '_ {
+        self.tree
+            .nearest_neighbor_iter_with_distance_2(&[point.x, point.y])
+            .map(|(entry, dist)| (entry.data, dist))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_insert_move_remove() {
+        let mut index = SpatialIndex::new();
+        let id = Uuid::new_v4();
+
+        index.insert(id, Rect::new(0.0, 0.0, 10.0, 10.0));
+        assert_eq!(
+            index.at_point(Point2D::new(5.0, 5.0)).collect::<Vec<_>>(),
+            vec![id]
+        );
+
+        // Moving replaces the old entry rather than adding a second one
+        index.insert(id, Rect::new(100.0, 100.0, 10.0, 10.0));
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.at_point(Point2D::new(5.0, 5.0)).count(), 0);
+        assert_eq!(index.at_point(Point2D::new(105.0, 105.0)).count(), 1);
+
+        assert!(index.remove(&id).is_some());
+        assert!(index.is_empty());
+        assert_eq!(
+            index
+                .intersecting(&Rect::new(0.0, 0.0, 1000.0, 1000.0))
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_nearest_uses_box_distance() {
+        let near = Uuid::new_v4();
+        let far = Uuid::new_v4();
+        let index = SpatialIndex::bulk_load([
+            (near, Rect::new(10.0, 0.0, 100.0, 100.0)),
+            (far, Rect::new(50.0, 200.0, 10.0, 10.0)),
+        ]);
+
+        let (id, dist) = index.nearest(Point2D::new(0.0, 50.0)).next().unwrap();
+        assert_eq!(id, near);
+        assert_eq!(dist, 100.0);
+        assert_eq!(
+            distance_2(&index.get(&near).unwrap(), Point2D::new(0.0, 50.0)),
+            dist
+        );
+    }
+}
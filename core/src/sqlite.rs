@@ -0,0 +1,719 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! SQLite storage - a notebook as a database with a row per note
+//!
+//! Notes, their links and their attributes live in tables of their own;
+//! everything else about the notebook (name, maps, adornments, agents,
+//! analysis settings and the format version) is kept as one JSON value per
+//! top-level field in a `notebook` table. Saving writes only the notes that
+//! were added, changed or removed, all in one transaction: the storage
+//! remembers each note's change stamp (see [`Notebook::note_generation`])
+//! as of the last save or load of a database, and only notes with a new
+//! stamp are looked at. Each note row also carries a digest of the note's
+//! saved form, which decides for databases the storage hasn't seen.
+//!
+//! [`SqliteNotebook`] reads a database in place, fetching notes one at a
+//! time, for notebooks too large to want in memory whole. Loading and
+//! saving keep every field exactly, so converting between this and the
+//! JSON format (see [`crate::storage::convert`]) loses nothing.
+
+use crate::embedding::fnv1a;
+use crate::format::{self, Versioned};
+use crate::note::{Note, NoteId, Point2D, Rect, DEFAULT_NOTE_SIZE};
+use crate::notebook::Notebook;
+use crate::storage::{Storage, StorageError};
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, Row};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+/// Version of the table layout, kept in the database's `user_version`
+pub const SCHEMA_VERSION: u32 = 1;
+
+const SCHEMA: &str = "
+    CREATE TABLE notebook (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE notes (
+        id TEXT PRIMARY KEY,
+        title TEXT NOT NULL,
+        content TEXT NOT NULL,
+        x REAL,
+        y REAL,
+        width REAL,
+        height REAL,
+        created_at TEXT NOT NULL,
+        modified_at TEXT NOT NULL,
+        prototype TEXT,
+        digest INTEGER NOT NULL
+    );
+    CREATE INDEX notes_position ON notes (x, y);
+    CREATE TABLE links (
+        source TEXT NOT NULL REFERENCES notes (id) ON DELETE CASCADE,
+        ordinal INTEGER NOT NULL,
+        target TEXT NOT NULL,
+        PRIMARY KEY (source, ordinal)
+    );
+    CREATE INDEX links_target ON links (target);
+    CREATE TABLE attributes (
+        note TEXT NOT NULL REFERENCES notes (id) ON DELETE CASCADE,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (note, key)
+    );
+";
+
+/// Columns of a note row, in the order [`read_note`] expects
+const NOTE_COLUMNS: &str =
+    "id, title, content, x, y, width, height, created_at, modified_at, prototype";
+
+/// Storage as a SQLite database
+#[derive(Debug, Default)]
+pub struct SqliteStorage {
+    /// Change stamps of the notes as last loaded from or saved to each
+    /// database
+    synced: Mutex<HashMap<PathBuf, HashMap<NoteId, u64>>>,
+}
+
+impl SqliteStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn remember(&self, path: &Path, notebook: &Notebook) {
+        let generations = notebook
+            .all_note_ids()
+            .filter_map(|id| Some((*id, notebook.note_generation(id)?)))
+            .collect();
+        let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let mut synced = self.synced.lock().unwrap_or_else(PoisonError::into_inner);
+        synced.insert(key, generations);
+    }
+
+    fn synced(&self, path: &Path) -> HashMap<NoteId, u64> {
+        let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let synced = self.synced.lock().unwrap_or_else(PoisonError::into_inner);
+        synced.get(&key).cloned().unwrap_or_default()
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn save(&self, notebook: &Notebook, path: &Path) -> Result<(), StorageError> {
+        let mut conn = Connection::open(path)?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        let version: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        let tx = conn.transaction()?;
+        let mut synced = self.synced(path);
+        match version {
+            0 => {
+                // Never take over a database that holds something else
+                let tables: i64 =
+                    tx.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get(0))?;
+                if tables > 0 {
+                    return Err(StorageError::InvalidFormat(format!(
+                        "{} is not a notebook database",
+                        path.display()
+                    )));
+                }
+                tx.execute_batch(SCHEMA)?;
+                tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+                // Whatever was saved here before is gone
+                synced.clear();
+            }
+            SCHEMA_VERSION => {}
+            found => {
+                return Err(StorageError::UnsupportedVersion {
+                    found,
+                    supported: SCHEMA_VERSION,
+                })
+            }
+        }
+
+        let mut manifest = match serde_json::to_value(Versioned::new(notebook))? {
+            Value::Object(object) => object,
+            _ => Map::new(),
+        };
+        manifest.remove("notes");
+        manifest.remove("backlinks");
+        let stored: HashMap<String, String> = tx
+            .prepare("SELECT key, value FROM notebook")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        for (key, value) in &manifest {
+            let value = serde_json::to_string(value)?;
+            if stored.get(key) != Some(&value) {
+                tx.execute(
+                    "INSERT OR REPLACE INTO notebook (key, value) VALUES (?1, ?2)",
+                    params![key, value],
+                )?;
+            }
+        }
+        for key in stored.keys().filter(|key| !manifest.contains_key(*key)) {
+            tx.execute("DELETE FROM notebook WHERE key = ?1", [key])?;
+        }
+
+        let digests: HashMap<String, i64> = tx
+            .prepare("SELECT id, digest FROM notes")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        for note in notebook.all_notes() {
+            let generation = notebook.note_generation(&note.id);
+            if generation.is_some() && synced.get(&note.id) == generation.as_ref() {
+                continue;
+            }
+            let digest = digest(note)?;
+            if digests.get(&note.id.to_string()) != Some(&digest) {
+                write_note(&tx, note, digest)?;
+            }
+        }
+        let kept: HashSet<String> = notebook.all_note_ids().map(NoteId::to_string).collect();
+        for id in digests.keys().filter(|id| !kept.contains(*id)) {
+            tx.execute("DELETE FROM notes WHERE id = ?1", [id])?;
+        }
+
+        tx.commit()?;
+        self.remember(path, notebook);
+        Ok(())
+    }
+
+    fn load(&self, path: &Path) -> Result<Notebook, StorageError> {
+        let notebook = SqliteNotebook::open(path)?.load()?;
+        self.remember(path, &notebook);
+        Ok(notebook)
+    }
+}
+
+/// Digest of a note's saved form; attributes are written in key order so
+/// the same note always gives the same digest
+fn digest(note: &Note) -> Result<i64, StorageError> {
+    let saved = serde_json::to_vec(&serde_json::to_value(note)?)?;
+    Ok(fnv1a(&saved) as i64)
+}
+
+/// Replace a note's row, links and attributes
+fn write_note(conn: &Connection, note: &Note, digest: i64) -> Result<(), StorageError> {
+    let id = note.id.to_string();
+    // Deleting the old row cascades to its links and attributes
+    conn.execute("DELETE FROM notes WHERE id = ?1", [&id])?;
+    conn.execute(
+        "INSERT INTO notes (id, title, content, x, y, width, height, \
+         created_at, modified_at, prototype, digest) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            id,
+            note.title,
+            note.content,
+            note.position.map(|p| p.x),
+            note.position.map(|p| p.y),
+            note.size.map(|s| s.0),
+            note.size.map(|s| s.1),
+            time_text(&note.created_at),
+            time_text(&note.modified_at),
+            note.prototype.map(|p| p.to_string()),
+            digest,
+        ],
+    )?;
+    let mut link =
+        conn.prepare_cached("INSERT INTO links (source, ordinal, target) VALUES (?1, ?2, ?3)")?;
+    for (ordinal, target) in note.links.iter().enumerate() {
+        link.execute(params![id, ordinal as i64, target.to_string()])?;
+    }
+    let mut attribute =
+        conn.prepare_cached("INSERT INTO attributes (note, key, value) VALUES (?1, ?2, ?3)")?;
+    for (key, value) in &note.attributes {
+        attribute.execute(params![id, key, serde_json::to_string(value)?])?;
+    }
+    Ok(())
+}
+
+fn time_text(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+fn parse_id(text: &str) -> Result<NoteId, StorageError> {
+    NoteId::parse_str(text)
+        .map_err(|_| StorageError::InvalidFormat(format!("invalid note id {text:?}")))
+}
+
+fn parse_time(text: &str) -> Result<DateTime<Utc>, StorageError> {
+    DateTime::parse_from_rfc3339(text)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| StorageError::InvalidFormat(format!("invalid time {text:?}")))
+}
+
+/// Columns of a note row, before the text in them is parsed
+type NoteRow = (
+    String,
+    String,
+    String,
+    Option<f64>,
+    Option<f64>,
+    Option<f64>,
+    Option<f64>,
+    String,
+    String,
+    Option<String>,
+);
+
+fn note_row(row: &Row) -> rusqlite::Result<NoteRow> {
+    Ok((
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+        row.get(4)?,
+        row.get(5)?,
+        row.get(6)?,
+        row.get(7)?,
+        row.get(8)?,
+        row.get(9)?,
+    ))
+}
+
+/// A note from its row, without links or attributes
+fn read_note(row: NoteRow) -> Result<Note, StorageError> {
+    let (id, title, content, x, y, width, height, created_at, modified_at, prototype) = row;
+    Ok(Note {
+        id: parse_id(&id)?,
+        title,
+        content,
+        position: x.zip(y).map(|(x, y)| Point2D::new(x, y)),
+        size: width.zip(height),
+        created_at: parse_time(&created_at)?,
+        modified_at: parse_time(&modified_at)?,
+        links: Vec::new(),
+        prototype: prototype.as_deref().map(parse_id).transpose()?,
+        attributes: HashMap::new(),
+    })
+}
+
+/// What a listing shows of a note, without its content
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NoteSummary {
+    pub id: NoteId,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<Point2D>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<(f64, f64)>,
+    pub modified_at: DateTime<Utc>,
+}
+
+/// A notebook database read in place, one note at a time
+#[derive(Debug)]
+pub struct SqliteNotebook {
+    conn: Connection,
+}
+
+impl SqliteNotebook {
+    /// Open a notebook database for reading
+    pub fn open(path: &Path) -> Result<Self, StorageError> {
+        if !path.exists() {
+            return Err(StorageError::NotFound(path.display().to_string()));
+        }
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let version: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        match version {
+            SCHEMA_VERSION => Ok(Self { conn }),
+            0 => Err(StorageError::InvalidFormat(format!(
+                "{} is not a notebook database",
+                path.display()
+            ))),
+            found => Err(StorageError::UnsupportedVersion {
+                found,
+                supported: SCHEMA_VERSION,
+            }),
+        }
+    }
+
+    /// Notebook name
+    pub fn name(&self) -> Result<String, StorageError> {
+        let name: Option<String> = self
+            .conn
+            .query_row("SELECT value FROM notebook WHERE key = 'name'", [], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        match name {
+            Some(name) => Ok(serde_json::from_str(&name)?),
+            None => Err(StorageError::InvalidFormat("notebook has no name".into())),
+        }
+    }
+
+    /// Number of notes
+    pub fn len(&self) -> Result<usize, StorageError> {
+        let count: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM notes", [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+
+    /// Check if the notebook has no notes
+    pub fn is_empty(&self) -> Result<bool, StorageError> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Every note, without content, in title order
+    pub fn summaries(&self) -> Result<Vec<NoteSummary>, StorageError> {
+        self.query_summaries(
+            "SELECT id, title, x, y, width, height, modified_at FROM notes \
+             ORDER BY title, id",
+            [],
+        )
+    }
+
+    /// Placed notes whose bounds intersect a rectangle, without content
+    pub fn notes_in_rect(&self, rect: Rect) -> Result<Vec<NoteSummary>, StorageError> {
+        let (default_width, default_height) = DEFAULT_NOTE_SIZE;
+        self.query_summaries(
+            "SELECT id, title, x, y, width, height, modified_at FROM notes \
+             WHERE x <= ?1 + ?3 AND y <= ?2 + ?4 \
+             AND x + COALESCE(width, ?5) >= ?1 AND y + COALESCE(height, ?6) >= ?2 \
+             ORDER BY title, id",
+            params![
+                rect.x,
+                rect.y,
+                rect.width,
+                rect.height,
+                default_width,
+                default_height
+            ],
+        )
+    }
+
+    fn query_summaries(
+        &self,
+        sql: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<NoteSummary>, StorageError> {
+        let mut statement = self.conn.prepare(sql)?;
+        let rows = statement.query_map(params, |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<f64>>(2)?
+                    .zip(row.get::<_, Option<f64>>(3)?),
+                row.get::<_, Option<f64>>(4)?
+                    .zip(row.get::<_, Option<f64>>(5)?),
+                row.get::<_, String>(6)?,
+            ))
+        })?;
+        rows.map(|row| {
+            let (id, title, position, size, modified_at) = row?;
+            Ok(NoteSummary {
+                id: parse_id(&id)?,
+                title,
+                position: position.map(|(x, y)| Point2D::new(x, y)),
+                size,
+                modified_at: parse_time(&modified_at)?,
+            })
+        })
+        .collect()
+    }
+
+    /// A single note with its content, links and attributes
+    pub fn note(&self, id: &NoteId) -> Result<Option<Note>, StorageError> {
+        let id = id.to_string();
+        let row = self
+            .conn
+            .query_row(
+                &format!("SELECT {NOTE_COLUMNS} FROM notes WHERE id = ?1"),
+                [&id],
+                note_row,
+            )
+            .optional()?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let mut note = read_note(row)?;
+
+        let mut links = self
+            .conn
+            .prepare("SELECT target FROM links WHERE source = ?1 ORDER BY ordinal")?;
+        for target in links.query_map([&id], |row| row.get::<_, String>(0))? {
+            note.links.push(parse_id(&target?)?);
+        }
+        let mut attributes = self
+            .conn
+            .prepare("SELECT key, value FROM attributes WHERE note = ?1")?;
+        let rows = attributes.query_map([&id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (key, value) = row?;
+            note.attributes.insert(key, serde_json::from_str(&value)?);
+        }
+        Ok(Some(note))
+    }
+
+    /// Notes that link to a note
+    pub fn backlinks(&self, id: &NoteId) -> Result<Vec<NoteId>, StorageError> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT DISTINCT source FROM links WHERE target = ?1 ORDER BY source")?;
+        let rows = statement.query_map([id.to_string()], |row| row.get::<_, String>(0))?;
+        rows.map(|source| parse_id(&source?)).collect()
+    }
+
+    /// Read the whole notebook into memory
+    pub fn load(&self) -> Result<Notebook, StorageError> {
+        let mut document = Map::new();
+        let mut statement = self.conn.prepare("SELECT key, value FROM notebook")?;
+        let rows = statement.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (key, value) = row?;
+            document.insert(key, serde_json::from_str(&value)?);
+        }
+
+        let mut notes = HashMap::new();
+        let mut statement = self
+            .conn
+            .prepare(&format!("SELECT {NOTE_COLUMNS} FROM notes"))?;
+        for row in statement.query_map([], note_row)? {
+            let note = read_note(row?)?;
+            notes.insert(note.id, note);
+        }
+
+        let mut backlinks: HashMap<NoteId, HashSet<NoteId>> = HashMap::new();
+        let mut statement = self
+            .conn
+            .prepare("SELECT source, target FROM links ORDER BY source, ordinal")?;
+        let rows = statement.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (source, target) = row?;
+            let (source, target) = (parse_id(&source)?, parse_id(&target)?);
+            if let Some(note) = notes.get_mut(&source) {
+                note.links.push(target);
+                backlinks.entry(target).or_default().insert(source);
+            }
+        }
+
+        let mut statement = self
+            .conn
+            .prepare("SELECT note, key, value FROM attributes")?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+        for row in rows {
+            let (id, key, value) = row?;
+            if let Some(note) = notes.get_mut(&parse_id(&id)?) {
+                note.attributes.insert(key, serde_json::from_str(&value)?);
+            }
+        }
+
+        document.insert("notes".into(), serde_json::to_value(&notes)?);
+        document.insert("backlinks".into(), serde_json::to_value(&backlinks)?);
+        format::from_document(Value::Object(document))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{convert, JsonStorage};
+    use crate::testing::{sample, saved_form};
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    fn digests(path: &Path) -> HashMap<String, i64> {
+        let conn = Connection::open(path).unwrap();
+        let mut statement = conn.prepare("SELECT id, digest FROM notes").unwrap();
+        let rows = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        rows.map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn test_round_trip_is_identical() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.nexia.db");
+        let (notebook, _, _, _) = sample();
+        let storage = SqliteStorage::new();
+        storage.save(&notebook, &path).unwrap();
+
+        let loaded = storage.load(&path).unwrap();
+        assert_eq!(saved_form(&loaded), saved_form(&notebook));
+        assert_eq!(loaded.search("milestones").len(), 1);
+    }
+
+    #[test]
+    fn test_saves_write_only_changed_notes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.nexia.db");
+        let (mut notebook, plan, budget, _) = sample();
+        let untouched = notebook.create_note("Untouched");
+        let storage = SqliteStorage::new();
+        storage.save(&notebook, &path).unwrap();
+        let before = digests(&path);
+
+        // Mark rows so untouched ones can be told from rewritten ones
+        Connection::open(&path)
+            .unwrap()
+            .execute("UPDATE notes SET title = title || ' (kept)'", [])
+            .unwrap();
+        notebook.get_note_mut(&plan).unwrap().content = "Revised".into();
+        notebook.remove_note(&budget);
+        let added = notebook.create_note("Added");
+        storage.save(&notebook, &path).unwrap();
+
+        let after = digests(&path);
+        assert_eq!(after.len(), 4);
+        assert_ne!(after[&plan.to_string()], before[&plan.to_string()]);
+        assert_eq!(
+            after[&untouched.to_string()],
+            before[&untouched.to_string()]
+        );
+        assert!(!after.contains_key(&budget.to_string()));
+        let mut titles: Vec<String> = SqliteNotebook::open(&path)
+            .unwrap()
+            .summaries()
+            .unwrap()
+            .into_iter()
+            .map(|summary| summary.title)
+            .collect();
+        titles.sort();
+        // Removing the budget note dropped the copy's link to it, so the
+        // copy was written again too
+        assert_eq!(
+            titles,
+            ["Added", "Project plan", "Project plan", "Untouched (kept)"]
+        );
+        assert!(after.contains_key(&added.to_string()));
+
+        Connection::open(&path)
+            .unwrap()
+            .execute(
+                "UPDATE notes SET title = 'Untouched' WHERE title LIKE '%(kept)'",
+                [],
+            )
+            .unwrap();
+        let loaded = storage.load(&path).unwrap();
+        assert_eq!(saved_form(&loaded), saved_form(&notebook));
+    }
+
+    #[test]
+    fn test_unchanged_notes_are_not_examined() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.nexia.db");
+        let (notebook, plan, _, _) = sample();
+        let storage = SqliteStorage::new();
+        storage.save(&notebook, &path).unwrap();
+        let set_digest = |digest: i64| {
+            Connection::open(&path)
+                .unwrap()
+                .execute(
+                    "UPDATE notes SET digest = ?1 WHERE id = ?2",
+                    params![digest, plan.to_string()],
+                )
+                .unwrap();
+        };
+
+        // The storage knows the note hasn't changed since it saved it
+        set_digest(0);
+        storage.save(&notebook, &path).unwrap();
+        assert_eq!(digests(&path)[&plan.to_string()], 0);
+
+        // A storage that hasn't seen the database compares digests
+        SqliteStorage::new().save(&notebook, &path).unwrap();
+        assert_ne!(digests(&path)[&plan.to_string()], 0);
+
+        // Changes made in a clone are stamped anew
+        let mut copy = notebook.clone();
+        copy.set_note_position(&plan, Some(Point2D::new(5.0, 5.0)))
+            .unwrap();
+        storage.save(&copy, &path).unwrap();
+        storage.save(&notebook, &path).unwrap();
+        let loaded = SqliteStorage::new().load(&path).unwrap();
+        assert_eq!(saved_form(&loaded), saved_form(&notebook));
+    }
+
+    #[test]
+    fn test_reading_in_place() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.nexia.db");
+        let (notebook, plan, budget, _) = sample();
+        SqliteStorage::new().save(&notebook, &path).unwrap();
+
+        let db = SqliteNotebook::open(&path).unwrap();
+        assert_eq!(db.name().unwrap(), "Sample");
+        assert_eq!(db.len().unwrap(), 3);
+        let note = db.note(&plan).unwrap().unwrap();
+        assert_eq!(note.content, "Milestones\n---\nnot front matter");
+        assert_eq!(note.links, vec![budget]);
+        assert_eq!(note.attributes["weight"], json!(1.5));
+        assert!(db.note(&NoteId::new_v4()).unwrap().is_none());
+        assert_eq!(db.backlinks(&budget).unwrap().len(), 2);
+
+        let found = db
+            .notes_in_rect(Rect::new(300.0, 150.0, 10.0, 10.0))
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, plan);
+        assert!(db
+            .notes_in_rect(Rect::new(341.0, 0.0, 10.0, 10.0))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_conversion_to_and_from_json_is_lossless() {
+        let dir = tempdir().unwrap();
+        let json_path = dir.path().join("test.nexia.json");
+        let db_path = dir.path().join("test.nexia.db");
+        let back_path = dir.path().join("back.nexia.json");
+        let (notebook, _, _, _) = sample();
+        let json = JsonStorage::new();
+        json.save(&notebook, &json_path).unwrap();
+
+        convert(&json, &json_path, &SqliteStorage::new(), &db_path).unwrap();
+        convert(&SqliteStorage::new(), &db_path, &json, &back_path).unwrap();
+        assert_eq!(
+            saved_form(&json.load(&back_path).unwrap()),
+            saved_form(&notebook)
+        );
+    }
+
+    #[test]
+    fn test_other_files_are_refused() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("other.db");
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch("CREATE TABLE things (id INTEGER)")
+            .unwrap();
+        let storage = SqliteStorage::new();
+        assert!(matches!(
+            storage.load(&path),
+            Err(StorageError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            storage.save(&Notebook::new("Other"), &path),
+            Err(StorageError::InvalidFormat(_))
+        ));
+
+        Connection::open(&path)
+            .unwrap()
+            .pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        assert!(matches!(
+            storage.save(&Notebook::new("Newer"), &path),
+            Err(StorageError::UnsupportedVersion { .. })
+        ));
+        assert!(matches!(
+            storage.load(&dir.path().join("missing.db")),
+            Err(StorageError::NotFound(_))
+        ));
+    }
+}
@@ -1,8 +1,19 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 //! Storage - persistence layer for notebooks
+//!
+//! Saves never overwrite a notebook file in place: the new contents are
+//! written to a temporary file in the same directory, flushed to disk and
+//! renamed over the old file, so a crash or full disk leaves either the old
+//! or the new version intact. The version being replaced is kept as a
+//! timestamped backup next to the file, and loading can fall back to the
+//! newest backup that still reads when the file itself is damaged.
+//! Files are versioned and upgraded on load; see [`crate::format`].
 
+use crate::format::{self, Versioned};
 use crate::notebook::Notebook;
-use std::path::Path;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 /// Errors that can occur during storage operations
@@ -14,8 +25,54 @@ pub enum StorageError {
     #[error("JSON serialization error: {0}")]
     Json(#[from] serde_json::Error),
 
+    #[error("YAML serialization error: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+
+    #[cfg(feature = "sqlite")]
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+
+    #[cfg(feature = "binary")]
+    #[error("MessagePack encoding error: {0}")]
+    Encode(#[from] rmp_serde::encode::Error),
+
+    #[cfg(feature = "binary")]
+    #[error("MessagePack decoding error: {0}")]
+    Decode(#[from] rmp_serde::decode::Error),
+
     #[error("File not found: {0}")]
     NotFound(String),
+
+    #[error("File format version {found} is newer than the supported version {supported}")]
+    UnsupportedVersion { found: u32, supported: u32 },
+
+    #[error("Invalid notebook file: {0}")]
+    InvalidFormat(String),
+
+    #[error("Notebook file is encrypted; a passphrase is needed to open it")]
+    PassphraseRequired,
+
+    #[error("Wrong passphrase")]
+    WrongPassphrase,
+
+    #[error("Notebook file is corrupted: {0}")]
+    Corrupted(String),
+}
+
+impl StorageError {
+    /// Whether the error means a file is missing or damaged, rather than
+    /// readable only by a newer version or with a passphrase
+    pub fn is_damage(&self) -> bool {
+        match self {
+            StorageError::NotFound(_)
+            | StorageError::Json(_)
+            | StorageError::InvalidFormat(_)
+            | StorageError::Corrupted(_) => true,
+            #[cfg(feature = "binary")]
+            StorageError::Decode(_) => true,
+            _ => false,
+        }
+    }
 }
 
 /// Storage trait for notebook persistence
@@ -27,15 +84,205 @@ pub trait Storage {
     fn load(&self, path: &Path) -> Result<Notebook, StorageError>;
 }
 
+/// Storage that keeps a notebook as the contents of a single file, so the
+/// contents can also be produced and read without going through the disk
+pub trait FileStorage: Storage {
+    /// Encode a notebook as file contents
+    fn encode(&self, notebook: &Notebook) -> Result<Vec<u8>, StorageError>;
+
+    /// Decode file contents into a notebook
+    fn decode(&self, bytes: &[u8]) -> Result<Notebook, StorageError>;
+}
+
+/// Copy a notebook from one storage format to another
+pub fn convert(
+    from: &impl Storage,
+    from_path: &Path,
+    to: &impl Storage,
+    to_path: &Path,
+) -> Result<(), StorageError> {
+    to.save(&from.load(from_path)?, to_path)
+}
+
+/// Number of backups kept by default
+pub const DEFAULT_BACKUPS: usize = 5;
+
+/// Write a file so that it holds either its old or its new contents, even
+/// if the process or machine dies part way
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), StorageError> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path
+        .file_name()
+        .ok_or_else(|| StorageError::NotFound(path.display().to_string()))?;
+    let temp = dir.join(format!(
+        ".{}.{}.tmp",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let written = File::create(&temp).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+    if let Err(e) = written.and_then(|_| fs::rename(&temp, path)) {
+        let _ = fs::remove_file(&temp);
+        return Err(e.into());
+    }
+    // Make the rename itself durable; not every platform can open a
+    // directory for this
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// Timestamp format of backup names, which sort in time order
+const BACKUP_STAMP: &str = "%Y%m%dT%H%M%S%.6fZ";
+
+/// Backups of a file, newest first
+///
+/// Only files named `<name>.<stamp>.bak` or `<name>.<stamp>_<n>.bak` count,
+/// so backups of other files in the same folder are never picked up.
+pub fn list_backups(path: &Path) -> Result<Vec<PathBuf>, StorageError> {
+    let Some(name) = path.file_name() else {
+        return Ok(Vec::new());
+    };
+    let prefix = format!("{}.", name.to_string_lossy());
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut backups: Vec<_> = fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter_map(|backup| {
+            let file = backup.file_name()?.to_str()?;
+            let order = backup_order(file.strip_prefix(&prefix)?.strip_suffix(".bak")?)?;
+            Some((order, backup))
+        })
+        .collect();
+    backups.sort();
+    backups.reverse();
+    Ok(backups.into_iter().map(|(_, backup)| backup).collect())
+}
+
+/// Time and collision number of a backup, from the part of its name
+/// between the file name and `.bak`
+fn backup_order(stamp: &str) -> Option<(chrono::NaiveDateTime, u32)> {
+    let (stamp, n) = match stamp.split_once('_') {
+        Some((stamp, n)) if n.len() >= 3 && n.bytes().all(|b| b.is_ascii_digit()) => {
+            (stamp, n.parse().ok()?)
+        }
+        Some(_) => return None,
+        None => (stamp, 0),
+    };
+    let time = chrono::NaiveDateTime::parse_from_str(stamp, BACKUP_STAMP).ok()?;
+    Some((time, n))
+}
+
+/// A loaded notebook and where it came from
+#[derive(Debug)]
+pub struct Loaded {
+    pub notebook: Notebook,
+
+    /// The backup that was loaded because the file itself could not be,
+    /// or None if the file loaded
+    pub recovered_from: Option<PathBuf>,
+}
+
 /// JSON file storage implementation
-pub struct JsonStorage;
+///
+/// Loading also reads files saved by `BinaryStorage`, told apart by their
+/// header.
+pub struct JsonStorage {
+    backups: usize,
+}
 
 impl JsonStorage {
     pub fn new() -> Self {
-        Self
+        Self {
+            backups: DEFAULT_BACKUPS,
+        }
+    }
+
+    /// Keep this many backups of each file; zero keeps none
+    pub fn with_backups(mut self, backups: usize) -> Self {
+        self.backups = backups;
+        self
+    }
+
+    /// Load a notebook, falling back to the newest backup that loads if
+    /// the file is missing or damaged
+    ///
+    /// If neither the file nor any backup loads, the file's own error is
+    /// returned. So are errors that don't mean damage, such as a file from
+    /// a newer version or one that needs a passphrase: opening an older
+    /// backup instead would have the next save overwrite the file.
+    pub fn load_or_recover(&self, path: &Path) -> Result<Loaded, StorageError> {
+        let error = match self.load(path) {
+            Ok(notebook) => {
+                return Ok(Loaded {
+                    notebook,
+                    recovered_from: None,
+                })
+            }
+            Err(e) if e.is_damage() => e,
+            Err(e) => return Err(e),
+        };
+        for backup in list_backups(path).unwrap_or_default() {
+            if let Ok(notebook) = Self::read(&backup) {
+                return Ok(Loaded {
+                    notebook,
+                    recovered_from: Some(backup),
+                });
+            }
+        }
+        Err(error)
+    }
+
+    fn read(path: &Path) -> Result<Notebook, StorageError> {
+        Self::parse(&fs::read(path)?)
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Notebook, StorageError> {
+        #[cfg(feature = "binary")]
+        if crate::binary::is_binary(bytes) {
+            return crate::binary::decode(bytes);
+        }
+        #[cfg(feature = "encryption")]
+        if crate::encryption::is_encrypted(bytes) {
+            return Err(StorageError::PassphraseRequired);
+        }
+        format::from_document(serde_json::from_slice(bytes)?)
     }
 }
 
+/// Copy a file to a new timestamped backup and delete the oldest backups
+/// beyond the number to keep
+pub(crate) fn back_up(path: &Path, keep: usize) -> Result<(), StorageError> {
+    if keep == 0 || !path.exists() {
+        return Ok(());
+    }
+    let stamp = chrono::Utc::now().format(BACKUP_STAMP);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut backup = path.with_file_name(format!("{name}.{stamp}.bak"));
+    // Numbered names count as newer than the plain one
+    let mut n = 1;
+    while backup.exists() {
+        backup = path.with_file_name(format!("{name}.{stamp}_{n:03}.bak"));
+        n += 1;
+    }
+    fs::copy(path, &backup)?;
+
+    for old in list_backups(path)?.into_iter().skip(keep) {
+        fs::remove_file(old)?;
+    }
+    Ok(())
+}
+
 impl Default for JsonStorage {
     fn default() -> Self {
         Self::new()
@@ -44,9 +291,9 @@ impl Default for JsonStorage {
 
 impl Storage for JsonStorage {
     fn save(&self, notebook: &Notebook, path: &Path) -> Result<(), StorageError> {
-        let json = serde_json::to_string_pretty(notebook)?;
-        std::fs::write(path, json)?;
-        Ok(())
+        let json = self.encode(notebook)?;
+        back_up(path, self.backups)?;
+        write_atomic(path, &json)
     }
 
     fn load(&self, path: &Path) -> Result<Notebook, StorageError> {
@@ -54,16 +301,23 @@ impl Storage for JsonStorage {
             return Err(StorageError::NotFound(path.display().to_string()));
         }
 
-        let json = std::fs::read_to_string(path)?;
-        let notebook = serde_json::from_str(&json)?;
-        Ok(notebook)
+        Self::read(path)
+    }
+}
+
+impl FileStorage for JsonStorage {
+    fn encode(&self, notebook: &Notebook) -> Result<Vec<u8>, StorageError> {
+        Ok(serde_json::to_vec_pretty(&Versioned::new(notebook))?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Notebook, StorageError> {
+        Self::parse(bytes)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs;
     use tempfile::tempdir;
 
     #[test]
@@ -97,4 +351,111 @@ mod tests {
         let result = storage.load(Path::new("/nonexistent/path.json"));
         assert!(matches!(result, Err(StorageError::NotFound(_))));
     }
+
+    #[test]
+    fn test_save_is_atomic_and_rotates_backups() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.nexia.json");
+        let storage = JsonStorage::new().with_backups(2);
+
+        let mut notebook = Notebook::new("Version 0");
+        storage.save(&notebook, &path).unwrap();
+        assert!(list_backups(&path).unwrap().is_empty());
+
+        for version in 1..=3 {
+            notebook.name = format!("Version {version}");
+            storage.save(&notebook, &path).unwrap();
+        }
+        let backups = list_backups(&path).unwrap();
+        assert_eq!(backups.len(), 2);
+        assert_eq!(JsonStorage::read(&backups[0]).unwrap().name, "Version 2");
+        assert_eq!(JsonStorage::read(&backups[1]).unwrap().name, "Version 1");
+        assert_eq!(storage.load(&path).unwrap().name, "Version 3");
+
+        // No temporary files are left behind
+        let files = std::fs::read_dir(dir.path()).unwrap().count();
+        assert_eq!(files, 3);
+    }
+
+    #[test]
+    fn test_load_recovers_from_newest_valid_backup() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.nexia.json");
+        let storage = JsonStorage::new();
+
+        let mut notebook = Notebook::new("Good");
+        storage.save(&notebook, &path).unwrap();
+        notebook.name = "Newer".into();
+        storage.save(&notebook, &path).unwrap();
+        storage.save(&notebook, &path).unwrap();
+
+        // A crash left the file truncated and the newest backup damaged
+        std::fs::write(&path, "{\"notes\": {").unwrap();
+        let backups = list_backups(&path).unwrap();
+        std::fs::write(&backups[0], "").unwrap();
+
+        assert!(matches!(storage.load(&path), Err(StorageError::Json(_))));
+        let loaded = storage.load_or_recover(&path).unwrap();
+        assert_eq!(loaded.notebook.name, "Good");
+        assert_eq!(loaded.recovered_from.as_ref(), Some(&backups[1]));
+
+        let loaded = storage
+            .load_or_recover(&dir.path().join("other.json"))
+            .map(|loaded| loaded.notebook.name);
+        assert!(matches!(loaded, Err(StorageError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_backups_of_sibling_files_are_left_alone() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("notes.json");
+        let sibling = dir.path().join("notes.json.old.json");
+        let storage = JsonStorage::new().with_backups(1);
+
+        storage.save(&Notebook::new("Sibling"), &sibling).unwrap();
+        storage.save(&Notebook::new("Sibling"), &sibling).unwrap();
+        std::fs::write(dir.path().join("notes.json.draft.bak"), "draft").unwrap();
+        let sibling_backups = list_backups(&sibling).unwrap();
+        assert_eq!(sibling_backups.len(), 1);
+
+        // The sibling's backups are neither listed, recovered nor rotated
+        storage.save(&Notebook::new("Mine"), &path).unwrap();
+        storage.save(&Notebook::new("Mine"), &path).unwrap();
+        storage.save(&Notebook::new("Mine"), &path).unwrap();
+        let backups = list_backups(&path).unwrap();
+        assert_eq!(backups.len(), 1);
+        assert!(!backups.contains(&sibling_backups[0]));
+        assert!(sibling_backups[0].exists());
+        assert!(dir.path().join("notes.json.draft.bak").exists());
+
+        std::fs::write(&path, "").unwrap();
+        std::fs::remove_file(&backups[0]).unwrap();
+        let loaded = storage.load_or_recover(&path);
+        assert!(matches!(loaded, Err(StorageError::Json(_))));
+    }
+
+    #[test]
+    fn test_newer_files_are_refused() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.nexia.json");
+        std::fs::write(&path, r#"{"format_version": 999, "notes": {}}"#).unwrap();
+
+        let result = JsonStorage::new().load(&path);
+        assert!(matches!(
+            result,
+            Err(StorageError::UnsupportedVersion { found: 999, .. })
+        ));
+
+        // An older backup is not opened in place of a newer file
+        let path = dir.path().join("other.nexia.json");
+        let storage = JsonStorage::new();
+        storage.save(&Notebook::new("Old"), &path).unwrap();
+        storage.save(&Notebook::new("Old"), &path).unwrap();
+        assert!(storage.load_or_recover(&list_backups(&path).unwrap()[0]).is_ok());
+        std::fs::write(&path, r#"{"format_version": 999, "notes": {}}"#).unwrap();
+        assert!(matches!(
+            storage.load_or_recover(&path),
+            Err(StorageError::UnsupportedVersion { found: 999, .. })
+        ));
+    }
 }
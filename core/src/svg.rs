@@ -0,0 +1,413 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! SVG export - render the canvas, a region or a selection as an image
+//!
+//! Output is a single self-contained SVG document: styles are inline and
+//! nothing refers to external files or fonts beyond a font family name.
+
+use crate::graph_io::xml_escape;
+use crate::note::{Note, NoteId, Point2D, Rect};
+use crate::notebook::Notebook;
+use crate::placement::inflate;
+use crate::routing::{self, fmt, RoutingOptions};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt::Write;
+
+/// Which part of the canvas to render
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "scope", rename_all = "snake_case")]
+pub enum SvgScope {
+    /// Every placed note
+    All,
+    /// Notes intersecting a rectangle, with the view fitted to it
+    Region { rect: Rect },
+    /// A chosen set of notes
+    Notes { ids: Vec<NoteId> },
+}
+
+/// Colours, fonts and layout for SVG export
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SvgOptions {
+    /// Page background (None for transparent)
+    pub background: Option<String>,
+    pub note_fill: String,
+    pub note_stroke: String,
+    pub corner_radius: f64,
+    pub title_color: String,
+    pub text_color: String,
+    pub link_color: String,
+    pub link_width: f64,
+    pub font_family: String,
+    pub font_size: f64,
+    /// Maximum characters of content shown under the title (0 for none)
+    pub excerpt_chars: usize,
+    /// Space around the drawing
+    pub padding: f64,
+    /// Draw adornments behind the notes
+    pub adornments: bool,
+    pub routing: RoutingOptions,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        Self {
+            background: Some("#ffffff".into()),
+            note_fill: "#fffbe6".into(),
+            note_stroke: "#8c8c8c".into(),
+            corner_radius: 6.0,
+            title_color: "#1f1f1f".into(),
+            text_color: "#595959".into(),
+            link_color: "#597ef7".into(),
+            link_width: 1.5,
+            font_family: "system-ui, sans-serif".into(),
+            font_size: 12.0,
+            excerpt_chars: 200,
+            padding: 20.0,
+            adornments: true,
+            routing: RoutingOptions::default(),
+        }
+    }
+}
+
+/// Approximate glyph width relative to font size, for wrapping text
+const CHAR_WIDTH: f64 = 0.6;
+
+/// Line height relative to font size
+const LINE_HEIGHT: f64 = 1.3;
+
+/// Greedy word wrap to at most `max_lines` lines of `width` characters,
+/// ending in an ellipsis if text was cut
+fn wrap(text: &str, width: usize, max_lines: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    let mut truncated = false;
+
+    'words: for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        loop {
+            let used = line.chars().count();
+            let space = usize::from(used > 0);
+            if used + space + word.len() <= width {
+                if space == 1 {
+                    line.push(' ');
+                }
+                line.extend(word.iter());
+                break;
+            }
+            if used > 0 {
+                if lines.len() + 1 == max_lines {
+                    truncated = true;
+                    break 'words;
+                }
+                lines.push(std::mem::take(&mut line));
+                continue;
+            }
+            // A single word longer than the line is hard-broken
+            line.extend(word.drain(..width));
+            if lines.len() + 1 == max_lines {
+                truncated = true;
+                break 'words;
+            }
+            lines.push(std::mem::take(&mut line));
+        }
+    }
+    if !line.is_empty() && lines.len() < max_lines {
+        lines.push(line);
+    }
+    if truncated {
+        if let Some(last) = lines.last_mut() {
+            while last.chars().count() + 1 > width {
+                last.pop();
+            }
+            last.push('…');
+        }
+    }
+    lines
+}
+
+/// Render part of the canvas as a standalone SVG document
+///
+/// Only notes placed on the canvas are drawn, along with the links between
+/// drawn notes. An empty selection produces an empty, zero-sized drawing.
+pub fn render_svg(notebook: &Notebook, scope: &SvgScope, options: &SvgOptions) -> String {
+    let mut notes: Vec<&Note> = match scope {
+        SvgScope::All => notebook
+            .all_notes()
+            .filter(|n| n.bounds().is_some())
+            .collect(),
+        SvgScope::Region { rect } => notebook.notes_in_rect(*rect),
+        SvgScope::Notes { ids } => {
+            let ids: HashSet<&NoteId> = ids.iter().collect();
+            ids.into_iter()
+                .filter_map(|id| notebook.get_note(id))
+                .filter(|n| n.bounds().is_some())
+                .collect()
+        }
+    };
+    notes.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+
+    // Drawing extent: the region itself, or the notes' bounding box
+    let extent = match scope {
+        SvgScope::Region { rect } => Some(*rect),
+        _ => notes.iter().filter_map(|n| n.bounds()).reduce(|a, b| {
+            Rect::from_corners(
+                Point2D::new(a.x.min(b.x), a.y.min(b.y)),
+                Point2D::new(
+                    (a.x + a.width).max(b.x + b.width),
+                    (a.y + a.height).max(b.y + b.height),
+                ),
+            )
+        }),
+    };
+    let view = extent
+        .map(|r| inflate(&r, options.padding))
+        .unwrap_or(Rect::new(0.0, 0.0, 0.0, 0.0));
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+         viewBox=\"{} {} {} {}\">",
+        fmt(view.width),
+        fmt(view.height),
+        fmt(view.x),
+        fmt(view.y),
+        fmt(view.width),
+        fmt(view.height)
+    );
+    let _ = writeln!(out, "  <title>{}</title>", xml_escape(&notebook.name));
+    let _ = writeln!(
+        out,
+        "  <defs>\n    <marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" \
+         markerWidth=\"8\" markerHeight=\"8\" orient=\"auto-start-reverse\">\n      \
+         <path d=\"M 0 0 L 10 5 L 0 10 z\" fill=\"{}\"/>\n    </marker>",
+        xml_escape(&options.link_color)
+    );
+    for (i, note) in notes.iter().enumerate() {
+        if let Some(b) = note.bounds() {
+            let _ = writeln!(
+                out,
+                "    <clipPath id=\"clip{i}\"><rect x=\"{}\" y=\"{}\" width=\"{}\" \
+                 height=\"{}\"/></clipPath>",
+                fmt(b.x),
+                fmt(b.y),
+                fmt(b.width),
+                fmt(b.height)
+            );
+        }
+    }
+    out.push_str("  </defs>\n");
+
+    if let Some(background) = &options.background {
+        let _ = writeln!(
+            out,
+            "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+            fmt(view.x),
+            fmt(view.y),
+            fmt(view.width),
+            fmt(view.height),
+            xml_escape(background)
+        );
+    }
+
+    if options.adornments {
+        let mut adornments: Vec<_> = notebook
+            .all_adornments()
+            .filter(|a| extent.is_some_and(|e| a.bounds.intersects(&e)))
+            .collect();
+        adornments.sort_by(|a, b| a.name.cmp(&b.name).then(a.id.cmp(&b.id)));
+        for a in adornments {
+            let b = a.bounds;
+            let _ = writeln!(
+                out,
+                "  <g class=\"adornment\">\n    <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" \
+                 fill=\"{}\" fill-opacity=\"{}\" stroke=\"{}\"/>\n    \
+                 <text x=\"{}\" y=\"{}\" font-family=\"{}\" font-size=\"{}\" \
+                 fill=\"{}\">{}</text>\n  </g>",
+                fmt(b.x),
+                fmt(b.y),
+                fmt(b.width),
+                fmt(b.height),
+                xml_escape(a.style.fill.as_deref().unwrap_or("#f0f0f0")),
+                fmt(a.style.opacity.unwrap_or(0.5)),
+                xml_escape(a.style.border.as_deref().unwrap_or("#d9d9d9")),
+                fmt(b.x + 6.0),
+                fmt(b.y + options.font_size * LINE_HEIGHT),
+                xml_escape(&options.font_family),
+                fmt(options.font_size),
+                xml_escape(&options.text_color),
+                xml_escape(&a.name)
+            );
+        }
+    }
+
+    // Links between drawn notes
+    let drawn: HashSet<NoteId> = notes.iter().map(|n| n.id).collect();
+    let links: Vec<(NoteId, NoteId)> = notes
+        .iter()
+        .flat_map(|n| n.links.iter().map(move |t| (n.id, *t)))
+        .filter(|(_, t)| drawn.contains(t))
+        .collect();
+    let routes = routing::route_links(notebook, &links, &options.routing);
+    if !routes.is_empty() {
+        let _ = writeln!(
+            out,
+            "  <g class=\"links\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\">",
+            xml_escape(&options.link_color),
+            fmt(options.link_width)
+        );
+        for route in &routes {
+            let _ = writeln!(
+                out,
+                "    <path d=\"{}\" marker-end=\"url(#arrow)\"/>",
+                route.to_svg_path()
+            );
+        }
+        out.push_str("  </g>\n");
+    }
+
+    // Notes: card, title, then a wrapped content excerpt
+    let font = options.font_size;
+    for (i, note) in notes.iter().enumerate() {
+        let Some(b) = note.bounds() else { continue };
+        let inset = 8.0;
+        let chars_per_line = ((b.width - 2.0 * inset) / (font * CHAR_WIDTH)).floor() as usize;
+        let line = font * LINE_HEIGHT;
+
+        let _ = writeln!(
+            out,
+            "  <g class=\"note\" data-id=\"{}\">\n    <rect x=\"{}\" y=\"{}\" \
+             width=\"{}\" height=\"{}\" rx=\"{}\" fill=\"{}\" stroke=\"{}\"/>",
+            note.id,
+            fmt(b.x),
+            fmt(b.y),
+            fmt(b.width),
+            fmt(b.height),
+            fmt(options.corner_radius),
+            xml_escape(&options.note_fill),
+            xml_escape(&options.note_stroke)
+        );
+        let _ = writeln!(
+            out,
+            "    <g clip-path=\"url(#clip{i})\" font-family=\"{}\" font-size=\"{}\">",
+            xml_escape(&options.font_family),
+            fmt(font)
+        );
+
+        let title = wrap(&note.title, chars_per_line, 1);
+        let mut y = b.y + inset + font;
+        if let Some(title) = title.first() {
+            let _ = writeln!(
+                out,
+                "      <text x=\"{}\" y=\"{}\" font-weight=\"bold\" fill=\"{}\">{}</text>",
+                fmt(b.x + inset),
+                fmt(y),
+                xml_escape(&options.title_color),
+                xml_escape(title)
+            );
+        }
+
+        let excerpt: String = note.content.chars().take(options.excerpt_chars).collect();
+        let room = ((b.y + b.height - inset - y) / line).floor().max(0.0) as usize;
+        if !excerpt.trim().is_empty() && room > 0 {
+            let mut lines = wrap(&excerpt, chars_per_line, room);
+            if excerpt.len() < note.content.len() {
+                if let Some(last) = lines.last_mut() {
+                    if !last.ends_with('…') {
+                        last.push('…');
+                    }
+                }
+            }
+            let _ = writeln!(
+                out,
+                "      <text fill=\"{}\">",
+                xml_escape(&options.text_color)
+            );
+            for text in lines {
+                y += line;
+                let _ = writeln!(
+                    out,
+                    "        <tspan x=\"{}\" y=\"{}\">{}</tspan>",
+                    fmt(b.x + inset),
+                    fmt(y),
+                    xml_escape(&text)
+                );
+            }
+            out.push_str("      </text>\n");
+        }
+        out.push_str("    </g>\n  </g>\n");
+    }
+
+    out.push_str("</svg>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> (Notebook, NoteId, NoteId, NoteId) {
+        let mut notebook = Notebook::new("Report <Q3>");
+        let mut a = Note::new("Plan & scope").with_position(0.0, 0.0);
+        a.content = "Milestones and deliverables for the quarter".into();
+        let a = notebook.add_note(a);
+        let b = notebook.add_note(Note::new("Risks").with_position(400.0, 0.0));
+        let far = notebook.add_note(Note::new("Elsewhere").with_position(5000.0, 5000.0));
+        notebook.link_notes(a, b).unwrap();
+        notebook.link_notes(a, far).unwrap();
+        (notebook, a, b, far)
+    }
+
+    #[test]
+    fn test_render_whole_canvas() {
+        let (notebook, a, _, _) = sample();
+        let svg = render_svg(&notebook, &SvgScope::All, &SvgOptions::default());
+
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert!(svg.contains("<title>Report &lt;Q3&gt;</title>"));
+        assert!(svg.contains(&format!("data-id=\"{a}\"")));
+        assert!(svg.contains(">Plan &amp; scope</text>"));
+        assert!(svg.contains("Milestones and"));
+        assert_eq!(svg.matches("marker-end=").count(), 2);
+        // The view covers every note plus padding
+        assert!(svg.contains("viewBox=\"-20 -20 5240 5140\""));
+    }
+
+    #[test]
+    fn test_render_region_and_selection() {
+        let (notebook, a, b, far) = sample();
+        let options = SvgOptions::default();
+
+        let region = SvgScope::Region {
+            rect: Rect::new(-10.0, -10.0, 700.0, 200.0),
+        };
+        let svg = render_svg(&notebook, &region, &options);
+        assert!(!svg.contains(&far.to_string()));
+        assert_eq!(svg.matches("marker-end=").count(), 1);
+
+        let selection = SvgScope::Notes { ids: vec![a, far] };
+        let svg = render_svg(&notebook, &selection, &options);
+        assert!(!svg.contains(&b.to_string()));
+        assert_eq!(svg.matches("marker-end=").count(), 1);
+    }
+
+    #[test]
+    fn test_empty_selection() {
+        let notebook = Notebook::new("Empty");
+        let scope = SvgScope::Notes { ids: Vec::new() };
+        let svg = render_svg(&notebook, &scope, &SvgOptions::default());
+        assert!(svg.contains("viewBox=\"0 0 0 0\""));
+        assert!(!svg.contains("class=\"note\""));
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("one two three", 7, 5), vec!["one two", "three"]);
+        assert_eq!(wrap("one two three", 7, 1), vec!["one tw…"]);
+        assert_eq!(wrap("abcdefghij", 4, 5), vec!["abcd", "efgh", "ij"]);
+        assert!(wrap("", 10, 3).is_empty());
+    }
+}
@@ -0,0 +1,46 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Testing - a sample notebook and comparisons shared by the storage tests
+
+use crate::format::Versioned;
+use crate::note::{Note, NoteId};
+use crate::notebook::Notebook;
+use serde_json::{json, Value};
+
+/// A notebook exercising every saved field, with the IDs of its plan,
+/// budget and copy notes
+///
+/// The copy shares the plan's title, links to both other notes and has
+/// the plan as its prototype; the budget's title has characters that are
+/// not allowed in file names.
+pub(crate) fn sample() -> (Notebook, NoteId, NoteId, NoteId) {
+    let mut notebook = Notebook::new("Sample");
+    let mut plan = Note::new("Project plan").with_position(100.0, 80.5);
+    plan.size = Some((240.0, 120.0));
+    plan.content = "Milestones\n---\nnot front matter".into();
+    plan.set_attribute("status", json!("active"));
+    plan.set_attribute("priority", json!(2));
+    plan.set_attribute("weight", json!(1.5));
+    plan.set_attribute("tags", json!(["work", "2024"]));
+    plan.set_attribute("review", json!({ "due": "2024-06-01", "done": false }));
+    let plan = notebook.add_note(plan);
+    let budget = notebook.create_note("Budget: 2024/25");
+    let copy = notebook.create_note("Project plan");
+    notebook.link_notes(plan, budget).unwrap();
+    notebook.link_notes(copy, budget).unwrap();
+    notebook.link_notes(copy, plan).unwrap();
+    notebook.get_note_mut(&copy).unwrap().prototype = Some(plan);
+    notebook.create_map("Second");
+    (notebook, plan, budget, copy)
+}
+
+/// The notebook as saved, with backlink sets in a fixed order
+pub(crate) fn saved_form(notebook: &Notebook) -> Value {
+    let mut form = serde_json::to_value(Versioned::new(notebook)).unwrap();
+    for sources in form["backlinks"].as_object_mut().unwrap().values_mut() {
+        sources
+            .as_array_mut()
+            .unwrap()
+            .sort_by_key(|id| id.as_str().unwrap().to_string());
+    }
+    form
+}
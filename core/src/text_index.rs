@@ -0,0 +1,417 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Text index - inverted indexes over note titles and content
+//!
+//! Two indexes are kept side by side. For substring search, titles and
+//! content are normalized by the index's [`Analyzer`] and split into
+//! overlapping character trigrams; a query's candidates are the notes
+//! containing all of its trigrams, which are then checked with a plain
+//! substring test, so results match a direct check exactly while only
+//! touching notes that can possibly match. For ranked search, text is split
+//! into analyzed terms with per-note term frequencies and field lengths.
+
+use crate::analysis::Analyzer;
+use crate::note::{Note, NoteId};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Bound;
+
+/// Three lowercase characters packed into one integer
+type Trigram = u64;
+
+/// Which note fields a search looks at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchField {
+    Title,
+    Content,
+    Any,
+}
+
+fn pack(a: char, b: char, c: char) -> Trigram {
+    ((a as u64) << 42) | ((b as u64) << 21) | c as u64
+}
+
+/// Distinct trigrams of already-lowercased text
+fn trigrams(text: &str) -> HashSet<Trigram> {
+    let chars: Vec<char> = text.chars().collect();
+    chars.windows(3).map(|w| pack(w[0], w[1], w[2])).collect()
+}
+
+/// How often a term occurs in one note
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TermFrequency {
+    pub title: u32,
+    pub content: u32,
+}
+
+/// Text of one note, as last indexed
+#[derive(Debug, Clone, Default)]
+struct Indexed {
+    /// Normalized title
+    title: String,
+
+    /// Normalized content
+    content: String,
+
+    /// Distinct words and their frequencies
+    words: HashMap<String, TermFrequency>,
+
+    /// Number of words in the title and in the content
+    lengths: (u32, u32),
+}
+
+/// Inverted trigram and word indexes over note titles and content
+#[derive(Debug, Clone, Default)]
+pub struct TextIndex {
+    analyzer: Analyzer,
+    title: HashMap<Trigram, HashSet<NoteId>>,
+    content: HashMap<Trigram, HashSet<NoteId>>,
+    terms: BTreeMap<String, HashMap<NoteId, TermFrequency>>,
+    total_lengths: (u64, u64),
+    docs: HashMap<NoteId, Indexed>,
+}
+
+impl TextIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty index analyzing text with the given analyzer
+    pub fn with_analyzer(analyzer: Analyzer) -> Self {
+        Self {
+            analyzer,
+            ..Self::default()
+        }
+    }
+
+    /// Build an index over many notes
+    pub fn build<'a>(analyzer: Analyzer, notes: impl IntoIterator<Item = &'a Note>) -> Self {
+        let mut index = Self::with_analyzer(analyzer);
+        for note in notes {
+            index.insert(note);
+        }
+        index
+    }
+
+    /// The analyzer applied to indexed text and queries
+    pub fn analyzer(&self) -> &Analyzer {
+        &self.analyzer
+    }
+
+    /// Number of indexed notes
+    pub fn len(&self) -> usize {
+        self.docs.len()
+    }
+
+    /// Check if the index is empty
+    pub fn is_empty(&self) -> bool {
+        self.docs.is_empty()
+    }
+
+    /// Index a note, replacing any previous entry for it
+    ///
+    /// Does nothing if the note's title and content are unchanged.
+    pub fn insert(&mut self, note: &Note) {
+        let title = self.analyzer.normalize(&note.title);
+        let content = self.analyzer.normalize(&note.content);
+        if let Some(existing) = self.docs.get(&note.id) {
+            if existing.title == title && existing.content == content {
+                return;
+            }
+        }
+        self.remove(&note.id);
+
+        for gram in trigrams(&title) {
+            self.title.entry(gram).or_default().insert(note.id);
+        }
+        for gram in trigrams(&content) {
+            self.content.entry(gram).or_default().insert(note.id);
+        }
+
+        let (words, lengths) = word_counts(&self.analyzer, note);
+        for (term, frequency) in &words {
+            self.terms
+                .entry(term.clone())
+                .or_default()
+                .insert(note.id, *frequency);
+        }
+        self.total_lengths.0 += u64::from(lengths.0);
+        self.total_lengths.1 += u64::from(lengths.1);
+
+        self.docs.insert(
+            note.id,
+            Indexed {
+                title,
+                content,
+                words,
+                lengths,
+            },
+        );
+    }
+
+    /// Remove a note from the index
+    pub fn remove(&mut self, id: &NoteId) {
+        let Some(doc) = self.docs.remove(id) else {
+            return;
+        };
+        for (postings, text) in [
+            (&mut self.title, &doc.title),
+            (&mut self.content, &doc.content),
+        ] {
+            for gram in trigrams(text) {
+                if let Some(ids) = postings.get_mut(&gram) {
+                    ids.remove(id);
+                    if ids.is_empty() {
+                        postings.remove(&gram);
+                    }
+                }
+            }
+        }
+        for term in doc.words.keys() {
+            if let Some(ids) = self.terms.get_mut(term) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    self.terms.remove(term);
+                }
+            }
+        }
+        self.total_lengths.0 -= u64::from(doc.lengths.0);
+        self.total_lengths.1 -= u64::from(doc.lengths.1);
+    }
+
+    /// Notes containing a word, with how often it occurs in each
+    pub fn postings(&self, term: &str) -> Option<&HashMap<NoteId, TermFrequency>> {
+        self.terms.get(term)
+    }
+
+    /// Number of notes containing a word
+    pub fn document_frequency(&self, term: &str) -> usize {
+        self.terms.get(term).map_or(0, HashMap::len)
+    }
+
+    /// Indexed words starting with the prefix, in order
+    pub fn terms_with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a str> {
+        self.terms
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .map(|(term, _)| term.as_str())
+            .take_while(move |term| term.starts_with(prefix))
+    }
+
+    /// Number of words in an indexed note's title and content
+    pub fn document_lengths(&self, id: &NoteId) -> Option<(u32, u32)> {
+        self.docs.get(id).map(|doc| doc.lengths)
+    }
+
+    /// Average number of words per title and per content
+    pub fn average_lengths(&self) -> (f64, f64) {
+        if self.docs.is_empty() {
+            return (0.0, 0.0);
+        }
+        let n = self.docs.len() as f64;
+        (
+            self.total_lengths.0 as f64 / n,
+            self.total_lengths.1 as f64 / n,
+        )
+    }
+
+    /// Check if an indexed note's field contains the (normalized) query
+    fn matches(&self, id: &NoteId, query: &str, field: SearchField) -> bool {
+        let Some(doc) = self.docs.get(id) else {
+            return false;
+        };
+        match field {
+            SearchField::Title => doc.title.contains(query),
+            SearchField::Content => doc.content.contains(query),
+            SearchField::Any => doc.title.contains(query) || doc.content.contains(query),
+        }
+    }
+
+    /// Candidate notes for one field: those containing every query trigram
+    fn candidates(
+        postings: &HashMap<Trigram, HashSet<NoteId>>,
+        grams: &HashSet<Trigram>,
+    ) -> HashSet<NoteId> {
+        let mut lists: Vec<&HashSet<NoteId>> = Vec::with_capacity(grams.len());
+        for gram in grams {
+            match postings.get(gram) {
+                Some(ids) => lists.push(ids),
+                None => return HashSet::new(),
+            }
+        }
+        // Intersect starting from the rarest trigram
+        lists.sort_by_key(|ids| ids.len());
+        let Some((first, rest)) = lists.split_first() else {
+            return HashSet::new();
+        };
+        first
+            .iter()
+            .filter(|id| rest.iter().all(|ids| ids.contains(id)))
+            .copied()
+            .collect()
+    }
+
+    /// IDs of indexed notes whose field contains the query, compared after
+    /// normalization
+    pub fn search(&self, query: &str, field: SearchField) -> Vec<NoteId> {
+        let query = self.analyzer.normalize(query);
+        let grams = trigrams(&query);
+
+        // Queries shorter than a trigram cannot use the postings
+        if grams.is_empty() {
+            return self
+                .docs
+                .keys()
+                .filter(|id| self.matches(id, &query, field))
+                .copied()
+                .collect();
+        }
+
+        let mut candidates = match field {
+            SearchField::Title => Self::candidates(&self.title, &grams),
+            SearchField::Content => Self::candidates(&self.content, &grams),
+            SearchField::Any => {
+                let mut ids = Self::candidates(&self.title, &grams);
+                ids.extend(Self::candidates(&self.content, &grams));
+                ids
+            }
+        };
+        candidates.retain(|id| self.matches(id, &query, field));
+        candidates.into_iter().collect()
+    }
+}
+
+/// Term frequencies and field lengths of a note
+pub fn word_counts(
+    analyzer: &Analyzer,
+    note: &Note,
+) -> (HashMap<String, TermFrequency>, (u32, u32)) {
+    let mut words: HashMap<String, TermFrequency> = HashMap::new();
+    let title = analyzer.tokenize(&note.title);
+    let content = analyzer.tokenize(&note.content);
+    for token in &title {
+        words.entry(token.term.clone()).or_default().title += 1;
+    }
+    for token in &content {
+        words.entry(token.term.clone()).or_default().content += 1;
+    }
+    (words, (title.len() as u32, content.len() as u32))
+}
+
+/// Check a note directly, with the same semantics as [`TextIndex::search`]
+pub fn note_matches(analyzer: &Analyzer, note: &Note, query: &str, field: SearchField) -> bool {
+    let query = analyzer.normalize(query);
+    let title = || analyzer.normalize(&note.title).contains(&query);
+    let content = || analyzer.normalize(&note.content).contains(&query);
+    match field {
+        SearchField::Title => title(),
+        SearchField::Content => content(),
+        SearchField::Any => title() || content(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::AnalysisConfig;
+
+    fn note(title: &str, content: &str) -> Note {
+        let mut note = Note::new(title);
+        note.content = content.into();
+        note
+    }
+
+    #[test]
+    fn test_substring_semantics() {
+        let a = note("Project Plan", "Milestones");
+        let b = note("Meeting", "About the PROJECT timeline");
+        let index = TextIndex::build(Analyzer::default(), [&a, &b]);
+
+        let mut hits = index.search("roject", SearchField::Any);
+        hits.sort();
+        let mut expected = vec![a.id, b.id];
+        expected.sort();
+        assert_eq!(hits, expected);
+
+        assert_eq!(index.search("roject", SearchField::Title), vec![a.id]);
+        assert_eq!(index.search("t t", SearchField::Content), vec![b.id]);
+        // Short queries fall back to checking every note
+        assert_eq!(index.search("Mi", SearchField::Content), vec![a.id]);
+        assert_eq!(index.search("", SearchField::Title).len(), 2);
+        assert!(index.search("absent", SearchField::Any).is_empty());
+    }
+
+    #[test]
+    fn test_update_and_remove() {
+        let mut a = note("Draft", "first version");
+        let mut index = TextIndex::build(Analyzer::default(), [&a]);
+        assert_eq!(index.search("first", SearchField::Content), vec![a.id]);
+
+        a.content = "second version".into();
+        index.insert(&a);
+        assert!(index.search("first", SearchField::Content).is_empty());
+        assert_eq!(index.search("second", SearchField::Content), vec![a.id]);
+
+        index.remove(&a.id);
+        assert!(index.is_empty());
+        assert!(index.search("version", SearchField::Any).is_empty());
+        assert!(index.title.is_empty() && index.content.is_empty());
+        assert!(index.terms.is_empty());
+        assert_eq!(index.total_lengths, (0, 0));
+    }
+
+    #[test]
+    fn test_word_index() {
+        let a = note("Rust notes", "Rust ownership, rust borrowing");
+        let b = note("Gardening", "Roses need sun");
+        let index = TextIndex::build(Analyzer::default(), [&a, &b]);
+
+        assert_eq!(
+            index.postings("rust").unwrap()[&a.id],
+            TermFrequency {
+                title: 1,
+                content: 2
+            }
+        );
+        assert_eq!(index.document_frequency("roses"), 1);
+        assert_eq!(index.document_lengths(&a.id), Some((2, 4)));
+        assert_eq!(index.average_lengths(), (1.5, 3.5));
+        assert_eq!(
+            index.terms_with_prefix("ro").collect::<Vec<_>>(),
+            vec!["roses"]
+        );
+    }
+
+    #[test]
+    fn test_unicode_case_folding() {
+        let a = note("ÜBER Straße", "");
+        let index = TextIndex::build(Analyzer::default(), [&a]);
+        assert_eq!(index.search("über", SearchField::Title), vec![a.id]);
+        // Agrees with the direct check even where lowercasing is not a
+        // full case fold
+        for query in ["STRASSE", "straße", "ber s"] {
+            assert_eq!(
+                note_matches(index.analyzer(), &a, query, SearchField::Title),
+                !index.search(query, SearchField::Title).is_empty()
+            );
+        }
+    }
+
+    #[test]
+    fn test_diacritics_follow_analyzer() {
+        let a = note("Café menu", "Crème brûlée");
+        let index = TextIndex::build(Analyzer::default(), [&a]);
+        assert_eq!(index.search("cafe", SearchField::Title), vec![a.id]);
+        assert_eq!(
+            index.search("CRÈME BRULEE", SearchField::Content),
+            vec![a.id]
+        );
+
+        let strict = TextIndex::build(
+            Analyzer::new(AnalysisConfig {
+                fold_diacritics: false,
+                ..AnalysisConfig::default()
+            }),
+            [&a],
+        );
+        assert!(strict.search("cafe", SearchField::Title).is_empty());
+        assert_eq!(strict.search("café", SearchField::Title), vec![a.id]);
+    }
+}
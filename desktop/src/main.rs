@@ -3,17 +3,43 @@
 
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use nexia_core::{Notebook, Note, NoteId, Storage, storage::JsonStorage};
+use nexia_core::{
+    agent::{Agent, AgentAction, AgentRun, Schedule},
+    analysis::AnalysisConfig,
+    arrange::ArrangeOp,
+    binary::BinaryStorage,
+    embedding::{SimilarNote, VectorIndex},
+    encryption::EncryptedStorage,
+    facet::{Facet, FacetOptions, FacetSelection, NoteGroup},
+    fuzzy::{FuzzyMatch, FuzzyOptions},
+    journal::{Journal, Replayed},
+    markdown::MarkdownStorage,
+    query::Query,
+    replace::{FindOptions, RegexMatch, ReplacePlan},
+    search::{SearchHit, SearchOptions},
+    sqlite::SqliteStorage,
+    Notebook, Note, NoteId, Storage, storage::JsonStorage,
+};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use std::sync::Mutex;
-use tauri::State;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use tauri::{AppHandle, Emitter, Manager, State};
 
 /// Application state shared across commands
 struct AppState {
     notebook: Mutex<Notebook>,
     file_path: Mutex<Option<PathBuf>>,
+    /// Changes since the last save, for notebooks that have a file
+    journal: Mutex<Option<Journal>>,
+    /// Passphrase the notebook file is encrypted with, if any
+    passphrase: Mutex<Option<String>>,
+    /// Handle for sending events to the frontend, set once the app starts
+    app: OnceLock<AppHandle>,
     storage: JsonStorage,
+    binary: BinaryStorage,
+    markdown: MarkdownStorage,
+    sqlite: SqliteStorage,
 }
 
 impl Default for AppState {
@@ -21,7 +47,60 @@ impl Default for AppState {
         Self {
             notebook: Mutex::new(Notebook::new("Untitled")),
             file_path: Mutex::new(None),
+            journal: Mutex::new(None),
+            passphrase: Mutex::new(None),
+            app: OnceLock::new(),
             storage: JsonStorage::new(),
+            binary: BinaryStorage::new(),
+            markdown: MarkdownStorage::new(),
+            sqlite: SqliteStorage::new(),
+        }
+    }
+}
+
+impl AppState {
+    /// Lock the notebook to change it
+    fn edit(&self) -> Editing<'_> {
+        Editing {
+            notebook: self.notebook.lock().unwrap(),
+            journal: &self.journal,
+            app: &self.app,
+        }
+    }
+}
+
+/// The notebook locked for changes, which are journaled when the lock is
+/// released so that they survive a crash before the next save
+struct Editing<'a> {
+    notebook: MutexGuard<'a, Notebook>,
+    journal: &'a Mutex<Option<Journal>>,
+    app: &'a OnceLock<AppHandle>,
+}
+
+impl Deref for Editing<'_> {
+    type Target = Notebook;
+
+    fn deref(&self) -> &Notebook {
+        &self.notebook
+    }
+}
+
+impl DerefMut for Editing<'_> {
+    fn deref_mut(&mut self) -> &mut Notebook {
+        &mut self.notebook
+    }
+}
+
+impl Drop for Editing<'_> {
+    fn drop(&mut self) {
+        if let Some(journal) = self.journal.lock().unwrap().as_mut() {
+            // The changes stay pending and are retried with the next edit,
+            // but the frontend is told they are not yet safe from a crash
+            if let Err(e) = journal.record(&mut self.notebook) {
+                if let Some(app) = self.app.get() {
+                    let _ = app.emit("journal-error", e.to_string());
+                }
+            }
         }
     }
 }
@@ -55,7 +134,7 @@ impl<T> CommandResponse<T> {
 /// Create a new note
 #[tauri::command]
 fn create_note(state: State<AppState>, title: String) -> CommandResponse<Note> {
-    let mut notebook = state.notebook.lock().unwrap();
+    let mut notebook = state.edit();
     let note = Note::new(title);
     let id = note.id;
     notebook.add_note(note);
@@ -92,7 +171,7 @@ fn get_all_notes(state: State<AppState>) -> CommandResponse<Vec<Note>> {
 /// Update a note's title
 #[tauri::command]
 fn update_note_title(state: State<AppState>, id: String, title: String) -> CommandResponse<Note> {
-    let mut notebook = state.notebook.lock().unwrap();
+    let mut notebook = state.edit();
     let uuid = match uuid::Uuid::parse_str(&id) {
         Ok(uuid) => uuid,
         Err(_) => return CommandResponse::err("Invalid note ID"),
@@ -110,7 +189,7 @@ fn update_note_title(state: State<AppState>, id: String, title: String) -> Comma
 /// Update a note's content
 #[tauri::command]
 fn update_note_content(state: State<AppState>, id: String, content: String) -> CommandResponse<Note> {
-    let mut notebook = state.notebook.lock().unwrap();
+    let mut notebook = state.edit();
     let uuid = match uuid::Uuid::parse_str(&id) {
         Ok(uuid) => uuid,
         Err(_) => return CommandResponse::err("Invalid note ID"),
@@ -128,7 +207,7 @@ fn update_note_content(state: State<AppState>, id: String, content: String) -> C
 /// Delete a note
 #[tauri::command]
 fn delete_note(state: State<AppState>, id: String) -> CommandResponse<()> {
-    let mut notebook = state.notebook.lock().unwrap();
+    let mut notebook = state.edit();
     let uuid = match uuid::Uuid::parse_str(&id) {
         Ok(uuid) => uuid,
         Err(_) => return CommandResponse::err("Invalid note ID"),
@@ -143,7 +222,7 @@ fn delete_note(state: State<AppState>, id: String) -> CommandResponse<()> {
 /// Link two notes
 #[tauri::command]
 fn link_notes(state: State<AppState>, from_id: String, to_id: String) -> CommandResponse<()> {
-    let mut notebook = state.notebook.lock().unwrap();
+    let mut notebook = state.edit();
 
     let from_uuid = match uuid::Uuid::parse_str(&from_id) {
         Ok(uuid) => uuid,
@@ -161,18 +240,317 @@ fn link_notes(state: State<AppState>, from_id: String, to_id: String) -> Command
     }
 }
 
-/// Search notes
+/// Align, distribute, size-match or snap a selection of notes
+#[tauri::command]
+fn arrange_notes(
+    state: State<AppState>,
+    ids: Vec<String>,
+    operation: ArrangeOp,
+) -> CommandResponse<Vec<Note>> {
+    let mut notebook = state.edit();
+
+    let mut uuids: Vec<NoteId> = Vec::with_capacity(ids.len());
+    for id in &ids {
+        match uuid::Uuid::parse_str(id) {
+            Ok(uuid) => uuids.push(uuid),
+            Err(_) => return CommandResponse::err("Invalid note ID"),
+        }
+    }
+
+    match notebook.arrange_notes(&uuids, &operation) {
+        Ok(changed) => CommandResponse::ok(
+            changed
+                .iter()
+                .filter_map(|id| notebook.get_note(id).cloned())
+                .collect(),
+        ),
+        Err(e) => CommandResponse::err(e.to_string()),
+    }
+}
+
+/// Search notes, best matches first, with snippets and highlight ranges
+#[tauri::command]
+fn search_notes(
+    state: State<AppState>,
+    query: String,
+    options: Option<SearchOptions>,
+) -> CommandResponse<Vec<SearchHit>> {
+    let notebook = state.notebook.lock().unwrap();
+    let options = options.unwrap_or_default();
+    CommandResponse::ok(notebook.search_ranked(&query, &options))
+}
+
+/// Get the notebook's text analysis settings
+#[tauri::command]
+fn get_analysis_config(state: State<AppState>) -> CommandResponse<AnalysisConfig> {
+    let notebook = state.notebook.lock().unwrap();
+    CommandResponse::ok(notebook.analysis_config().clone())
+}
+
+/// Change the notebook's text analysis settings, reindexing all notes
+#[tauri::command]
+fn set_analysis_config(state: State<AppState>, config: AnalysisConfig) -> CommandResponse<()> {
+    let mut notebook = state.edit();
+    notebook.set_analysis_config(config);
+    CommandResponse::ok(())
+}
+
+/// Notes similar in meaning to a note
+#[tauri::command]
+fn similar_notes(
+    state: State<AppState>,
+    id: String,
+    limit: Option<usize>,
+) -> CommandResponse<Vec<SimilarNote>> {
+    let note_id = match uuid::Uuid::parse_str(&id) {
+        Ok(id) => id,
+        Err(_) => return CommandResponse::err("Invalid note ID"),
+    };
+    let mut notebook = state.notebook.lock().unwrap();
+    match notebook.similar_notes(&note_id, limit.unwrap_or(10)) {
+        Ok(similar) => CommandResponse::ok(similar),
+        Err(e) => CommandResponse::err(e.to_string()),
+    }
+}
+
+/// Notes similar in meaning to a piece of text
+#[tauri::command]
+fn semantic_search(
+    state: State<AppState>,
+    query: String,
+    limit: Option<usize>,
+) -> CommandResponse<Vec<SimilarNote>> {
+    let mut notebook = state.notebook.lock().unwrap();
+    match notebook.semantic_search(&query, limit.unwrap_or(10)) {
+        Ok(similar) => CommandResponse::ok(similar),
+        Err(e) => CommandResponse::err(e.to_string()),
+    }
+}
+
+/// Fuzzy-match note titles for the quick switcher
+#[tauri::command]
+fn quick_switch(
+    state: State<AppState>,
+    pattern: String,
+    options: Option<FuzzyOptions>,
+) -> CommandResponse<Vec<FuzzyMatch>> {
+    let notebook = state.notebook.lock().unwrap();
+    let options = options.unwrap_or_default();
+    CommandResponse::ok(notebook.quick_switch(&pattern, &options))
+}
+
+/// Regex matches across titles, content and string attributes
+#[tauri::command]
+fn find_regex(
+    state: State<AppState>,
+    pattern: String,
+    options: Option<FindOptions>,
+) -> CommandResponse<Vec<RegexMatch>> {
+    let notebook = state.notebook.lock().unwrap();
+    let options = options.unwrap_or_default();
+    match notebook.find_regex(&pattern, &options) {
+        Ok(matches) => CommandResponse::ok(matches),
+        Err(e) => CommandResponse::err(e.to_string()),
+    }
+}
+
+/// Preview a notebook-wide find-and-replace
+#[tauri::command]
+fn preview_replace(
+    state: State<AppState>,
+    pattern: String,
+    replacement: String,
+    options: Option<FindOptions>,
+) -> CommandResponse<ReplacePlan> {
+    let notebook = state.notebook.lock().unwrap();
+    let options = options.unwrap_or_default();
+    match notebook.preview_replace(&pattern, &replacement, &options) {
+        Ok(plan) => CommandResponse::ok(plan),
+        Err(e) => CommandResponse::err(e.to_string()),
+    }
+}
+
+/// Apply a previewed replacement, returning the plan that undoes it
+#[tauri::command]
+fn apply_replace(state: State<AppState>, plan: ReplacePlan) -> CommandResponse<ReplacePlan> {
+    let mut notebook = state.edit();
+    match notebook.apply_replace(&plan) {
+        Ok(undo) => CommandResponse::ok(undo),
+        Err(e) => CommandResponse::err(e.to_string()),
+    }
+}
+
+/// Attribute facets over the notes matching the selections
+#[tauri::command]
+fn attribute_facets(
+    state: State<AppState>,
+    selections: Vec<FacetSelection>,
+    options: Option<FacetOptions>,
+) -> CommandResponse<Vec<Facet>> {
+    let notebook = state.notebook.lock().unwrap();
+    let options = options.unwrap_or_default();
+    CommandResponse::ok(notebook.facets(&selections, &options))
+}
+
+/// Notes matching every facet selection
 #[tauri::command]
-fn search_notes(state: State<AppState>, query: String) -> CommandResponse<Vec<Note>> {
+fn filter_notes(
+    state: State<AppState>,
+    selections: Vec<FacetSelection>,
+) -> CommandResponse<Vec<Note>> {
     let notebook = state.notebook.lock().unwrap();
-    let results: Vec<Note> = notebook.search(&query).into_iter().cloned().collect();
-    CommandResponse::ok(results)
+    let notes = notebook.filter_by_facets(&selections).into_iter().cloned().collect();
+    CommandResponse::ok(notes)
+}
+
+/// Group notes by an attribute for list and kanban views
+#[tauri::command]
+fn group_notes(state: State<AppState>, key: String) -> CommandResponse<Vec<NoteGroup>> {
+    let notebook = state.notebook.lock().unwrap();
+    CommandResponse::ok(notebook.group_by_attribute(&key))
+}
+
+/// An agent together with its current matches
+#[derive(Serialize)]
+struct AgentInfo {
+    agent: Agent,
+    results: Vec<NoteId>,
+}
+
+fn agent_info(notebook: &Notebook, agent: &Agent) -> AgentInfo {
+    let results = notebook
+        .agent_results(&agent.id)
+        .unwrap_or_default()
+        .iter()
+        .map(|note| note.id)
+        .collect();
+    AgentInfo {
+        agent: agent.clone(),
+        results,
+    }
+}
+
+/// Create an agent from a query
+#[tauri::command]
+fn create_agent(
+    state: State<AppState>,
+    name: String,
+    query: String,
+    action: Option<AgentAction>,
+    schedule: Option<Schedule>,
+) -> CommandResponse<AgentInfo> {
+    let mut notebook = state.edit();
+    let query = match Query::parse(&query) {
+        Ok(query) => query,
+        Err(e) => return CommandResponse::err(e.to_string()),
+    };
+
+    let agent = Agent::new(name, query)
+        .with_action(action.unwrap_or_default())
+        .with_schedule(schedule.unwrap_or_default());
+    let id = notebook.add_agent(agent);
+    if let Err(e) = notebook.run_agent(&id) {
+        return CommandResponse::err(e.to_string());
+    }
+    match notebook.get_agent(&id) {
+        Some(agent) => CommandResponse::ok(agent_info(&notebook, agent)),
+        None => CommandResponse::err("Agent not found"),
+    }
+}
+
+/// List agents with their matches, running any that are due
+#[tauri::command]
+fn list_agents(state: State<AppState>) -> CommandResponse<Vec<AgentInfo>> {
+    let mut notebook = state.edit();
+    notebook.refresh_agents(chrono::Utc::now());
+    let agents = notebook
+        .all_agents()
+        .map(|agent| agent_info(&notebook, agent))
+        .collect();
+    CommandResponse::ok(agents)
+}
+
+/// Result of running an agent: what it changed and the notes it matched
+#[derive(Serialize)]
+struct AgentRunInfo {
+    run: AgentRun,
+    matches: Vec<Note>,
+}
+
+/// Run an agent now, or with `dry_run` only preview its changes
+#[tauri::command]
+fn run_agent(
+    state: State<AppState>,
+    id: String,
+    dry_run: Option<bool>,
+) -> CommandResponse<AgentRunInfo> {
+    let mut notebook = state.edit();
+    let uuid = match uuid::Uuid::parse_str(&id) {
+        Ok(uuid) => uuid,
+        Err(_) => return CommandResponse::err("Invalid agent ID"),
+    };
+
+    let result = if dry_run.unwrap_or(false) {
+        notebook.preview_agent(&uuid)
+    } else {
+        notebook.run_agent(&uuid)
+    };
+    match result {
+        Ok(run) => {
+            let matches = notebook
+                .agent_results(&uuid)
+                .unwrap_or_default()
+                .into_iter()
+                .cloned()
+                .collect();
+            CommandResponse::ok(AgentRunInfo { run, matches })
+        }
+        Err(e) => CommandResponse::err(e.to_string()),
+    }
+}
+
+/// Delete an agent
+#[tauri::command]
+fn delete_agent(state: State<AppState>, id: String) -> CommandResponse<()> {
+    let mut notebook = state.edit();
+    let uuid = match uuid::Uuid::parse_str(&id) {
+        Ok(uuid) => uuid,
+        Err(_) => return CommandResponse::err("Invalid agent ID"),
+    };
+
+    match notebook.remove_agent(&uuid) {
+        Some(_) => CommandResponse::ok(()),
+        None => CommandResponse::err("Agent not found"),
+    }
+}
+
+/// How a notebook is stored at a path
+enum Backend {
+    Json,
+    Binary,
+    Markdown,
+    Sqlite,
+}
+
+/// Folders, and paths without an extension, hold Markdown files; `.db`
+/// and `.sqlite` files are databases; `.nxb` files are compressed binary;
+/// anything else is a JSON file
+fn backend(path: &Path) -> Backend {
+    if path.is_dir() {
+        return Backend::Markdown;
+    }
+    match path.extension().and_then(|ext| ext.to_str()) {
+        None => Backend::Markdown,
+        Some("db" | "sqlite") => Backend::Sqlite,
+        Some("nxb") => Backend::Binary,
+        Some(_) => Backend::Json,
+    }
 }
 
 /// Save notebook to file
 #[tauri::command]
 fn save_notebook(state: State<AppState>, path: Option<String>) -> CommandResponse<String> {
-    let notebook = state.notebook.lock().unwrap();
+    let mut notebook = state.notebook.lock().unwrap();
     let mut file_path = state.file_path.lock().unwrap();
 
     let save_path = match path {
@@ -187,24 +565,139 @@ fn save_notebook(state: State<AppState>, path: Option<String>) -> CommandRespons
         },
     };
 
-    match state.storage.save(&notebook, &save_path) {
+    let passphrase = state.passphrase.lock().unwrap().clone();
+    let saved = match (backend(&save_path), passphrase) {
+        (Backend::Json, None) => state.storage.save(&notebook, &save_path),
+        (Backend::Binary, None) => state.binary.save(&notebook, &save_path),
+        (Backend::Markdown, None) => state.markdown.save(&notebook, &save_path),
+        (Backend::Sqlite, None) => state.sqlite.save(&notebook, &save_path),
+        (Backend::Json, Some(passphrase)) => {
+            EncryptedStorage::new(JsonStorage::new(), passphrase).save(&notebook, &save_path)
+        }
+        (Backend::Binary, Some(passphrase)) => {
+            EncryptedStorage::new(BinaryStorage::new(), passphrase).save(&notebook, &save_path)
+        }
+        (Backend::Markdown | Backend::Sqlite, Some(_)) => {
+            return CommandResponse::err("Only JSON and binary notebook files can be encrypted")
+        }
+    };
+    if let Err(e) = saved {
+        return CommandResponse::err(e.to_string());
+    }
+
+    // The journal and similarity vectors are not encrypted, so an
+    // encrypted notebook keeps neither
+    if state.passphrase.lock().unwrap().is_some() {
+        *state.journal.lock().unwrap() = None;
+        let _ = std::fs::remove_file(Journal::path_for(&save_path));
+        let _ = std::fs::remove_file(VectorIndex::path_for(&save_path));
+        return CommandResponse::ok(save_path.display().to_string());
+    }
+
+    // The saved notebook holds every journaled change, so the journal
+    // starts over
+    match Journal::create(Journal::path_for(&save_path), &mut notebook) {
+        Ok(journal) => *state.journal.lock().unwrap() = Some(journal),
+        Err(e) => return CommandResponse::err(e.to_string()),
+    }
+
+    // Keep similarity vectors next to the notebook so they need not be
+    // recomputed on the next load
+    if let Err(e) = notebook.refresh_vectors() {
+        return CommandResponse::err(e.to_string());
+    }
+    match notebook.vector_index().save(&VectorIndex::path_for(&save_path)) {
         Ok(_) => CommandResponse::ok(save_path.display().to_string()),
         Err(e) => CommandResponse::err(e.to_string()),
     }
 }
 
+/// A loaded notebook
+#[derive(Serialize)]
+struct OpenedNotebook {
+    notebook: Notebook,
+    /// Backup opened because the file itself was damaged, so the user can
+    /// be told
+    recovered_from: Option<String>,
+}
+
 /// Load notebook from file
+///
+/// Encrypted files need their passphrase, which is then used for later
+/// saves; without it the error says a passphrase is required.
 #[tauri::command]
-fn load_notebook(state: State<AppState>, path: String) -> CommandResponse<Notebook> {
+fn load_notebook(
+    state: State<AppState>,
+    path: String,
+    passphrase: Option<String>,
+) -> CommandResponse<OpenedNotebook> {
     let path = PathBuf::from(&path);
 
-    match state.storage.load(&path) {
-        Ok(loaded) => {
+    if let Some(passphrase) = passphrase {
+        // JSON storage also reads binary contents once decrypted
+        let storage = EncryptedStorage::new(JsonStorage::new(), passphrase.clone());
+        // A damaged file is replaced by its newest backup that opens with
+        // the passphrase; a wrong passphrase is reported instead
+        let loaded = match backend(&path) {
+            Backend::Json | Backend::Binary => storage.load_or_recover(&path),
+            Backend::Markdown | Backend::Sqlite => {
+                return CommandResponse::err("Only JSON and binary notebook files can be encrypted")
+            }
+        };
+        return match loaded {
+            Ok(loaded) => {
+                *state.notebook.lock().unwrap() = loaded.notebook.clone();
+                *state.file_path.lock().unwrap() = Some(path);
+                *state.journal.lock().unwrap() = None;
+                *state.passphrase.lock().unwrap() = Some(passphrase);
+                CommandResponse::ok(OpenedNotebook {
+                    notebook: loaded.notebook,
+                    recovered_from: loaded
+                        .recovered_from
+                        .map(|backup| backup.display().to_string()),
+                })
+            }
+            Err(e) => CommandResponse::err(e.to_string()),
+        };
+    }
+
+    // A damaged JSON or binary file is replaced by its newest readable
+    // backup; the file's header tells the two apart
+    let mut recovered_from = None;
+    let loaded = match backend(&path) {
+        Backend::Json | Backend::Binary => state.storage.load_or_recover(&path).map(|loaded| {
+            recovered_from = loaded.recovered_from;
+            loaded.notebook
+        }),
+        Backend::Markdown => state.markdown.load(&path),
+        Backend::Sqlite => state.sqlite.load(&path),
+    };
+
+    // Changes made after the last save are recovered from the journal
+    let journal_path = Journal::path_for(&path);
+    let replayed = loaded.and_then(|loaded| Journal::replay(&journal_path, loaded));
+    match replayed {
+        Ok(Replayed {
+            notebook: mut loaded,
+            ..
+        }) => {
+            let journal = match Journal::resume(journal_path, &mut loaded) {
+                Ok(journal) => journal,
+                Err(e) => return CommandResponse::err(e.to_string()),
+            };
+            if let Ok(vectors) = VectorIndex::load(&VectorIndex::path_for(&path)) {
+                loaded.load_vector_index(vectors);
+            }
             let mut notebook = state.notebook.lock().unwrap();
             let mut file_path = state.file_path.lock().unwrap();
             *notebook = loaded.clone();
             *file_path = Some(path);
-            CommandResponse::ok(loaded)
+            *state.journal.lock().unwrap() = Some(journal);
+            *state.passphrase.lock().unwrap() = None;
+            CommandResponse::ok(OpenedNotebook {
+                notebook: loaded,
+                recovered_from: recovered_from.map(|backup| backup.display().to_string()),
+            })
         }
         Err(e) => CommandResponse::err(e.to_string()),
     }
@@ -217,14 +710,48 @@ fn new_notebook(state: State<AppState>, name: String) -> CommandResponse<()> {
     let mut file_path = state.file_path.lock().unwrap();
     *notebook = Notebook::new(name);
     *file_path = None;
+    *state.journal.lock().unwrap() = None;
+    *state.passphrase.lock().unwrap() = None;
+    CommandResponse::ok(())
+}
+
+/// Encrypt the notebook with a passphrase from the next save on, or stop
+/// encrypting it with `None`
+#[tauri::command]
+fn set_passphrase(state: State<AppState>, passphrase: Option<String>) -> CommandResponse<()> {
+    *state.passphrase.lock().unwrap() = passphrase;
     CommandResponse::ok(())
 }
 
+/// Change the passphrase of the open notebook's encrypted file in place
+#[tauri::command]
+fn change_passphrase(
+    state: State<AppState>,
+    old_passphrase: String,
+    new_passphrase: String,
+) -> CommandResponse<()> {
+    let Some(path) = state.file_path.lock().unwrap().clone() else {
+        return CommandResponse::err("No file path specified");
+    };
+    let mut storage = EncryptedStorage::new(JsonStorage::new(), old_passphrase);
+    match storage.change_passphrase(&path, new_passphrase.clone()) {
+        Ok(()) => {
+            *state.passphrase.lock().unwrap() = Some(new_passphrase);
+            CommandResponse::ok(())
+        }
+        Err(e) => CommandResponse::err(e.to_string()),
+    }
+}
+
 fn main() {
     tauri::Builder::default()
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_dialog::init())
         .manage(AppState::default())
+        .setup(|app| {
+            let _ = app.state::<AppState>().app.set(app.handle().clone());
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             create_note,
             get_note,
@@ -233,10 +760,28 @@ fn main() {
             update_note_content,
             delete_note,
             link_notes,
+            arrange_notes,
             search_notes,
+            get_analysis_config,
+            set_analysis_config,
+            quick_switch,
+            similar_notes,
+            semantic_search,
+            find_regex,
+            preview_replace,
+            apply_replace,
+            attribute_facets,
+            filter_notes,
+            group_notes,
+            create_agent,
+            list_agents,
+            run_agent,
+            delete_agent,
             save_notebook,
             load_notebook,
             new_notebook,
+            set_passphrase,
+            change_passphrase,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");